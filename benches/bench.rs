@@ -1,5 +1,10 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 
+#[cfg(feature = "perf")]
+#[global_allocator]
+static ALLOCATOR: metrics_cloudwatch_embedded::perf::CountingAllocator =
+    metrics_cloudwatch_embedded::perf::CountingAllocator;
+
 fn criterion_benchmark(c: &mut Criterion) {
     let metrics = metrics_cloudwatch_embedded::Builder::new()
         .cloudwatch_namespace("MyApplication")
@@ -15,6 +20,26 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("flush", |b| {
         b.iter(|| metrics.set_property("RequestId", "ABC123").flush(std::io::sink()))
     });
+
+    c.bench_function("counter macro", |b| {
+        b.iter(|| metrics::counter!("requests", "Method" => "Default").increment(1))
+    });
+
+    let requests = metrics.counter("requests", &[("Method", "Default")]);
+    c.bench_function("counter handle", |b| b.iter(|| requests.increment(1)));
+
+    #[cfg(feature = "perf")]
+    {
+        let stats = metrics.bench_stats();
+        println!(
+            "{} flushes, {:.1} allocations and {:?} per flush, {:?} waiting on {} locks",
+            stats.flushes,
+            stats.flush_allocations as f64 / stats.flushes as f64,
+            stats.flush_time / stats.flushes as u32,
+            stats.lock_wait_time,
+            stats.lock_acquisitions
+        );
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,169 @@
+//! Derive macros for [metrics_cloudwatch_embedded](https://docs.rs/metrics_cloudwatch_embedded), use the re-exports
+//! from that crate via its `derive` feature flag rather than depending on this crate directly
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, LitStr};
+
+/// The Embedded Metric Format supports a maximum of 30 dimensions per metric
+const MAX_DIMENSIONS: usize = 30;
+
+/// See `metrics_cloudwatch_embedded::Dimensions`
+#[proc_macro_derive(Dimensions, attributes(dimension))]
+pub fn derive_dimensions(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match dimensions(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn dimensions(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = quote!(::metrics_cloudwatch_embedded);
+    let ident = &input.ident;
+
+    let (labels, value_impl) = match &input.data {
+        Data::Struct(data) => {
+            let fields = match &data.fields {
+                Fields::Named(fields) => &fields.named,
+                _ => {
+                    return Err(Error::new_spanned(
+                        ident,
+                        "Dimensions can only be derived for structs with named fields",
+                    ))
+                }
+            };
+            if fields.len() > MAX_DIMENSIONS {
+                return Err(Error::new_spanned(
+                    ident,
+                    format!(
+                        "{} has {} dimensions, the maximum is {MAX_DIMENSIONS}",
+                        ident,
+                        fields.len()
+                    ),
+                ));
+            }
+
+            let mut keys = Vec::new();
+            let mut labels = Vec::new();
+            for field in fields {
+                let field_ident = field.ident.as_ref().unwrap();
+                let key = match rename(&field.attrs)? {
+                    Some(key) => key,
+                    None => LitStr::new(&pascal_case(&field_ident.to_string()), Span::call_site()),
+                };
+                if keys.contains(&key.value()) {
+                    return Err(Error::new_spanned(
+                        field,
+                        format!("duplicate dimension {}", key.value()),
+                    ));
+                }
+                keys.push(key.value());
+                labels.push(quote! {
+                    #krate::__metrics::Label::new(
+                        #krate::__metrics::SharedString::const_str(#key),
+                        #krate::DimensionValue::dimension_value(&self.#field_ident),
+                    )
+                });
+            }
+            (labels, None)
+        }
+        Data::Enum(data) => {
+            let key = match rename(&input.attrs)? {
+                Some(key) => key,
+                None => LitStr::new(&ident.to_string(), Span::call_site()),
+            };
+
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                if !matches!(variant.fields, Fields::Unit) {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "Dimensions can only be derived for enums of unit variants",
+                    ));
+                }
+                let variant_ident = &variant.ident;
+                let value = match rename(&variant.attrs)? {
+                    Some(value) => value,
+                    None => LitStr::new(&variant_ident.to_string(), Span::call_site()),
+                };
+                arms.push(quote!(Self::#variant_ident => #value));
+            }
+
+            let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+            let value_impl = quote! {
+                impl #impl_generics #krate::DimensionValue for #ident #ty_generics #where_clause {
+                    fn dimension_value(&self) -> #krate::__metrics::SharedString {
+                        #krate::__metrics::SharedString::const_str(match self {
+                            #(#arms,)*
+                        })
+                    }
+                }
+            };
+            let labels = vec![quote! {
+                #krate::__metrics::Label::new(
+                    #krate::__metrics::SharedString::const_str(#key),
+                    #krate::DimensionValue::dimension_value(self),
+                )
+            }];
+            (labels, Some(value_impl))
+        }
+        Data::Union(_) => return Err(Error::new_spanned(ident, "Dimensions can not be derived for unions")),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut ref_generics = input.generics.clone();
+    ref_generics.params.insert(0, parse_quote!('__dimensions));
+    let (ref_impl_generics, _, _) = ref_generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #krate::Dimensions for #ident #ty_generics #where_clause {
+            fn labels(&self) -> ::std::vec::Vec<#krate::__metrics::Label> {
+                ::std::vec![#(#labels),*]
+            }
+        }
+
+        impl #impl_generics #krate::__metrics::IntoLabels for #ident #ty_generics #where_clause {
+            fn into_labels(self) -> ::std::vec::Vec<#krate::__metrics::Label> {
+                #krate::Dimensions::labels(&self)
+            }
+        }
+
+        impl #ref_impl_generics #krate::__metrics::IntoLabels for &'__dimensions #ident #ty_generics #where_clause {
+            fn into_labels(self) -> ::std::vec::Vec<#krate::__metrics::Label> {
+                #krate::Dimensions::labels(self)
+            }
+        }
+
+        #value_impl
+    })
+}
+
+/// Name given via `#[dimension(name = "...")]`
+fn rename(attrs: &[Attribute]) -> syn::Result<Option<LitStr>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("dimension")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported dimension attribute, expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+/// `request_method` -> `RequestMethod`, matching the casing CloudWatch dimensions conventionally use
+fn pascal_case(name: &str) -> String {
+    name.trim_start_matches("r#")
+        .split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars))
+        })
+        .flatten()
+        .collect()
+}
@@ -33,12 +33,9 @@ async fn main() -> Result<(), Error> {
         .without_time()
         .init();
 
-    let metrics = metrics_cloudwatch_embedded::Builder::new()
+    let metrics = metrics_cloudwatch_embedded::Builder::lambda_defaults()
         .cloudwatch_namespace("MetricsTest")
-        .with_dimension("function", std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap())
         .lambda_cold_start_span(info_span!("cold start"))
-        .lambda_cold_start_metric("ColdStart")
-        .with_lambda_request_id("RequestId")
         .init()
         .unwrap();
 
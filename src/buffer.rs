@@ -0,0 +1,98 @@
+//! # Buffer
+//!
+//! Lock-free bounded buffer of histogram values, recorded into concurrently and drained at flush
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Values recorded into one half while the other is drained
+struct Half {
+    /// f64 bits, only the first `reserved` (up to capacity) are written
+    slots: Box<[AtomicU64]>,
+    /// Slots claimed by recorders, keeps counting past capacity so a full half stays full
+    reserved: AtomicUsize,
+    /// Recorders between claiming a slot and writing it
+    writers: AtomicUsize,
+}
+
+impl Half {
+    fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            reserved: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.reserved.load(Ordering::Acquire).min(self.slots.len())
+    }
+}
+
+/// Double buffer of histogram values
+/// * Recording claims a slot of the active half with a single atomic increment, never blocking
+/// * Draining switches the active half and waits for recorders still writing to the previous one, which only
+///   takes as long as storing a value
+/// * Only one thread may drain at a time, the collector drains while holding its state lock
+pub(crate) struct HistogramBuffer {
+    halves: [Half; 2],
+    active: AtomicUsize,
+}
+
+impl HistogramBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            halves: [Half::new(capacity), Half::new(capacity)],
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Values the buffer holds between drains
+    pub fn capacity(&self) -> usize {
+        self.halves[0].slots.len()
+    }
+
+    /// Values recorded since the last drain
+    pub fn len(&self) -> usize {
+        self.halves[self.active.load(Ordering::SeqCst)].len()
+    }
+
+    /// Append the value, false if the buffer is full
+    pub fn push(&self, value: f64) -> bool {
+        loop {
+            let active = self.active.load(Ordering::SeqCst);
+            let half = &self.halves[active];
+            half.writers.fetch_add(1, Ordering::SeqCst);
+
+            // Drain switched halves before we registered as a writer, it may not wait for us
+            if self.active.load(Ordering::SeqCst) != active {
+                half.writers.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+
+            let index = half.reserved.fetch_add(1, Ordering::SeqCst);
+            if let Some(slot) = half.slots.get(index) {
+                slot.store(value.to_bits(), Ordering::Relaxed);
+            }
+            half.writers.fetch_sub(1, Ordering::SeqCst);
+            return index < half.slots.len();
+        }
+    }
+
+    /// Move the values recorded since the last drain into `values`, which is cleared first
+    pub fn drain(&self, values: &mut Vec<f64>) {
+        values.clear();
+
+        let previous = self.active.fetch_xor(1, Ordering::SeqCst);
+        let half = &self.halves[previous];
+        while half.writers.load(Ordering::SeqCst) != 0 {
+            std::hint::spin_loop();
+        }
+
+        values.extend(
+            half.slots[..half.len()]
+                .iter()
+                .map(|slot| f64::from_bits(slot.load(Ordering::Relaxed))),
+        );
+        half.reserved.store(0, Ordering::SeqCst);
+    }
+}
@@ -1,6 +1,11 @@
 #![allow(dead_code)]
-use super::{collector, Error};
+use super::sanitize::NameSanitizer;
+use super::sink::{AgentSink, PrettyWriter, Sink, StdoutSink};
+use super::{clock, collector, Error};
 use metrics::SharedString;
+use serde_json::value::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 /// Builder for the Embedded Cloudwatch Metrics Collector
 ///
@@ -14,12 +19,66 @@ use metrics::SharedString;
 pub struct Builder {
     cloudwatch_namespace: Option<SharedString>,
     default_dimensions: Vec<(SharedString, SharedString)>,
+    dimension_rollups: Vec<Vec<SharedString>>,
     timestamp: Option<u64>,
+    custom_metric_warning_threshold: Option<usize>,
+    cardinality_audit: bool,
+    mirror_recorder: Option<Box<dyn metrics::Recorder + Send + Sync>>,
+    debug_mirror: Option<collector::DebugMirror>,
+    sink: Arc<dyn Sink>,
+    /// Error constructing the sink of a preset, reported by init() unless another sink is set
+    sink_error: Option<std::io::Error>,
+    tenant_key: Option<collector::TenantKey>,
+    tenant_namespaces: bool,
+    max_tenants: Option<usize>,
+    global_labels: Vec<metrics::Label>,
+    key_rewrite: Option<collector::KeyRewrite>,
+    name_sanitizer: Option<NameSanitizer>,
+    on_document: Option<collector::DocumentHook>,
+    on_error: Option<collector::ErrorHandler>,
+    redacted_properties: Vec<SharedString>,
+    redaction: Option<collector::RedactionHook>,
+    flush_sequence: bool,
+    iso_time: bool,
+    lint: bool,
+    limit_warnings: bool,
+    strict_validation: bool,
+    unit_policy: collector::UnitPolicy,
+    max_metrics: usize,
+    gauge_heartbeat: Option<std::time::Duration>,
+    histogram_statistic_sets: bool,
+    histogram_capacity: usize,
+    histogram_sketches: Vec<(SharedString, collector::SketchOutput)>,
+    emit_zeros: Vec<SharedString>,
+    heartbeat: bool,
+    startup_probe: Option<(SharedString, Option<Box<dyn std::io::Write + Send>>)>,
+    sort_labels: bool,
+    namespace_environment: Option<collector::NamespaceEnvironment>,
+    namespace_routes: Vec<(SharedString, SharedString)>,
+    counter_emission: Vec<(SharedString, collector::CounterEmission)>,
+    flush_order: collector::FlushOrder,
+    clock: Box<dyn clock::Clock>,
+    properties: BTreeMap<SharedString, Value>,
+    property_dimensions: Vec<(SharedString, SharedString)>,
     #[cfg(feature = "lambda")]
     lambda_cold_start_span: Option<tracing::span::Span>,
     #[cfg(feature = "lambda")]
     lambda_cold_start: Option<&'static str>,
     #[cfg(feature = "lambda")]
+    lambda_panic: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    lambda_invocation_isolation: bool,
+    #[cfg(feature = "lambda")]
+    lambda_flush_trigger: crate::lambda::FlushTrigger,
+    #[cfg(feature = "lambda")]
+    lambda_event_fields: Vec<collector::EventField>,
+    #[cfg(feature = "lambda")]
+    lambda_account_id: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    lambda_region: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    lambda_qualifier: Option<&'static str>,
+    #[cfg(feature = "lambda")]
     lambda_request_id: Option<&'static str>,
     #[cfg(feature = "lambda")]
     lambda_xray_trace_id: Option<&'static str>,
@@ -31,18 +90,136 @@ impl Builder {
         Builder {
             cloudwatch_namespace: Default::default(),
             default_dimensions: Default::default(),
+            dimension_rollups: Vec::new(),
             timestamp: None,
+            custom_metric_warning_threshold: None,
+            cardinality_audit: false,
+            mirror_recorder: None,
+            debug_mirror: None,
+            sink: Arc::new(StdoutSink),
+            sink_error: None,
+            tenant_key: None,
+            tenant_namespaces: false,
+            max_tenants: None,
+            global_labels: Vec::new(),
+            key_rewrite: None,
+            name_sanitizer: None,
+            on_document: None,
+            on_error: None,
+            redacted_properties: Vec::new(),
+            redaction: None,
+            flush_sequence: false,
+            iso_time: false,
+            lint: false,
+            limit_warnings: false,
+            strict_validation: false,
+            unit_policy: collector::UnitPolicy::LatestWins,
+            max_metrics: collector::MAX_METRICS,
+            gauge_heartbeat: None,
+            histogram_statistic_sets: false,
+            histogram_capacity: collector::MAX_HISTOGRAM_VALUES,
+            histogram_sketches: Vec::new(),
+            emit_zeros: Vec::new(),
+            heartbeat: false,
+            startup_probe: None,
+            sort_labels: false,
+            namespace_environment: None,
+            namespace_routes: Vec::new(),
+            counter_emission: Vec::new(),
+            flush_order: collector::FlushOrder::default(),
+            clock: Box::new(clock::SystemClock),
+            properties: BTreeMap::new(),
+            property_dimensions: Vec::new(),
             #[cfg(feature = "lambda")]
             lambda_cold_start_span: None,
             #[cfg(feature = "lambda")]
             lambda_cold_start: None,
             #[cfg(feature = "lambda")]
+            lambda_panic: None,
+            #[cfg(feature = "lambda")]
+            lambda_invocation_isolation: false,
+            #[cfg(feature = "lambda")]
+            lambda_flush_trigger: crate::lambda::FlushTrigger::AfterResponse,
+            #[cfg(feature = "lambda")]
+            lambda_event_fields: Vec::new(),
+            #[cfg(feature = "lambda")]
+            lambda_account_id: None,
+            #[cfg(feature = "lambda")]
+            lambda_region: None,
+            #[cfg(feature = "lambda")]
+            lambda_qualifier: None,
+            #[cfg(feature = "lambda")]
             lambda_request_id: None,
             #[cfg(feature = "lambda")]
             lambda_xray_trace_id: None,
         }
     }
 
+    /// Preset for AWS Lambda functions flushed after each invoke by [lambda::MetricsLayer](crate::lambda::MetricsLayer)
+    /// * Documents are written to stdout via [StdoutSink], where Lambda forwards them to CloudWatch Logs
+    /// * Flushed once the response has been posted, see [Builder::lambda_flush_trigger]
+    /// * `Function` dimension from `AWS_LAMBDA_FUNCTION_NAME`, if set
+    /// * `ColdStart` and `Panic` metrics, see [Builder::lambda_cold_start_metric] and [Builder::lambda_panic_metric]
+    /// * `RequestId` and `XRayTraceId` properties
+    ///
+    /// *requires the `lambda` feature flag*
+    #[cfg(feature = "lambda")]
+    pub fn lambda_defaults() -> Self {
+        let builder = Self::new()
+            .with_sink(StdoutSink)
+            .lambda_flush_trigger(crate::lambda::FlushTrigger::AfterResponse)
+            .lambda_cold_start_metric("ColdStart")
+            .lambda_panic_metric("Panic")
+            .with_lambda_request_id("RequestId")
+            .with_lambda_xray_trace_id("XRayTraceId");
+        match std::env::var("AWS_LAMBDA_FUNCTION_NAME") {
+            Ok(function) => builder.with_dimension("Function", function),
+            Err(_) => builder,
+        }
+    }
+
+    /// Preset for long running services such as ECS tasks
+    /// * Documents are sent to the CloudWatch Agent via [AgentSink::from_env], init() fails if
+    ///   [AGENT_ENDPOINT_ENV](crate::AGENT_ENDPOINT_ENV) isn't a valid endpoint
+    /// * A `Heartbeat` metric when a flush would otherwise be empty, see [Builder::with_heartbeat]
+    /// * `Stage` dimension, see [Builder::with_stage_from_env]
+    /// * `Hostname`, `ProcessId` and `Executable` properties, see [Builder::with_host_metadata]
+    /// * `FlushSequence` and `ProcessStartTime` properties for spotting gaps, see [Builder::with_flush_sequence]
+    /// * The flush interval is left to the application, e.g. a [TickFlusher](crate::TickFlusher) or
+    ///   [Collector::spawn_flusher](collector::Collector::spawn_flusher) writing to
+    ///   [Collector::sink_writer](collector::Collector::sink_writer)
+    pub fn ecs_defaults() -> Self {
+        let builder = match AgentSink::from_env() {
+            Ok(sink) => Self::new().with_sink(sink),
+            Err(e) => Self {
+                sink_error: Some(e),
+                ..Self::new()
+            },
+        };
+        builder
+            .with_heartbeat()
+            .with_stage_from_env("STAGE")
+            .with_host_metadata()
+            .with_flush_sequence()
+    }
+
+    /// Preset for running locally, where documents are read by a person rather than CloudWatch
+    /// * Documents are indented via [PrettyWriter] and written to stdout
+    /// * `Time` property, see [Builder::with_iso_time]
+    /// * Warnings about metrics without units or descriptions, see [Builder::with_lint]
+    /// * Flushing is left to the application, e.g. [Collector::flush_to_sink](collector::Collector::flush_to_sink)
+    ///   before exiting
+    pub fn local_dev_defaults() -> Self {
+        Self::new()
+            .with_sink(|document: &[u8]| {
+                let mut stdout = std::io::stdout().lock();
+                std::io::Write::write_all(&mut PrettyWriter::new(&mut stdout), document)?;
+                std::io::Write::flush(&mut stdout)
+            })
+            .with_iso_time()
+            .with_lint()
+    }
+
     /// Sets the CloudWatch namespace for all metrics
     /// * Must be set or init() will return Err("cloudwatch_namespace missing")
     pub fn cloudwatch_namespace(self, namespace: impl Into<SharedString>) -> Self {
@@ -61,12 +238,545 @@ impl Builder {
         self
     }
 
+    /// Adds static dimensions (name, value), e.g. loaded from a config file, same as calling
+    /// [Builder::with_dimension] for each
+    /// * init() will return an error if a name is set more than once, including via [Builder::with_dimension]
+    ///
+    /// # Example
+    /// ```
+    /// let dimensions = std::collections::BTreeMap::from([("Service", "Orders"), ("Cell", "1")]);
+    ///
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_dimensions(dimensions);
+    /// ```
+    pub fn with_dimensions<K, V>(mut self, dimensions: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<SharedString>,
+        V: Into<SharedString>,
+    {
+        self.default_dimensions
+            .extend(dimensions.into_iter().map(|(name, value)| (name.into(), value.into())));
+        self
+    }
+
+    /// Also publishes metrics under a subset of their dimensions, e.g. `["Function"]` alongside
+    /// `["Function", "API"]`, via an additional dimension set in the same document rather than duplicate metrics
+    /// * This method can be called multiple times, each adds a dimension set
+    /// * A rollup is only added to documents having all of its dimensions, an empty rollup aggregates across all
+    ///   dimensions
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_dimension("Function", "orders")
+    ///     .with_dimension_rollup(["Function"]);
+    /// ```
+    pub fn with_dimension_rollup(mut self, dimensions: impl IntoIterator<Item = impl Into<SharedString>>) -> Self {
+        self.dimension_rollups
+            .push(dimensions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Adds a `Stage` dimension read from the given environment variable, falling back to `STAGE`, `ENVIRONMENT` and
+    /// `ENV`, to standardize how dev/staging/prod metrics are separated
+    /// * The dimension is omitted if none of the variables are set
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_stage_from_env("DEPLOYMENT_STAGE");
+    /// ```
+    pub fn with_stage_from_env(self, name: &str) -> Self {
+        match stage_from_env(name) {
+            Some(stage) => self.with_dimension("Stage", stage),
+            None => self,
+        }
+    }
+
+    /// Appends an environment identifier to every namespace metrics are emitted under, e.g. `MyApplication-staging`,
+    /// so staging metrics can never land in production namespaces
+    /// * Also applied to [Builder::with_tenant_namespaces] and
+    ///   [Collector::write_single_to_namespace](collector::Collector::write_single_to_namespace)
+    pub fn with_namespace_suffix(mut self, environment: impl Into<SharedString>) -> Self {
+        self.namespace_environment = Some(collector::NamespaceEnvironment::Suffix(environment.into()));
+        self
+    }
+
+    /// Prepends an environment identifier to every namespace metrics are emitted under, e.g. `staging-MyApplication`,
+    /// otherwise the same as [Builder::with_namespace_suffix]
+    pub fn with_namespace_prefix(mut self, environment: impl Into<SharedString>) -> Self {
+        self.namespace_environment = Some(collector::NamespaceEnvironment::Prefix(environment.into()));
+        self
+    }
+
+    /// [Builder::with_namespace_suffix] read from the given environment variable, falling back to `STAGE`,
+    /// `ENVIRONMENT` and `ENV` like [Builder::with_stage_from_env]
+    /// * Namespaces are left as is if none of the variables are set
+    pub fn with_namespace_suffix_from_env(self, name: &str) -> Self {
+        match stage_from_env(name) {
+            Some(stage) => self.with_namespace_suffix(stage),
+            None => self,
+        }
+    }
+
+    /// Emits metrics whose name starts with `prefix` under another namespace, e.g.
+    /// `with_namespace_route("db_", "MyApplication/Database")` for the metrics of a shared library
+    /// * Routes are checked in the order added, the first matching prefix wins
+    /// * Routed metrics share the document, dimensions and properties of the rest of their label set
+    /// * The namespace is decorated by [Builder::with_namespace_suffix] or [Builder::with_namespace_prefix] too
+    pub fn with_namespace_route(mut self, prefix: impl Into<SharedString>, namespace: impl Into<SharedString>) -> Self {
+        self.namespace_routes.push((prefix.into(), namespace.into()));
+        self
+    }
+
+    /// Sorts labels by name at registration, so call sites passing the same labels in a different order share one
+    /// series rather than emitting the same dimensions twice
+    /// * Dimensions are emitted in sorted order rather than call site order
+    pub fn with_sorted_labels(mut self) -> Self {
+        self.sort_labels = true;
+        self
+    }
+
+    /// Adds a label (name, value) to every metric at registration
+    /// * Unlike [Builder::with_dimension], global labels are part of each [metrics::Key] so they are subject to the
+    ///   same handling as labels at the call site
+    /// * Labels at the call site with the same name take precedence
+    /// * Metrics can have no more than 30 dimensions + labels
+    pub fn with_global_label(mut self, name: impl Into<SharedString>, value: impl Into<SharedString>) -> Self {
+        self.global_labels.push(metrics::Label::new(name, value));
+        self
+    }
+
+    /// Passes every [metrics::Key] through a callback at registration, which can rename metrics, add or drop labels
+    /// or reject the metric entirely by returning None
+    /// * Applied after [Builder::with_global_label]
+    /// * Rejected metrics are registered as a no-op
+    /// * Metric descriptions are passed through the callback without labels to determine the described name
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_key_rewrite(|key| {
+    ///         // Drop the high cardinality UserId label from every metric
+    ///         let labels: Vec<metrics::Label> = key.labels().filter(|label| label.key() != "UserId").cloned().collect();
+    ///         Some(metrics::Key::from_parts(key.name_shared(), labels))
+    ///     });
+    /// ```
+    pub fn with_key_rewrite(
+        mut self,
+        rewrite: impl Fn(&metrics::Key) -> Option<metrics::Key> + Send + Sync + 'static,
+    ) -> Self {
+        self.key_rewrite = Some(Box::new(rewrite));
+        self
+    }
+
+    /// Sanitizes metric names and labels at registration to what CloudWatch accepts, rather than CloudWatch Logs
+    /// silently dropping the documents, see [NameSanitizer]
+    /// * Applied after [Builder::with_key_rewrite]
+    /// * Rejected metrics are reported via [Builder::on_error] and registered as a no-op
+    /// * Dimensions set via [Builder::with_dimension] aren't sanitized
+    ///
+    /// # Example
+    /// ```
+    /// use metrics_cloudwatch_embedded::NameSanitizer;
+    ///
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_name_sanitizer(NameSanitizer::new())
+    ///     .collector()
+    ///     .unwrap();
+    ///
+    /// // Emitted as caf__latency
+    /// metrics.histogram("café latency", Vec::new()).record(1.0);
+    /// ```
+    pub fn with_name_sanitizer(mut self, sanitizer: NameSanitizer) -> Self {
+        self.name_sanitizer = Some(sanitizer);
+        self
+    }
+
+    /// Passes every document through a callback before it is serialized, for custom enrichment such as adding
+    /// computed fields or stripping sensitive properties
+    /// * Applies to flush, [Collector::flush_to_values](collector::Collector::flush_to_values) and
+    ///   [Collector::write_single](collector::Collector::write_single)
+    /// * Documents are built as a [serde_json::Map] first, which is slower than the default flush
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .on_document(|document| {
+    ///         document.remove("UserEmail");
+    ///     });
+    /// ```
+    pub fn on_document(mut self, hook: impl Fn(&mut collector::EmfDocument) + Send + Sync + 'static) -> Self {
+        self.on_document = Some(Box::new(hook));
+        self
+    }
+
+    /// Replaces the value of the named property with `"[REDACTED]"` in every document, so request context can be
+    /// attached liberally without leaking it into CloudWatch Logs
+    /// * The property is still available to [Builder::with_property_dimension] and tenant resolution
+    pub fn redact_property(mut self, name: impl Into<SharedString>) -> Self {
+        self.redacted_properties.push(name.into());
+        self
+    }
+
+    /// Passes each property's name and value through a callback at flush, e.g. to mask emails or strip tokens
+    /// * Applies to documents written by the collector, after [Builder::redact_property]
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .with_redaction(|_name, value| {
+    ///         if value.as_str().is_some_and(|value| value.contains('@')) {
+    ///             *value = "[EMAIL]".into();
+    ///         }
+    ///     });
+    /// ```
+    pub fn with_redaction(mut self, redaction: impl Fn(&str, &mut Value) + Send + Sync + 'static) -> Self {
+        self.redaction = Some(Box::new(redaction));
+        self
+    }
+
+    /// Handles errors such as conflicting metric registrations with a callback instead of reporting them via the
+    /// `tracing` crate (or the `log` crate if the `tracing` feature is disabled)
+    /// * The callback may be invoked during metric registration and must not register metrics itself
+    /// * Histogram overflow is reported while recording, which bypasses the callback
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///     .cloudwatch_namespace("MyApplication")
+    ///     .on_error(|message| eprintln!("metrics error: {message}"));
+    /// ```
+    pub fn on_error(mut self, handler: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets a property to emit with the metrics from the start, same as calling
+    /// [Collector::set_property](collector::Collector::set_property) right after init
+    /// * Properties named `_aws` or after a default dimension fail init()
+    pub fn with_property(mut self, name: impl Into<SharedString>, value: impl Into<Value>) -> Self {
+        self.properties.insert(name.into(), value.into());
+        self
+    }
+
+    /// Emits the property with the given name as a dimension instead, for values only known after init such as
+    /// [Builder::with_lambda_account_id]
+    /// * Documents are emitted without the dimension while the property is unset or not a string
+    /// * Counts towards the limit of 30 dimensions per metric
+    pub fn with_property_dimension(mut self, name: impl Into<SharedString>) -> Self {
+        let name = name.into();
+        self.property_dimensions.push((name.clone(), name));
+        self
+    }
+
+    /// Emits a dimension whose value is resolved at flush time from a property with another name, e.g. dimension
+    /// `Stage` from property `stage`, for request-derived dimensions without re-registering metrics per request
+    /// * The property is emitted as the dimension rather than as a property
+    /// * Documents are emitted without the dimension while the property is unset or not a string
+    /// * Counts towards the limit of 30 dimensions per metric
+    pub fn with_dimension_from_property(
+        mut self,
+        dimension: impl Into<SharedString>,
+        property: impl Into<SharedString>,
+    ) -> Self {
+        self.property_dimensions.push((dimension.into(), property.into()));
+        self
+    }
+
+    /// Decorates every document with properties identifying the host and process, useful when the same binary
+    /// runs on many hosts writing to a shared log group
+    /// * `Hostname` from the `HOSTNAME`/`COMPUTERNAME` environment variables or `/etc/hostname`, if available
+    /// * `ProcessId` of the current process
+    /// * `Executable` file name of the current executable, if available
+    pub fn with_host_metadata(mut self) -> Self {
+        if let Some(hostname) = hostname() {
+            self.properties.insert("Hostname".into(), hostname.into());
+        }
+        self.properties.insert("ProcessId".into(), std::process::id().into());
+        if let Some(executable) = std::env::current_exe()
+            .ok()
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+        {
+            self.properties.insert("Executable".into(), executable.into());
+        }
+        self
+    }
+
+    /// Decorates every document with properties identifying the deployed build, enabling "which deploy regressed
+    /// latency" queries in CloudWatch Logs Insights
+    /// * `ServiceVersion` set to version
+    /// * `GitSha` set to git_sha, omitted if empty
+    ///
+    /// See [with_build_info!](crate::with_build_info) to fill these in at compile time
+    pub fn with_build_info(mut self, version: impl Into<SharedString>, git_sha: impl Into<SharedString>) -> Self {
+        let version: SharedString = version.into();
+        let git_sha: SharedString = git_sha.into();
+        self.properties
+            .insert("ServiceVersion".into(), Value::String(version.into_owned()));
+        if !git_sha.is_empty() {
+            self.properties
+                .insert("GitSha".into(), Value::String(git_sha.into_owned()));
+        }
+        self
+    }
+
     /// Sets the timestamp for flush to a constant value to simplify tests
     pub fn with_timestamp(mut self, timestamp: u64) -> Self {
         self.timestamp = Some(timestamp);
         self
     }
 
+    /// Sets the source of timestamps, such as a [MockClock](crate::MockClock) for deterministic tests
+    /// * [Builder::with_timestamp] takes precedence
+    pub fn with_clock(mut self, clock: impl clock::Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Warns via the [tracing] crate when the number of distinct metric/dimension set combinations
+    /// (each billed as a custom metric) grows beyond the given threshold
+    /// * See [Collector::estimate_cost](collector::Collector::estimate_cost) for the current count
+    pub fn custom_metric_warning_threshold(mut self, threshold: usize) -> Self {
+        self.custom_metric_warning_threshold = Some(threshold);
+        self
+    }
+
+    /// Tracks every distinct (namespace, metric, dimension set) combination emitted since startup
+    /// * See [Collector::cardinality_report](collector::Collector::cardinality_report) to find which
+    ///   labels are exploding cardinality
+    /// * Retains a copy of every combination's names and values, intended for debugging
+    pub fn with_cardinality_audit(mut self) -> Self {
+        self.cardinality_audit = true;
+        self
+    }
+
+    /// Mirrors a sampled fraction of flushed documents to a secondary writer such as stderr or a file, for debugging
+    /// in production without duplicating the full log volume
+    /// * `sample_rate` is clamped to 0.0..=1.0, e.g. 0.01 mirrors every 100th document
+    /// * Failures writing to the mirror are reported via [Builder::on_error] without failing the flush
+    pub fn with_debug_mirror(mut self, writer: impl std::io::Write + Send + 'static, sample_rate: f64) -> Self {
+        self.debug_mirror = Some(collector::DebugMirror::new(Box::new(writer), sample_rate));
+        self
+    }
+
+    /// Sets the [Sink] documents are emitted to by [Collector::flush_to_sink](collector::Collector::flush_to_sink) and
+    /// the [MetricsLayer](crate::lambda::MetricsLayer), so the destination is decided once at initialization
+    /// * Defaults to [StdoutSink]
+    pub fn with_sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sink = Arc::new(sink);
+        self.sink_error = None;
+        self
+    }
+
+    /// Mirrors every recorded metric into a secondary [metrics::Recorder], in addition to emitting them as
+    /// embedded metrics
+    /// * Intended for migrating from another backend (e.g. a PutMetricData exporter), cut over by removing this call
+    /// * Metric descriptions are forwarded to the secondary recorder as well
+    pub fn with_mirror_recorder(mut self, recorder: impl metrics::Recorder + Send + Sync + 'static) -> Self {
+        self.mirror_recorder = Some(Box::new(recorder));
+        self
+    }
+
+    /// Partitions documents per tenant, identified by the value of the metric label with the given name
+    /// * Metrics without the label are emitted as usual
+    pub fn with_tenant_label(mut self, name: impl Into<SharedString>) -> Self {
+        self.tenant_key = Some(collector::TenantKey::Label(name.into()));
+        self
+    }
+
+    /// Partitions documents per tenant, identified by the string value of the property with the given name
+    /// * The property is emitted as a dimension instead, so it counts against the 30 dimension limit
+    pub fn with_tenant_property(mut self, name: impl Into<SharedString>) -> Self {
+        self.tenant_key = Some(collector::TenantKey::Property(name.into()));
+        self
+    }
+
+    /// Emits each tenant's documents under the `{cloudwatch_namespace}/{tenant}` namespace
+    /// * Requires [Builder::with_tenant_label] or [Builder::with_tenant_property]
+    pub fn with_tenant_namespaces(mut self) -> Self {
+        self.tenant_namespaces = true;
+        self
+    }
+
+    /// Limits the number of distinct tenants, metrics for any further tenants are attributed to the
+    /// [TENANT_OVERFLOW](collector::TENANT_OVERFLOW) tenant
+    /// * Requires [Builder::with_tenant_label] or [Builder::with_tenant_property]
+    pub fn max_tenants(mut self, max_tenants: usize) -> Self {
+        self.max_tenants = Some(max_tenants);
+        self
+    }
+
+    /// Decorates every document written by [Collector::flush](collector::Collector::flush) with an auto-incrementing
+    /// `FlushSequence` property and a constant `ProcessStartTime` property (milliseconds since the unix epoch)
+    /// * Useful for detecting gaps or duplicated emissions downstream when debugging lost metrics
+    /// * Documents from the same flush share a sequence number, flushes without any documents don't take one
+    pub fn with_flush_sequence(mut self) -> Self {
+        self.flush_sequence = true;
+        self
+    }
+
+    /// Decorates every document with a `Time` property holding `_aws.Timestamp` as an ISO-8601 UTC string, so raw
+    /// log lines can be read without converting epoch milliseconds by hand
+    pub fn with_iso_time(mut self) -> Self {
+        self.iso_time = true;
+        self
+    }
+
+    /// Caps the number of metrics per document, label sets with more metrics are split across several documents
+    /// * Defaults to and is capped at 100, the Embedded Metric Format maximum
+    /// * For stricter downstream parsers or older CloudWatch agent versions
+    pub fn with_max_metrics_per_document(mut self, max_metrics: usize) -> Self {
+        self.max_metrics = max_metrics.clamp(1, collector::MAX_METRICS);
+        self
+    }
+
+    /// Omits gauges whose value hasn't changed since they were last emitted, cutting log volume for slow-changing
+    /// gauges such as configuration values
+    /// * Unchanged gauges are still emitted once `heartbeat` has elapsed so the series doesn't go stale
+    pub fn with_gauge_compaction(mut self, heartbeat: std::time::Duration) -> Self {
+        self.gauge_heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Number of values each histogram buffers between flushes, 100 by default
+    /// * Recording beyond the capacity fails with an error, unless via [Builder::with_histogram_statistic_sets]
+    /// * Each histogram preallocates twice the capacity, recording into one half while the other is flushed
+    /// * Histograms with more than 100 values in a flush are emitted as distinct values and their counts, or as a
+    ///   statistic set when there are more than 100 distinct values
+    pub fn with_histogram_capacity(mut self, capacity: usize) -> Self {
+        self.histogram_capacity = capacity;
+        self
+    }
+
+    /// Aggregates histogram values recorded beyond those buffered between flushes into a statistic set
+    /// (Sum/SampleCount/Min/Max) rather than dropping them with an error
+    /// * Histograms that overflowed are emitted as a statistic set including the buffered values, losing percentiles
+    ///   for that flush but not samples
+    /// * [BoundedHistogram::try_record](collector::BoundedHistogram::try_record) no longer saturates
+    pub fn with_histogram_statistic_sets(mut self) -> Self {
+        self.histogram_statistic_sets = true;
+        self
+    }
+
+    /// Records histograms matching `pattern` into a quantile sketch rather than buffering values, absorbing any
+    /// number of samples between flushes in bounded memory
+    /// * `pattern` is either an exact metric name or a prefix followed by `*`, e.g. `"latency.*"`
+    /// * Quantiles are within 1% of the true value
+    /// * The first matching pattern wins, applying to histograms registered afterwards
+    pub fn with_histogram_sketch(mut self, pattern: impl Into<SharedString>, output: collector::SketchOutput) -> Self {
+        self.histogram_sketches.push((pattern.into(), output));
+        self
+    }
+
+    /// Emits every counter with a value of 0 when it hasn't been incremented since the last flush, rather than
+    /// omitting it
+    /// * Equivalent to `with_emit_zeros_for("*")`
+    pub fn with_emit_zeros(self) -> Self {
+        self.with_emit_zeros_for("*")
+    }
+
+    /// Emits counters matching `pattern` with a value of 0 when they haven't been incremented since the last flush,
+    /// so alarms on SLO-critical counters see a datapoint rather than missing data while other counters stay sparse
+    /// * `pattern` is either an exact metric name or a prefix followed by `*`, e.g. `"slo.*"`
+    /// * May be called multiple times, a counter is emitted if any pattern matches
+    pub fn with_emit_zeros_for(mut self, pattern: impl Into<SharedString>) -> Self {
+        self.emit_zeros.push(pattern.into());
+        self
+    }
+
+    /// Emits counters matching `pattern` as a gauge of the delta or as a rate rather than as a sparse delta, for
+    /// dashboards expecting gauge semantics from legacy tooling, without changing call sites
+    /// * `pattern` is either an exact metric name or a prefix followed by `*`, e.g. `"legacy.*"`
+    /// * The first matching pattern wins, counters matching none are emitted as
+    ///   [CounterEmission::Delta](collector::CounterEmission::Delta)
+    pub fn with_counter_emission(
+        mut self,
+        pattern: impl Into<SharedString>,
+        emission: collector::CounterEmission,
+    ) -> Self {
+        self.counter_emission.push((pattern.into(), emission));
+        self
+    }
+
+    /// Order documents are emitted in across label sets, defaults to
+    /// [FlushOrder::Labels](collector::FlushOrder::Labels)
+    /// * For downstream log processors relying on grouping, e.g. per-tenant shippers
+    pub fn with_flush_order(mut self, flush_order: collector::FlushOrder) -> Self {
+        self.flush_order = flush_order;
+        self
+    }
+
+    /// Writes a single document with a metric of the given name and a value of 1 to the [Sink] set via
+    /// [Builder::with_sink], stdout by default, as the collector is constructed, so a sink that isn't writable fails
+    /// [Builder::init] at startup rather than silently at the first flush
+    pub fn with_startup_probe(mut self, name: impl Into<SharedString>) -> Self {
+        self.startup_probe = Some((name.into(), None));
+        self
+    }
+
+    /// [Builder::with_startup_probe] writing to the given writer instead of the configured sink
+    pub fn with_startup_probe_writer(
+        mut self,
+        name: impl Into<SharedString>,
+        writer: impl std::io::Write + Send + 'static,
+    ) -> Self {
+        self.startup_probe = Some((name.into(), Some(Box::new(writer))));
+        self
+    }
+
+    /// Emits a `Heartbeat` metric with a value of 1 and the default dimensions when a flush would otherwise write
+    /// nothing, so an alarm can tell a quiet process from a dead one
+    pub fn with_heartbeat(mut self) -> Self {
+        self.heartbeat = true;
+        self
+    }
+
+    /// Which unit is kept when a metric name is described via `metrics::describe_*!` with conflicting units,
+    /// defaults to [UnitPolicy::LatestWins](collector::UnitPolicy::LatestWins)
+    /// * Conflicts are reported via [Builder::on_error] either way
+    /// * Describing without a unit never clears a unit already described
+    /// * Units apply to documents from the next flush onwards, documents already emitted are unchanged
+    pub fn with_unit_policy(mut self, unit_policy: collector::UnitPolicy) -> Self {
+        self.unit_policy = unit_policy;
+        self
+    }
+
+    /// Development aid warning at flush about metrics emitted without a unit or without ever being described via
+    /// `metrics::describe_*!`, each metric name is reported once
+    /// * Intended for catching unit-less metrics before they reach CloudWatch, not for production
+    pub fn with_lint(mut self) -> Self {
+        self.lint = true;
+        self
+    }
+
+    /// Reports documents approaching the Embedded Metric Format and CloudWatch Logs limits via [Builder::on_error]
+    /// as they're flushed, before data starts being dropped
+    /// * 90 or more metrics, 25 or more dimensions, or 90% of the 256 KB log event size
+    /// * Reported for every document near a limit
+    pub fn with_limit_warnings(mut self) -> Self {
+        self.limit_warnings = true;
+        self
+    }
+
+    /// Validates each document against the Embedded Metric Format and CloudWatch Logs limits before it's written,
+    /// see [validate_document](crate::validate_document)
+    /// * Documents exceeding a limit aren't written and are reported via [Builder::on_error], the flush continues
+    ///   and then fails with the first [ValidationError](crate::ValidationError) (as an `InvalidData`
+    ///   [std::io::Error])
+    /// * Each document is parsed after serializing, intended for tests and CI rather than production
+    pub fn with_strict_validation(mut self) -> Self {
+        self.strict_validation = true;
+        self
+    }
+
     /// Passes a tracing span to drop after our cold start is complete
     ///
     /// *requires the `lambda` feature flag*
@@ -87,6 +797,45 @@ impl Builder {
         self
     }
 
+    /// Catches a panic in the lambda handler to increment a counter with the given name and flush the metrics
+    /// accumulated by the invocation, before resuming the panic
+    /// * Applies to handlers run via the [handler](crate::lambda::handler) and [service](crate::lambda::service)
+    ///   helpers, in a custom stack the runtime turns panics into errors before they reach the
+    ///   [MetricsLayer](crate::lambda::MetricsLayer)
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn lambda_panic_metric(mut self, name: &'static str) -> Self {
+        self.lambda_panic = Some(name);
+        self
+    }
+
+    /// Keeps metrics recorded while the lambda handler is being polled separate from those recorded elsewhere, e.g.
+    /// by background tasks, so the flush at the end of an invocation only emits the invocation's own metrics
+    /// * Metrics recorded outside of the handler are left for an explicit [Collector::flush](collector::Collector::flush)
+    /// * Only applies to metrics registered via the [metrics] macros while polling, tasks spawned by the handler
+    ///   and handles registered beforehand record outside of the invocation
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_lambda_invocation_isolation(mut self) -> Self {
+        self.lambda_invocation_isolation = true;
+        self
+    }
+
+    /// When the lambda layer flushes each invocation's metrics, see [FlushTrigger](crate::lambda::FlushTrigger)
+    /// * Only applies to runtimes started via the [lambda](crate::lambda) `run` helpers
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn lambda_flush_trigger(mut self, trigger: crate::lambda::FlushTrigger) -> Self {
+        self.lambda_flush_trigger = trigger;
+        self
+    }
+
     /// Decorates every metric with request_id from the lambda request context as a property
     /// with the given name
     ///
@@ -98,6 +847,73 @@ impl Builder {
         self
     }
 
+    /// Decorates every metric with a value extracted from the raw invocation payload via a JSON pointer, as a
+    /// property with the given name
+    /// * The property is removed for invocations where the pointer doesn't match
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_event_property(mut self, name: &'static str, pointer: &'static str) -> Self {
+        self.lambda_event_fields.push(collector::EventField {
+            name,
+            pointer,
+            dimension: false,
+        });
+        self
+    }
+
+    /// Adds a dimension with a value extracted from the raw invocation payload via a JSON pointer, e.g.
+    /// `with_event_dimension("Source", "/detail-type")` for EventBridge events
+    /// * Non-string values are converted to their JSON text
+    /// * Documents are emitted without the dimension for invocations where the pointer doesn't match
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_event_dimension(mut self, name: &'static str, pointer: &'static str) -> Self {
+        self.lambda_event_fields.push(collector::EventField {
+            name,
+            pointer,
+            dimension: true,
+        });
+        self.with_property_dimension(name)
+    }
+
+    /// Decorates every metric with the account id parsed from the invoked function ARN as a property with the
+    /// given name, use [Builder::with_property_dimension] to emit it as a dimension instead
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_lambda_account_id(mut self, name: &'static str) -> Self {
+        self.lambda_account_id = Some(name);
+        self
+    }
+
+    /// Decorates every metric with the region parsed from the invoked function ARN as a property with the
+    /// given name, use [Builder::with_property_dimension] to emit it as a dimension instead
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_lambda_region(mut self, name: &'static str) -> Self {
+        self.lambda_region = Some(name);
+        self
+    }
+
+    /// Decorates every metric with the version or alias parsed from the invoked function ARN as a property with the
+    /// given name (`$LATEST` for unqualified invokes), use [Builder::with_property_dimension] to emit it as a
+    /// dimension instead
+    ///
+    /// *requires the `lambda` feature flag*
+    ///
+    #[cfg(feature = "lambda")]
+    pub fn with_lambda_qualifier(mut self, name: &'static str) -> Self {
+        self.lambda_qualifier = Some(name);
+        self
+    }
+
     /// Decorates every metric with lambda_xray_trace_id from the lambda request context as a property
     /// with the given name
     ///
@@ -109,25 +925,176 @@ impl Builder {
         self
     }
 
+    /// Private helper for validating the tenant options
+    fn tenant(
+        key: Option<collector::TenantKey>,
+        namespaces: bool,
+        max_tenants: Option<usize>,
+    ) -> Result<Option<collector::TenantConfig>, Error> {
+        match key {
+            Some(key) => Ok(Some(collector::TenantConfig {
+                key,
+                namespaces,
+                max_tenants,
+            })),
+            None if namespaces || max_tenants.is_some() => Err("tenant label or property missing".into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Private helper for rejecting dimensions set more than once
+    fn check_dimensions(default_dimensions: &[(SharedString, SharedString)]) -> Result<(), Error> {
+        for (i, (name, _)) in default_dimensions.iter().enumerate() {
+            if default_dimensions[..i].iter().any(|(other, _)| other == name) {
+                return Err(format!("dimension {name} set more than once").into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Private helper for rejecting properties that would corrupt the document structure
+    fn check_properties(
+        properties: &BTreeMap<SharedString, Value>,
+        default_dimensions: &[(SharedString, SharedString)],
+    ) -> Result<(), Error> {
+        for name in properties.keys() {
+            if let Some(reason) = collector::reserved_property(name, default_dimensions) {
+                return Err(format!("property {name} is invalid as {reason}").into());
+            }
+        }
+        Ok(())
+    }
+
     /// Private helper for consuming the builder into collector configuration (non-lambda)
     #[cfg(not(feature = "lambda"))]
     fn build(self) -> Result<collector::Config, Error> {
+        Self::check_dimensions(&self.default_dimensions)?;
+        Self::check_properties(&self.properties, &self.default_dimensions)?;
+        if let Some(e) = &self.sink_error {
+            return Err(format!("invalid sink: {e}").into());
+        }
+        let cloudwatch_namespace = self.cloudwatch_namespace.ok_or("cloudwatch_namespace missing")?;
+        let cloudwatch_namespace =
+            collector::NamespaceEnvironment::apply(&self.namespace_environment, &cloudwatch_namespace)
+                .map_or(cloudwatch_namespace, SharedString::from_owned);
+        let namespace_routes = self
+            .namespace_routes
+            .into_iter()
+            .map(|(prefix, namespace)| {
+                let decorated = collector::NamespaceEnvironment::apply(&self.namespace_environment, &namespace);
+                (prefix, decorated.map_or(namespace, SharedString::from_owned))
+            })
+            .collect();
         Ok(collector::Config {
-            cloudwatch_namespace: self.cloudwatch_namespace.ok_or("cloudwatch_namespace missing")?,
+            cloudwatch_namespace,
             default_dimensions: self.default_dimensions,
+            dimension_rollups: self.dimension_rollups,
             timestamp: self.timestamp,
+            custom_metric_warning_threshold: self.custom_metric_warning_threshold,
+            cardinality_audit: self.cardinality_audit,
+            mirror_recorder: self.mirror_recorder,
+            debug_mirror: self.debug_mirror,
+            sink: self.sink,
+            tenant: Self::tenant(self.tenant_key, self.tenant_namespaces, self.max_tenants)?,
+            global_labels: self.global_labels,
+            key_rewrite: self.key_rewrite,
+            name_sanitizer: self.name_sanitizer,
+            on_document: self.on_document,
+            on_error: self.on_error,
+            redacted_properties: self.redacted_properties,
+            redaction: self.redaction,
+            flush_sequence: self.flush_sequence,
+            iso_time: self.iso_time,
+            lint: self.lint,
+            limit_warnings: self.limit_warnings,
+            strict_validation: self.strict_validation,
+            unit_policy: self.unit_policy,
+            max_metrics: self.max_metrics,
+            gauge_heartbeat: self.gauge_heartbeat,
+            histogram_statistic_sets: self.histogram_statistic_sets,
+            histogram_capacity: self.histogram_capacity,
+            histogram_sketches: self.histogram_sketches,
+            emit_zeros: self.emit_zeros,
+            heartbeat: self.heartbeat,
+            sort_labels: self.sort_labels,
+            namespace_environment: self.namespace_environment,
+            namespace_routes,
+            counter_emission: self.counter_emission,
+            flush_order: self.flush_order,
+            clock: self.clock,
+            properties: self.properties,
+            property_dimensions: self.property_dimensions,
         })
     }
 
     /// Private helper for consuming the builder into collector configuration (lambda)
     #[cfg(feature = "lambda")]
     fn build(self) -> Result<(collector::Config, Option<tracing::span::Span>), Error> {
+        Self::check_dimensions(&self.default_dimensions)?;
+        Self::check_properties(&self.properties, &self.default_dimensions)?;
+        if let Some(e) = &self.sink_error {
+            return Err(format!("invalid sink: {e}").into());
+        }
+        let cloudwatch_namespace = self.cloudwatch_namespace.ok_or("cloudwatch_namespace missing")?;
+        let cloudwatch_namespace =
+            collector::NamespaceEnvironment::apply(&self.namespace_environment, &cloudwatch_namespace)
+                .map_or(cloudwatch_namespace, SharedString::from_owned);
+        let namespace_routes = self
+            .namespace_routes
+            .into_iter()
+            .map(|(prefix, namespace)| {
+                let decorated = collector::NamespaceEnvironment::apply(&self.namespace_environment, &namespace);
+                (prefix, decorated.map_or(namespace, SharedString::from_owned))
+            })
+            .collect();
         Ok((
             collector::Config {
-                cloudwatch_namespace: self.cloudwatch_namespace.ok_or("cloudwatch_namespace missing")?,
+                cloudwatch_namespace,
                 default_dimensions: self.default_dimensions,
+                dimension_rollups: self.dimension_rollups,
                 timestamp: self.timestamp,
+                custom_metric_warning_threshold: self.custom_metric_warning_threshold,
+                cardinality_audit: self.cardinality_audit,
+                mirror_recorder: self.mirror_recorder,
+                debug_mirror: self.debug_mirror,
+                sink: self.sink,
+                tenant: Self::tenant(self.tenant_key, self.tenant_namespaces, self.max_tenants)?,
+                global_labels: self.global_labels,
+                key_rewrite: self.key_rewrite,
+                name_sanitizer: self.name_sanitizer,
+                on_document: self.on_document,
+                on_error: self.on_error,
+                redacted_properties: self.redacted_properties,
+                redaction: self.redaction,
+                flush_sequence: self.flush_sequence,
+                iso_time: self.iso_time,
+                lint: self.lint,
+                limit_warnings: self.limit_warnings,
+                strict_validation: self.strict_validation,
+                unit_policy: self.unit_policy,
+                max_metrics: self.max_metrics,
+                gauge_heartbeat: self.gauge_heartbeat,
+                histogram_statistic_sets: self.histogram_statistic_sets,
+                histogram_capacity: self.histogram_capacity,
+                histogram_sketches: self.histogram_sketches,
+                emit_zeros: self.emit_zeros,
+                heartbeat: self.heartbeat,
+                sort_labels: self.sort_labels,
+                namespace_environment: self.namespace_environment,
+                namespace_routes,
+                counter_emission: self.counter_emission,
+                flush_order: self.flush_order,
+                clock: self.clock,
+                properties: self.properties,
+                property_dimensions: self.property_dimensions,
                 lambda_cold_start: self.lambda_cold_start,
+                lambda_panic: self.lambda_panic,
+                lambda_invocation_isolation: self.lambda_invocation_isolation,
+                lambda_flush_trigger: self.lambda_flush_trigger,
+                lambda_event_fields: self.lambda_event_fields,
+                lambda_account_id: self.lambda_account_id,
+                lambda_region: self.lambda_region,
+                lambda_qualifier: self.lambda_qualifier,
                 lambda_request_id: self.lambda_request_id,
                 lambda_xray_trace_id: self.lambda_xray_trace_id,
             },
@@ -135,21 +1102,112 @@ impl Builder {
         ))
     }
 
-    /// Intialize the metrics collector including the call to [metrics::set_global_recorder]
-    pub fn init(self) -> Result<&'static collector::Collector, Error> {
+    /// Construct the metrics collector without installing it as the global recorder
+    /// * Use with [Router](super::Router) to keep several collectors with their own namespaces, dimensions and
+    ///   flush destinations in one process, or with [metrics::with_local_recorder] via
+    ///   [Collector::recorder](collector::Collector::recorder)
+    /// * Libraries and tests can use this to avoid the process wide recorder, so tests can run in parallel without
+    ///   forking
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .collector()
+    ///      .unwrap();
+    ///
+    /// metrics::with_local_recorder(&metrics.recorder(), || {
+    ///     metrics::counter!("requests").increment(1);
+    /// });
+    ///
+    /// assert_eq!(metrics.flush_to_values()[0]["requests"], 1);
+    /// ```
+    pub fn collector(mut self) -> Result<collector::Collector, Error> {
+        let startup_probe = self.startup_probe.take();
+
         #[cfg(not(feature = "lambda"))]
         let config = self.build()?;
         #[cfg(not(feature = "lambda"))]
-        let collector: &'static collector::Collector = Box::leak(Box::new(collector::Collector::new(config)));
+        let collector = collector::Collector::new(config);
 
         // Since we need to mutate the cold start span (if present), we can't just drop it in collector::Config
         #[cfg(feature = "lambda")]
         let (config, lambda_cold_start_span) = self.build()?;
         #[cfg(feature = "lambda")]
-        let collector: &'static collector::Collector =
-            Box::leak(Box::new(collector::Collector::new(config, lambda_cold_start_span)));
+        let collector = collector::Collector::new(config, lambda_cold_start_span);
+
+        if let Some((name, writer)) = startup_probe {
+            let mut writer = writer.unwrap_or_else(|| Box::new(collector.sink_writer()));
+            collector
+                .write_single(name, Some(metrics::Unit::Count), 1, &mut writer)
+                .and_then(|_| writer.flush())
+                .map_err(|e| format!("startup probe failed: {e}"))?;
+        }
+
+        Ok(collector)
+    }
+
+    /// Intialize the metrics collector including the call to [metrics::set_global_recorder]
+    pub fn init(self) -> Result<&'static collector::Collector, Error> {
+        let collector: &'static collector::Collector = Box::leak(Box::new(self.collector()?));
 
-        metrics::set_global_recorder::<collector::Recorder>(collector.into()).map_err(|e| e.to_string())?;
+        metrics::set_global_recorder::<collector::Recorder<'static>>(collector.into()).map_err(|e| e.to_string())?;
         Ok(collector)
     }
+
+    /// Intialize the metrics collector behind a global recorder without leaking it, so it can be torn down via
+    /// [Collector::shutdown](collector::Collector::shutdown) and replaced by calling this again
+    /// * Fails if [Builder::init] was called, or another collector is installed and hasn't been shut down
+    /// * Registering a metric is slightly slower than with [Builder::init], as the global recorder looks up the
+    ///   installed collector
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init_arc()
+    ///      .unwrap();
+    ///
+    /// metrics::counter!("requests").increment(1);
+    /// let mut output = Vec::new();
+    /// metrics.shutdown(&mut output).unwrap();
+    /// assert!(String::from_utf8(output).unwrap().contains(r#""requests":1"#));
+    ///
+    /// // Discarded, nothing is installed
+    /// metrics::counter!("requests").increment(1);
+    /// assert!(metrics.flush_to_values().is_empty());
+    ///
+    /// let replacement = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init_arc()
+    ///      .unwrap();
+    /// metrics::counter!("requests").increment(2);
+    /// assert_eq!(replacement.flush_to_values()[0]["requests"], 2);
+    /// ```
+    pub fn init_arc(self) -> Result<Arc<collector::Collector>, Error> {
+        let collector = Arc::new(self.collector()?);
+        collector::SharedRecorder::install(collector.clone())?;
+        Ok(collector)
+    }
+}
+
+/// Environment variables checked by [Builder::with_stage_from_env] after the given one
+const STAGE_FALLBACK_VARS: [&str; 3] = ["STAGE", "ENVIRONMENT", "ENV"];
+
+/// First non-empty value of the given environment variable or the fallbacks
+fn stage_from_env(name: &str) -> Option<String> {
+    std::iter::once(name)
+        .chain(STAGE_FALLBACK_VARS)
+        .find_map(|name| std::env::var(name).ok().filter(|stage| !stage.is_empty()))
+}
+
+/// Best effort hostname lookup without pulling in a dependency
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
 }
@@ -0,0 +1,77 @@
+//! # Clock
+//!
+//! Source of the timestamps written to `_aws.Timestamp`, replaceable for deterministic tests
+
+use super::emf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Source of timestamps in milliseconds since the unix epoch, see [Builder::with_clock](super::Builder::with_clock)
+pub trait Clock: Send + Sync {
+    /// Current time in milliseconds since the unix epoch
+    fn now(&self) -> u64;
+}
+
+/// [Clock] reading the system time, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        emf::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+}
+
+/// [Clock] that only moves when told to, for asserting timestamps in tests
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::MockClock;
+/// use std::sync::Arc;
+///
+/// let clock = Arc::new(MockClock::new(1687657545423));
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .with_clock(clock.clone())
+///      .init()
+///      .unwrap();
+///
+/// clock.advance(std::time::Duration::from_secs(1));
+/// ```
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicU64,
+}
+
+impl MockClock {
+    /// Constructs a clock stopped at the given milliseconds since the unix epoch
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    /// Sets the time in milliseconds since the unix epoch
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    /// Moves the time forward
+    pub fn advance(&self, duration: Duration) {
+        self.now.fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
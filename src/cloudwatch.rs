@@ -0,0 +1,192 @@
+//! # CloudWatch
+//!
+//! Publishing metrics via the CloudWatch `PutMetricData` API for environments where stdout isn't routed to
+//! CloudWatch Logs
+
+use super::sink::Sink;
+use serde_json::value::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+
+/// Metric data per `PutMetricData` request
+const MAX_REQUEST_DATUMS: usize = 1000;
+
+/// A metric's value(s) within a [MetricDatum]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    /// A counter or gauge
+    Value(f64),
+    /// Histogram values, each occurring the corresponding number of times in counts or once if counts is empty
+    Values { values: Vec<f64>, counts: Vec<f64> },
+    /// A histogram aggregated via [Builder::with_histogram_statistic_sets](crate::Builder::with_histogram_statistic_sets)
+    StatisticSet {
+        sample_count: f64,
+        sum: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// One entry of a `PutMetricData` request's `MetricData`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricDatum {
+    pub name: String,
+    pub dimensions: Vec<(String, String)>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// CloudWatch unit name, e.g. `Milliseconds`
+    pub unit: Option<String>,
+    pub value: MetricValue,
+}
+
+/// Client submitting `PutMetricData` requests for a [PutMetricDataSink], e.g. by wrapping an
+/// `aws_sdk_cloudwatch::Client`, so this crate doesn't depend on a particular SDK version or HTTP stack
+/// * Implemented for closures
+pub trait PutMetricData: Send + Sync {
+    /// Submit metric data to a namespace, at most 1000 per call
+    fn put_metric_data(&self, namespace: &str, data: &[MetricDatum]) -> io::Result<()>;
+}
+
+impl<F: Fn(&str, &[MetricDatum]) -> io::Result<()> + Send + Sync> PutMetricData for F {
+    fn put_metric_data(&self, namespace: &str, data: &[MetricDatum]) -> io::Result<()> {
+        self(namespace, data)
+    }
+}
+
+/// [Sink] converting documents into `PutMetricData` requests instead of writing them to logs
+/// * One [MetricDatum] per metric and dimension set, the same metrics CloudWatch extracts from the documents
+/// * Data is submitted per namespace at the end of each flush
+/// * Properties aren't sent, `PutMetricData` has no equivalent
+/// * Retries are left to the client, the AWS SDK retries by default
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::{MetricDatum, PutMetricDataSink};
+///
+/// let sink = PutMetricDataSink::new(|namespace: &str, data: &[MetricDatum]| {
+///     // Submit via aws_sdk_cloudwatch::Client::put_metric_data
+///     println!("submitting {} metrics to {namespace}", data.len());
+///     Ok(())
+/// });
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyService")
+///      .with_sink(sink)
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush_to_sink().unwrap();
+/// ```
+pub struct PutMetricDataSink<C> {
+    client: C,
+    pending: Mutex<BTreeMap<String, Vec<MetricDatum>>>,
+}
+
+impl<C: PutMetricData> PutMetricDataSink<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            pending: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<C: PutMetricData> Sink for PutMetricDataSink<C> {
+    fn emit(&self, document: &[u8]) -> io::Result<()> {
+        let document: Value = serde_json::from_slice(document)?;
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        for (namespace, datum) in metric_data(&document) {
+            pending.entry(namespace).or_default().push(datum);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner));
+
+        // Submit every namespace even if one fails, returning the first error
+        let mut result = Ok(());
+        for (namespace, data) in pending {
+            for chunk in data.chunks(MAX_REQUEST_DATUMS) {
+                if let Err(e) = self.client.put_metric_data(&namespace, chunk) {
+                    result = result.and(Err(e));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// The metric data CloudWatch would extract from a document, with their namespaces
+fn metric_data(document: &Value) -> Vec<(String, MetricDatum)> {
+    let timestamp = document["_aws"]["Timestamp"].as_u64().unwrap_or_default();
+    let directives = document["_aws"]["CloudWatchMetrics"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+
+    let mut data = Vec::new();
+    for directive in directives {
+        let namespace = directive["Namespace"].as_str().unwrap_or_default();
+        let dimension_sets = directive["Dimensions"].as_array().map_or(&[][..], Vec::as_slice);
+        let metrics = directive["Metrics"].as_array().map_or(&[][..], Vec::as_slice);
+
+        for metric in metrics {
+            let Some(name) = metric["Name"].as_str() else {
+                continue;
+            };
+            let Some(value) = metric_value(&document[name]) else {
+                continue;
+            };
+
+            for dimension_set in dimension_sets {
+                let dimensions = dimension_set
+                    .as_array()
+                    .map_or(&[][..], Vec::as_slice)
+                    .iter()
+                    .filter_map(|dimension| {
+                        let dimension = dimension.as_str()?;
+                        Some((dimension.to_owned(), document[dimension].as_str()?.to_owned()))
+                    })
+                    .collect();
+
+                data.push((
+                    namespace.to_owned(),
+                    MetricDatum {
+                        name: name.to_owned(),
+                        dimensions,
+                        timestamp,
+                        unit: metric["Unit"].as_str().map(str::to_owned),
+                        value: value.clone(),
+                    },
+                ));
+            }
+        }
+    }
+    data
+}
+
+/// A metric's value as emitted in a document, a number, an array of numbers, a statistic set or a distribution
+fn metric_value(value: &Value) -> Option<MetricValue> {
+    let numbers = |value: &Value| -> Option<Vec<f64>> { value.as_array()?.iter().map(Value::as_f64).collect() };
+
+    match value {
+        Value::Number(number) => number.as_f64().map(MetricValue::Value),
+        Value::Array(_) => Some(MetricValue::Values {
+            values: numbers(value)?,
+            counts: Vec::new(),
+        }),
+        Value::Object(object) if object.contains_key("Values") => Some(MetricValue::Values {
+            values: numbers(&object["Values"])?,
+            counts: numbers(object.get("Counts")?)?,
+        }),
+        Value::Object(object) => Some(MetricValue::StatisticSet {
+            sample_count: object.get("SampleCount")?.as_f64()?,
+            sum: object.get("Sum")?.as_f64()?,
+            min: object.get("Min")?.as_f64()?,
+            max: object.get("Max")?.as_f64()?,
+        }),
+        _ => None,
+    }
+}
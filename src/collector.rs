@@ -3,48 +3,459 @@
 //! Metrics Collector + Emitter returned from metrics_cloudwatch_embedded::Builder
 
 #![allow(dead_code)]
-use super::emf;
+use super::buffer::HistogramBuffer;
+use super::registry::Registry;
+use super::report::{report_error, report_warn};
+use super::sketch::Sketch;
+use super::timer::Timer;
+use super::{clock, emf, logger};
 use metrics::SharedString;
+use serde::Serialize;
 use serde_json::value::Value;
-use std::collections::{BTreeMap, HashMap};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::error;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, TryLockError};
+use std::time::{Duration, Instant};
 
 /// The Embedded Metric Format supports a maximum of 100 values per key
-const MAX_HISTOGRAM_VALUES: usize = 100;
+pub(crate) const MAX_HISTOGRAM_VALUES: usize = 100;
+
+/// The Embedded Metric Format supports a maximum of 100 metrics per document
+pub(crate) const MAX_METRICS: usize = 100;
+
+/// Monthly price in USD of a single CloudWatch custom metric (first 10,000 metrics tier)
+///
+/// <https://aws.amazon.com/cloudwatch/pricing/>
+pub const CUSTOM_METRIC_MONTHLY_COST: f64 = 0.30;
 
 /// The Embedded Metric Format supports a maximum of 30 dimensions per metric
-const MAX_DIMENSIONS: usize = 30;
+pub(crate) const MAX_DIMENSIONS: usize = 30;
+
+/// CloudWatch Logs supports a maximum of 256 KB per log event
+pub(crate) const MAX_DOCUMENT_BYTES: usize = 256 * 1024;
+
+/// Capacity of the flush buffer kept between flushes
+const MAX_RETAINED_BUFFER: usize = 1024 * 1024;
+
+/// Metadata for metrics registered via [Collector::counter], [Collector::gauge] and [Collector::histogram]
+static HANDLE_METADATA: metrics::Metadata<'static> =
+    metrics::Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
+
+/// Property name of the flush sequence number via [Builder::with_flush_sequence](super::Builder::with_flush_sequence)
+pub const FLUSH_SEQUENCE: &str = "FlushSequence";
+
+/// Property name of the process start time via [Builder::with_flush_sequence](super::Builder::with_flush_sequence)
+pub const PROCESS_START_TIME: &str = "ProcessStartTime";
+
+/// Property name of the human-readable timestamp via [Builder::with_iso_time](super::Builder::with_iso_time)
+pub const ISO_TIME: &str = "Time";
+
+/// Metric name of the heartbeat via [Builder::with_heartbeat](super::Builder::with_heartbeat)
+pub const HEARTBEAT: &str = "Heartbeat";
+
+/// Value of properties redacted via [Builder::redact_property](super::Builder::redact_property)
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Tenant that metrics are attributed to once [Builder::max_tenants](super::Builder::max_tenants) distinct tenants have been seen
+pub const TENANT_OVERFLOW: &str = "Other";
 
 /// Configuration via Builder
 pub struct Config {
     pub cloudwatch_namespace: SharedString,
     pub default_dimensions: Vec<(SharedString, SharedString)>,
+    pub dimension_rollups: Vec<Vec<SharedString>>,
     pub timestamp: Option<u64>,
+    pub custom_metric_warning_threshold: Option<usize>,
+    pub cardinality_audit: bool,
+    pub mirror_recorder: Option<Box<dyn metrics::Recorder + Send + Sync>>,
+    pub debug_mirror: Option<DebugMirror>,
+    pub sink: Arc<dyn crate::Sink>,
+    pub tenant: Option<TenantConfig>,
+    pub global_labels: Vec<metrics::Label>,
+    pub key_rewrite: Option<KeyRewrite>,
+    pub name_sanitizer: Option<crate::NameSanitizer>,
+    pub on_document: Option<DocumentHook>,
+    pub on_error: Option<ErrorHandler>,
+    pub redacted_properties: Vec<SharedString>,
+    pub redaction: Option<RedactionHook>,
+    pub flush_sequence: bool,
+    pub iso_time: bool,
+    pub lint: bool,
+    pub limit_warnings: bool,
+    pub strict_validation: bool,
+    pub unit_policy: UnitPolicy,
+    pub max_metrics: usize,
+    pub gauge_heartbeat: Option<Duration>,
+    pub histogram_statistic_sets: bool,
+    pub histogram_capacity: usize,
+    pub histogram_sketches: Vec<(SharedString, SketchOutput)>,
+    pub emit_zeros: Vec<SharedString>,
+    pub heartbeat: bool,
+    pub sort_labels: bool,
+    pub namespace_environment: Option<NamespaceEnvironment>,
+    pub namespace_routes: Vec<(SharedString, SharedString)>,
+    pub counter_emission: Vec<(SharedString, CounterEmission)>,
+    pub flush_order: FlushOrder,
+    pub clock: Box<dyn clock::Clock>,
+    pub properties: BTreeMap<SharedString, Value>,
+    pub property_dimensions: Vec<(SharedString, SharedString)>,
     #[cfg(feature = "lambda")]
     pub lambda_cold_start: Option<&'static str>,
     #[cfg(feature = "lambda")]
+    pub lambda_panic: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    pub lambda_invocation_isolation: bool,
+    #[cfg(feature = "lambda")]
+    pub lambda_flush_trigger: crate::lambda::FlushTrigger,
+    #[cfg(feature = "lambda")]
+    pub lambda_event_fields: Vec<EventField>,
+    #[cfg(feature = "lambda")]
+    pub lambda_account_id: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    pub lambda_region: Option<&'static str>,
+    #[cfg(feature = "lambda")]
+    pub lambda_qualifier: Option<&'static str>,
+    #[cfg(feature = "lambda")]
     pub lambda_request_id: Option<&'static str>,
     #[cfg(feature = "lambda")]
     pub lambda_xray_trace_id: Option<&'static str>,
 }
 
-/// Histogram Handler implemented as mpsc::SyncSender<f64>
+/// Callback via [Builder::with_key_rewrite](super::Builder::with_key_rewrite)
+pub type KeyRewrite = Box<dyn Fn(&metrics::Key) -> Option<metrics::Key> + Send + Sync>;
+
+/// An embedded metrics document as passed to [Builder::on_document](super::Builder::on_document)
+pub type EmfDocument = serde_json::Map<String, Value>;
+
+/// Callback via [Builder::on_document](super::Builder::on_document)
+pub type DocumentHook = Box<dyn Fn(&mut EmfDocument) + Send + Sync>;
+
+/// Callback via [Builder::with_redaction](super::Builder::with_redaction)
+pub type RedactionHook = Box<dyn Fn(&str, &mut Value) + Send + Sync>;
+
+/// Callback via [Builder::on_error](super::Builder::on_error)
+pub type ErrorHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Callback via [Collector::set_property_fn]
+pub type PropertyFn = Arc<dyn Fn() -> Value + Send + Sync>;
+
+/// Which unit is kept when a metric name is described with conflicting units, via
+/// [Builder::with_unit_policy](super::Builder::with_unit_policy)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnitPolicy {
+    /// The most recent describe_xxx wins, the default
+    #[default]
+    LatestWins,
+    /// The first describe_xxx with a unit wins
+    FirstWins,
+}
+
+/// How a counter is emitted, via [Builder::with_counter_emission](super::Builder::with_counter_emission)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CounterEmission {
+    /// The increase since the last flush, omitted when there was none, the default
+    #[default]
+    Delta,
+    /// The increase since the last flush, emitted every flush including when it's 0
+    Gauge,
+    /// The increase per second since the last flush, emitted every flush with a unit of Count/Second
+    Rate,
+}
+
+/// What a histogram recorded into a sketch emits at flush, via
+/// [Builder::with_histogram_sketch](super::Builder::with_histogram_sketch)
+#[derive(Debug, Clone, PartialEq)]
+pub enum SketchOutput {
+    /// A gauge per percentile (0-100) named after the histogram with a `.p` suffix, e.g. `latency.p99` for 99.0
+    Percentiles(Vec<f64>),
+    /// Values with Counts, downsampled to at most 100 buckets, from which CloudWatch computes any percentile
+    Distribution,
+}
+
+/// Order documents are emitted in across label sets, via [Builder::with_flush_order](super::Builder::with_flush_order)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FlushOrder {
+    /// Sorted by label names and values, the default
+    #[default]
+    Labels,
+    /// The order each label set was first registered in
+    Registration,
+    /// Sorted by the value of the given label, label sets without it are emitted last
+    Label(SharedString),
+}
+
+/// Secondary writer flushed documents are sampled to, via [Builder::with_debug_mirror](super::Builder::with_debug_mirror)
+pub struct DebugMirror {
+    sample_rate: f64,
+    /// Writer along with the fraction of a document accumulated towards the next sample
+    state: Mutex<(Box<dyn std::io::Write + Send>, f64)>,
+}
+
+impl DebugMirror {
+    pub(crate) fn new(writer: Box<dyn std::io::Write + Send>, sample_rate: f64) -> Self {
+        Self {
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+            state: Mutex::new((writer, 0.0)),
+        }
+    }
+
+    /// Writer to mirror the next document to, if it's sampled
+    /// * Sampling is deterministic, exactly every 1/sample_rate-th document is mirrored
+    fn sample(&self) -> Option<std::sync::MutexGuard<'_, (Box<dyn std::io::Write + Send>, f64)>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.1 += self.sample_rate;
+        if state.1 < 1.0 {
+            return None;
+        }
+        state.1 -= 1.0;
+        Some(state)
+    }
+}
+
+/// Environment identifier added to every namespace, via [Builder::with_namespace_suffix](super::Builder::with_namespace_suffix)
+/// or [Builder::with_namespace_prefix](super::Builder::with_namespace_prefix)
+pub enum NamespaceEnvironment {
+    Prefix(SharedString),
+    Suffix(SharedString),
+}
+
+impl NamespaceEnvironment {
+    /// The namespace with the environment added, None if there's no environment to add
+    pub(crate) fn apply(environment: &Option<Self>, namespace: &str) -> Option<String> {
+        match environment {
+            Some(Self::Prefix(environment)) => Some(format!("{environment}-{namespace}")),
+            Some(Self::Suffix(environment)) => Some(format!("{namespace}-{environment}")),
+            None => None,
+        }
+    }
+}
+
+/// Identifies the tenant of a document
+pub enum TenantKey {
+    /// Tenant is the value of a metric label, via [Builder::with_tenant_label]
+    Label(SharedString),
+    /// Tenant is the value of a property emitted as a dimension instead, via [Builder::with_tenant_property]
+    Property(SharedString),
+}
+
+/// Field extracted from the raw invocation payload via [Builder::with_event_property](super::Builder::with_event_property)
+/// or [Builder::with_event_dimension](super::Builder::with_event_dimension)
+#[cfg(feature = "lambda")]
+pub struct EventField {
+    /// Property name to set
+    pub name: &'static str,
+    /// JSON pointer (RFC 6901) into the payload
+    pub pointer: &'static str,
+    /// Whether the property is emitted as a dimension, which requires a string value
+    pub dimension: bool,
+}
+
+/// Per-tenant document partitioning configuration via Builder
+pub struct TenantConfig {
+    pub key: TenantKey,
+    pub namespaces: bool,
+    pub max_tenants: Option<usize>,
+}
+
+/// Histogram Handler recording into a preallocated lock-free buffer, drained at flush
 struct HistogramHandle {
-    sender: mpsc::SyncSender<f64>,
+    values: HistogramBuffer,
+    /// Whether values beyond the buffer are aggregated into `overflow`, via [Builder::with_histogram_statistic_sets]
+    statistic_sets: bool,
+    overflow: Mutex<Option<StatisticSet>>,
+    /// Records into a sketch instead of the buffer, via [Builder::with_histogram_sketch]
+    sketch: Option<Mutex<Sketch>>,
+}
+
+impl HistogramHandle {
+    /// Appends the value unless the buffer is full
+    fn try_push(&self, value: f64) -> bool {
+        if let Some(sketch) = &self.sketch {
+            sketch.lock().unwrap_or_else(PoisonError::into_inner).add(value);
+            return true;
+        }
+
+        if self.values.push(value) {
+            true
+        } else if self.statistic_sets {
+            let mut overflow = self.overflow.lock().unwrap_or_else(PoisonError::into_inner);
+            match &mut *overflow {
+                Some(set) => set.record(value),
+                None => *overflow = Some(StatisticSet::new(value)),
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take the values aggregated since the buffer filled up, if any
+    fn take_overflow(&self) -> Option<StatisticSet> {
+        self.overflow.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    /// Number of values recorded since the last flush
+    fn pending(&self) -> u64 {
+        if let Some(sketch) = &self.sketch {
+            return sketch.lock().unwrap_or_else(PoisonError::into_inner).count();
+        }
+        let buffered = self.values.len() as u64;
+        let overflow = self.overflow.lock().unwrap_or_else(PoisonError::into_inner);
+        buffered + overflow.map_or(0, |set| set.count)
+    }
+}
+
+/// Aggregate of histogram values in the Embedded Metric Format's statistic set form
+#[derive(Debug, Clone, Copy)]
+struct StatisticSet {
+    sum: f64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl StatisticSet {
+    fn new(value: f64) -> Self {
+        Self {
+            sum: value,
+            count: 1,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn record(&mut self, value: f64) {
+        self.sum += value;
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Whether every value aggregated was finite
+    fn is_finite(&self) -> bool {
+        self.sum.is_finite() && self.min.is_finite() && self.max.is_finite()
+    }
+
+    fn to_value(self) -> Value {
+        serde_json::json!({
+            "Max": gauge_value(self.max),
+            "Min": gauge_value(self.min),
+            "SampleCount": self.count,
+            "Sum": gauge_value(self.sum),
+        })
+    }
 }
 
 impl metrics::HistogramFn for HistogramHandle {
-    // Sends the metric value to our sync_channel
     fn record(&self, value: f64) {
-        if self.sender.send(value).is_err() {
-            error!("Failed to record histogram value, more than 100 unflushed values?");
+        if !self.try_push(value) {
+            report_error!(
+                "Failed to record histogram value, more than {} unflushed values?",
+                self.values.capacity()
+            );
         }
     }
 }
 
+/// Histogram handle returned from [Collector::bounded_histogram], reporting when the values buffered until the
+/// next flush are at capacity instead of dropping them with an error
+/// * Lets high-throughput producers degrade gracefully, e.g. by sampling harder or flushing early
+pub struct BoundedHistogram {
+    /// None when registration failed or the key was rejected, recording is then a no-op
+    handle: Option<Arc<HistogramHandle>>,
+}
+
+/// Error returned from [BoundedHistogram::try_record] when the histogram can't buffer more values until the next flush
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturated;
+
+impl std::fmt::Display for Saturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "histogram buffer is full until the next flush")
+    }
+}
+
+impl std::error::Error for Saturated {}
+
+impl BoundedHistogram {
+    /// Record a value, or return [Saturated] leaving it unrecorded
+    pub fn try_record(&self, value: f64) -> Result<(), Saturated> {
+        match &self.handle {
+            Some(handle) if !handle.try_push(value) => Err(Saturated),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of values that can be recorded before the next flush
+    pub fn remaining(&self) -> usize {
+        match &self.handle {
+            Some(handle) => handle.values.capacity() - handle.values.len(),
+            None => MAX_HISTOGRAM_VALUES,
+        }
+    }
+}
+
+/// Counter handle forwarding to both our counter and the one from [Builder::with_mirror_recorder]
+struct MirroredCounter(metrics::Counter, metrics::Counter);
+
+impl metrics::CounterFn for MirroredCounter {
+    fn increment(&self, value: u64) {
+        self.0.increment(value);
+        self.1.increment(value);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.0.absolute(value);
+        self.1.absolute(value);
+    }
+}
+
+/// Gauge handle forwarding to both our gauge and the one from [Builder::with_mirror_recorder]
+struct MirroredGauge(metrics::Gauge, metrics::Gauge);
+
+impl metrics::GaugeFn for MirroredGauge {
+    fn increment(&self, value: f64) {
+        self.0.increment(value);
+        self.1.increment(value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.0.decrement(value);
+        self.1.decrement(value);
+    }
+
+    fn set(&self, value: f64) {
+        self.0.set(value);
+        self.1.set(value);
+    }
+}
+
+/// Histogram handle forwarding to both our histogram and the one from [Builder::with_mirror_recorder]
+struct MirroredHistogram(metrics::Histogram, metrics::Histogram);
+
+impl metrics::HistogramFn for MirroredHistogram {
+    fn record(&self, value: f64) {
+        self.0.record(value);
+        self.1.record(value);
+    }
+}
+
+/// Handle of a registered metric, shared with its [MetricInfo]
+#[derive(Clone)]
+enum Registered {
+    Counter(Arc<AtomicU64>),
+    Gauge(Arc<AtomicU64>),
+    Histogram(Arc<HistogramHandle>),
+}
+
+/// Metric registered since the collector's state was last locked
+struct Registration {
+    labels: Vec<metrics::Label>,
+    key: metrics::Key,
+    info: MetricInfo,
+}
+
 // Metric information stored in an index
 enum MetricInfo {
     Counter(CounterInfo),
@@ -57,27 +468,304 @@ struct CounterInfo {
 }
 
 struct GaugeInfo {
+    /// f64 bits, [metrics::GaugeFn] for [AtomicU64] increments and decrements via a compare-and-swap loop so
+    /// concurrent updates aren't lost
     value: Arc<AtomicU64>,
+    /// Value bits and timestamp of the last emission, for [Builder::with_gauge_compaction](super::Builder::with_gauge_compaction)
+    last_emitted: Option<(u64, u64)>,
 }
 
 struct HistogramInfo {
-    sender: mpsc::SyncSender<f64>,
-    receiver: mpsc::Receiver<f64>,
+    handle: Arc<HistogramHandle>,
+    /// Buffer the handle's values are drained into at flush, reused between flushes
+    spare: Vec<f64>,
+    /// Quantiles (0-1) and metric names emitted from the sketch, for [SketchOutput::Percentiles]
+    percentiles: Vec<(f64, String)>,
+}
+
+impl HistogramInfo {
+    fn new(config: &Config, name: &str) -> Self {
+        let sketch = config
+            .histogram_sketches
+            .iter()
+            .find(|(pattern, _)| name_matches(pattern, name))
+            .map(|(_, output)| output);
+        let percentiles = match sketch {
+            Some(SketchOutput::Percentiles(percentiles)) => percentiles
+                .iter()
+                .map(|percentile| (percentile / 100.0, format!("{name}.p{percentile}")))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        // Sketched histograms never use the buffer
+        let capacity = if sketch.is_some() { 0 } else { config.histogram_capacity };
+        Self {
+            handle: Arc::new(HistogramHandle {
+                values: HistogramBuffer::new(capacity),
+                statistic_sets: config.histogram_statistic_sets,
+                overflow: Mutex::new(None),
+                sketch: sketch.map(|_| Mutex::new(Sketch::new())),
+            }),
+            spare: Vec::with_capacity(capacity),
+            percentiles,
+        }
+    }
+
+    /// Drain the values recorded since the last call into the spare buffer
+    fn swap(&mut self) -> &[f64] {
+        self.handle.values.drain(&mut self.spare);
+        &self.spare
+    }
+}
+
+/// Metrics sharing a label set, along with the label set's dimensions serialized at registration
+struct LabelSetInfo {
+    metrics: BTreeMap<metrics::Key, MetricInfo>,
+    dimensions: Arc<emf::DimensionFragment>,
+    /// Sequence number for [FlushOrder::Registration]
+    registered: u64,
+}
+
+/// Everything a flush emits, copied under the state lock so documents are built and written once it's released
+struct FlushSnapshot<'a> {
+    properties: BTreeMap<SharedString, Value>,
+    /// Sequence number of this flush, only used via [Builder::with_flush_sequence](super::Builder::with_flush_sequence)
+    flush_sequence: u64,
+    /// Label sets with values to emit, in flush order
+    label_sets: Vec<LabelSetSnapshot<'a>>,
+}
+
+struct LabelSetSnapshot<'a> {
+    labels: Vec<metrics::Label>,
+    namespace: String,
+    dimensions: Arc<emf::DimensionFragment>,
+    metrics: Vec<MetricSnapshot<'a>>,
+}
+
+struct MetricSnapshot<'a> {
+    name: metrics::KeyName,
+    unit: Option<&'static str>,
+    /// Namespace via [Builder::with_namespace_route](super::Builder::with_namespace_route), if routed
+    route: Option<&'a str>,
+    value: FlushValue,
 }
 
+/// Value of a metric taken at flush, converted to JSON once the state lock is released
+enum FlushValue {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Vec<f64>),
+    /// Distinct values and their counts from a sketch
+    Distribution(Vec<f64>, Vec<u64>),
+    Statistics(StatisticSet),
+}
+
+impl FlushValue {
+    fn to_value(&self) -> Value {
+        match self {
+            Self::Counter(value) => (*value).into(),
+            Self::Gauge(value) => gauge_value(*value),
+            Self::Histogram(values) => histogram_value(values),
+            Self::Distribution(values, counts) => serde_json::json!({"Values": values, "Counts": counts}),
+            Self::Statistics(set) => set.to_value(),
+        }
+    }
+}
+
+/// Extra dimensions, a timestamp and properties for a metric written via [Collector::write_single_with]
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::WriteOptions;
+///
+/// let options = WriteOptions::new()
+///     .dimension("Tenant", "tenant-1")
+///     .timestamp(1_700_000_000_000)
+///     .property("RequestId", "ABC123");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    dimensions: Vec<(SharedString, SharedString)>,
+    timestamp: Option<u64>,
+    properties: Vec<(SharedString, Value)>,
+}
+
+impl WriteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dimension alongside the default dimensions, which it may not overlap
+    pub fn dimension(mut self, name: impl Into<SharedString>, value: impl Into<SharedString>) -> Self {
+        self.dimensions.push((name.into(), value.into()));
+        self
+    }
+
+    /// Milliseconds since the Unix epoch to emit at instead of the current time, e.g. when the event happened
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Add a property to this document only, taking precedence over a property set on the collector with the same
+    /// name
+    pub fn property(mut self, name: impl Into<SharedString>, value: impl Into<Value>) -> Self {
+        self.properties.push((name.into(), value.into()));
+        self
+    }
+
+    /// Why the options can't be used with the default dimensions, if they'd corrupt the document
+    fn check(&self, default_dimensions: &[(SharedString, SharedString)]) -> Option<String> {
+        if default_dimensions.len() + self.dimensions.len() > MAX_DIMENSIONS {
+            return Some(format!("more than {MAX_DIMENSIONS} dimensions"));
+        }
+        for (i, (name, _)) in self.dimensions.iter().enumerate() {
+            if let Some(reason) = reserved_property(name, default_dimensions) {
+                return Some(format!("dimension {name} is invalid as {reason}"));
+            }
+            if self.dimensions[..i].iter().any(|(other, _)| other == name) {
+                return Some(format!("dimension {name} is given more than once"));
+            }
+        }
+        for (name, _) in &self.properties {
+            if let Some(reason) = reserved_property(name, default_dimensions) {
+                return Some(format!("property {name} is invalid as {reason}"));
+            }
+            if self.dimensions.iter().any(|(dimension, _)| dimension == name) {
+                return Some(format!("property {name} is invalid as it is already a dimension"));
+            }
+        }
+        None
+    }
+}
+
+/// Estimate of CloudWatch custom metric usage returned from [Collector::estimate_cost]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostEstimate {
+    /// Number of distinct metric name + dimension set combinations registered, each is billed as a custom metric
+    pub custom_metrics: usize,
+    /// Projected monthly cost in USD at [CUSTOM_METRIC_MONTHLY_COST] per custom metric
+    pub monthly_cost: f64,
+}
+
+/// Value of a metric in a [SnapshotEntry]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SnapshotValue {
+    /// Count since the last flush
+    Counter(u64),
+    /// Last value set
+    Gauge(f64),
+    /// Number of values recorded since the last flush
+    Histogram(u64),
+}
+
+/// Entry in the snapshot returned from [Collector::snapshot]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotEntry {
+    pub name: String,
+    /// Label names and values, excluding default dimensions
+    pub labels: Vec<(String, String)>,
+    pub value: SnapshotValue,
+}
+
+/// Entry in the report returned from [Collector::cardinality_report]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CardinalityEntry {
+    pub namespace: String,
+    pub metric: String,
+    /// Dimension names and values in document order, including default dimensions
+    pub dimensions: Vec<(String, String)>,
+    /// Number of documents this combination has been emitted in since startup
+    pub documents: u64,
+}
+
+/// Report of a flush returned from [Collector::flush_with_report]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FlushReport {
+    /// Number of documents written
+    pub documents: usize,
+    /// Number of bytes written
+    pub bytes: usize,
+    /// Every label set visited, including those with nothing to emit
+    pub label_sets: Vec<LabelSetReport>,
+}
+
+/// Entry in [FlushReport::label_sets]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LabelSetReport {
+    /// Label names and values, excluding default dimensions
+    pub labels: Vec<(String, String)>,
+    /// Names of the metrics emitted
+    pub emitted: Vec<String>,
+    /// Names of the metrics skipped for having no new values since the last flush
+    pub skipped: Vec<String>,
+}
+
+/// Key of the cardinality audit: (namespace, metric, dimensions)
+type CardinalityKey = (String, String, Vec<(String, String)>);
+
 /// Collector state used to register new metrics and flush
 /// This lives within a mutex
 struct CollectorState {
     /// Tree of labels to name to metric details
-    info_tree: BTreeMap<Vec<metrics::Label>, BTreeMap<metrics::Key, MetricInfo>>,
-    /// Store units seperate because describe_xxx isn't scoped to labels
-    /// Key is a copied String until at least metrics cl #381 is released in metrics
-    units: HashMap<metrics::KeyName, metrics::Unit>,
+    info_tree: BTreeMap<Vec<metrics::Label>, LabelSetInfo>,
+    /// Tree of metrics recorded within lambda invocations, only used via
+    /// [Builder::with_lambda_invocation_isolation](super::Builder::with_lambda_invocation_isolation)
+    invocation_tree: BTreeMap<Vec<metrics::Label>, LabelSetInfo>,
     /// Properties to be written with metrics
     properties: BTreeMap<SharedString, Value>,
+    /// Properties removed after the next flush via [Collector::set_flush_property]
+    flush_properties: HashSet<SharedString>,
+    /// Number of distinct metric name + label set combinations registered
+    custom_metrics: usize,
+    /// Namespace of each tenant with metrics or properties in the state, only tracked via
+    /// [Builder::with_tenant_namespaces]
+    tenants: BTreeMap<SharedString, SharedString>,
+    /// Number of label sets registered so far, for [FlushOrder::Registration]
+    label_sets_registered: u64,
+    /// Timestamp of the last flush, for [CounterEmission::Rate]
+    last_flush: u64,
+    /// Number of flushes that emitted documents so far, only tracked via [Builder::with_flush_sequence]
+    flush_sequence: u64,
+    /// Number of non-finite gauge and histogram values dropped at flush
+    dropped_non_finite: u64,
     /// Cold start span to drop after first invoke
     #[cfg(feature = "lambda")]
     lambda_cold_start_span: Option<tracing::span::Span>,
+    /// Set once the first invoke has been seen
+    #[cfg(feature = "lambda")]
+    lambda_cold_start_done: bool,
+    /// Set via [Collector::defer_flush] to skip the flush at the end of the current invoke
+    #[cfg(feature = "lambda")]
+    lambda_flush_deferred: bool,
+}
+
+impl CollectorState {
+    fn tree(&mut self, scope: Scope) -> &mut BTreeMap<Vec<metrics::Label>, LabelSetInfo> {
+        match scope {
+            Scope::Global => &mut self.info_tree,
+            Scope::Invocation => &mut self.invocation_tree,
+        }
+    }
+}
+
+/// Which tree of metrics a [Recorder] registers into and a flush emits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// Every metric, unless recorded within an isolated invocation
+    Global,
+    /// Metrics recorded while polling a lambda invocation, via
+    /// [Builder::with_lambda_invocation_isolation](super::Builder::with_lambda_invocation_isolation)
+    #[cfg_attr(not(any(feature = "lambda", test)), allow(dead_code))]
+    Invocation,
+}
+
+/// Metric names seen by describe_xxx and names already reported via [Builder::with_lint](super::Builder::with_lint)
+#[derive(Default)]
+struct Lint {
+    described: HashSet<metrics::KeyName>,
+    reported: HashSet<String>,
 }
 
 /// Embedded CloudWatch Metrics Collector + Emitter
@@ -99,63 +787,1111 @@ struct CollectorState {
 /// ```
 pub struct Collector {
     state: Mutex<CollectorState>,
+    /// Metrics registered into [CollectorState::info_tree] and [CollectorState::invocation_tree], looked up without
+    /// locking the state
+    registry: Registry<Registered, Registration>,
+    invocation_registry: Registry<Registered, Registration>,
+    /// Serializes calls to flush so documents from concurrent flushes never interleave, guarding the buffer documents
+    /// are serialized into, reused between flushes
+    flush_lock: Mutex<Vec<u8>>,
+    /// Properties computed at flush via [Collector::set_property_fn], kept out of the state so they're evaluated
+    /// without holding its lock
+    property_fns: RwLock<BTreeMap<SharedString, PropertyFn>>,
+    /// Store units seperate because describe_xxx isn't scoped to labels, and outside of the state so recording a
+    /// duration doesn't contend with registration and flushes
+    /// Key is a copied String until at least metrics cl #381 is released in metrics
+    units: RwLock<HashMap<metrics::KeyName, metrics::Unit>>,
+    /// Document counts per distinct combination, only tracked via [Builder::with_cardinality_audit], kept out of the
+    /// state like the lint so documents are checked after a flush releases its lock
+    cardinality_audit: Option<Mutex<BTreeMap<CardinalityKey, u64>>>,
+    /// Metric hygiene findings, only tracked via [Builder::with_lint]
+    lint: Option<Mutex<Lint>>,
+    /// Tenants admitted so far for [Builder::max_tenants], kept out of the state so registering a metric never waits
+    /// on a flush
+    admitted_tenants: Mutex<BTreeSet<SharedString>>,
+    /// Milliseconds since the unix epoch when this collector was created
+    start_time: u64,
+    #[cfg(feature = "perf")]
+    perf: crate::perf::PerfCounters,
     pub config: Config,
 }
 
 impl Collector {
     pub fn new(config: Config, #[cfg(feature = "lambda")] lambda_cold_start_span: Option<tracing::span::Span>) -> Self {
+        let start_time = config.timestamp.unwrap_or_else(|| config.clock.now());
         Self {
             state: Mutex::new(CollectorState {
                 info_tree: BTreeMap::new(),
-                units: HashMap::new(),
-                properties: BTreeMap::new(),
+                invocation_tree: BTreeMap::new(),
+                properties: config.properties.clone(),
+                flush_properties: HashSet::new(),
+                custom_metrics: 0,
+                tenants: BTreeMap::new(),
+                label_sets_registered: 0,
+                last_flush: start_time,
+                flush_sequence: 0,
+                dropped_non_finite: 0,
                 #[cfg(feature = "lambda")]
                 lambda_cold_start_span,
+                #[cfg(feature = "lambda")]
+                lambda_cold_start_done: false,
+                #[cfg(feature = "lambda")]
+                lambda_flush_deferred: false,
             }),
+            flush_lock: Mutex::new(Vec::new()),
+            property_fns: RwLock::new(BTreeMap::new()),
+            units: RwLock::new(HashMap::new()),
+            cardinality_audit: config.cardinality_audit.then(Default::default),
+            lint: config.lint.then(Default::default),
+            admitted_tenants: Mutex::new(BTreeSet::new()),
+            start_time,
+            registry: Registry::new(),
+            invocation_registry: Registry::new(),
+            #[cfg(feature = "perf")]
+            perf: Default::default(),
             config,
         }
     }
 
+    /// Lock the collector's state, measuring the wait with the `perf` feature flag
+    fn lock_state(&self) -> MutexGuard<'_, CollectorState> {
+        #[cfg(feature = "perf")]
+        let start = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        #[cfg(feature = "perf")]
+        self.perf.record_lock(start.elapsed());
+
+        for scope in [Scope::Global, Scope::Invocation] {
+            if self.registry(scope).has_pending() {
+                self.merge_registrations(&mut state, scope);
+            }
+        }
+        state
+    }
+
+    fn registry(&self, scope: Scope) -> &Registry<Registered, Registration> {
+        match scope {
+            Scope::Global => &self.registry,
+            Scope::Invocation => &self.invocation_registry,
+        }
+    }
+
+    /// Add the metrics registered since the state was last locked to their label sets
+    fn merge_registrations(&self, state: &mut CollectorState, scope: Scope) {
+        for Registration { labels, key, info } in self.registry(scope).drain() {
+            if !state.tree(scope).contains_key(&labels) {
+                let label_set = self.label_set_info(state, &labels);
+                state.tree(scope).insert(labels.clone(), label_set);
+            }
+            if let Some(label_set) = state.tree(scope).get_mut(&labels) {
+                label_set.metrics.insert(key.clone(), info);
+            }
+            self.track_custom_metric(state, &key);
+        }
+    }
+
+    /// Overhead of this collector since it was created, for asserting on in benchmarks and CI perf gates
+    ///
+    /// *requires the `perf` feature flag*
+    #[cfg(feature = "perf")]
+    pub fn bench_stats(&self) -> crate::perf::BenchStats {
+        self.perf.stats()
+    }
+
     /// Set a property to emit with the metrics
     /// * Properites persist accross flush calls
     /// * Setting a property with same name multiple times will overwrite the previous value
+    /// * Values may be any JSON value including nested objects and arrays, which are emitted as is
+    /// * Properties named `_aws` or after a default dimension would corrupt the document, they are rejected with an
+    ///   error reported via [Builder::on_error](super::Builder::on_error)
     pub fn set_property(&self, name: impl Into<SharedString>, value: impl Into<Value>) -> &Self {
+        let name = name.into();
+        let value = value.into();
+        if let Some(reason) = reserved_property(&name, &self.config.default_dimensions) {
+            self.handle_error(format_args!("Unable to set property {name} as {reason}"));
+            return self;
+        }
+        self.insert_property(&mut self.lock_state(), name, value);
+        self
+    }
+
+    /// Set several properties in one locked operation, so a flush never sees only some of them
+    /// * Values may be nested objects and arrays, e.g. a whole request context, which are emitted as is
+    /// * Each property is checked as by [Collector::set_property], rejected ones are skipped
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics.set_properties([
+    ///     ("RequestId".into(), "ABC123".into()),
+    ///     ("Context".into(), serde_json::json!({"Tenant": "acme", "Features": ["beta"]})),
+    /// ]);
+    /// ```
+    pub fn set_properties(&self, properties: impl IntoIterator<Item = (SharedString, Value)>) -> &Self {
+        // Errors are reported before locking so the handler may use the collector
+        let properties: Vec<_> = properties
+            .into_iter()
+            .filter(
+                |(name, _)| match reserved_property(name, &self.config.default_dimensions) {
+                    Some(reason) => {
+                        self.handle_error(format_args!("Unable to set property {name} as {reason}"));
+                        false
+                    }
+                    None => true,
+                },
+            )
+            .collect();
+
+        let mut state = self.lock_state();
+        for (name, value) in properties {
+            self.insert_property(&mut state, name, value);
+        }
+        drop(state);
+        self
+    }
+
+    /// Set a property that passed the reserved name checks
+    fn insert_property(&self, state: &mut CollectorState, name: SharedString, mut value: Value) {
+        if let Some(TenantConfig {
+            key: TenantKey::Property(tenant_property),
+            ..
+        }) = &self.config.tenant
         {
-            let mut state = self.state.lock().unwrap();
-            state.properties.insert(name.into(), value.into());
+            if *tenant_property == name {
+                if let Some(tenant) = value.as_str() {
+                    if !self.admit_tenant(tenant) {
+                        value = TENANT_OVERFLOW.into();
+                    }
+                }
+                if let Some(tenant) = value.as_str() {
+                    self.track_tenant_namespace(&mut state.tenants, tenant);
+                }
+            }
+        }
+
+        self.property_fns_mut().remove(&name);
+        state.flush_properties.remove(&name);
+        state.properties.insert(name, value);
+    }
+
+    /// Set a property for the next flush only, for per-request values such as a customer id that shouldn't carry
+    /// over to the metrics of later requests
+    /// * Removed once the next flush completes, including flushes that emit nothing
+    /// * Otherwise behaves as [Collector::set_property], which makes the property persist again
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics.set_flush_property("CustomerId", "ABC123");
+    /// metrics::counter!("requests").increment(1);
+    /// metrics.flush(std::io::stdout()).unwrap();
+    /// ```
+    pub fn set_flush_property(&self, name: impl Into<SharedString>, value: impl Into<Value>) -> &Self {
+        let name = name.into();
+        if let Some(reason) = reserved_property(&name, &self.config.default_dimensions) {
+            self.handle_error(format_args!("Unable to set property {name} as {reason}"));
+            return self;
+        }
+
+        // Set and marked under one lock, so a flush in between can't emit it without clearing it
+        let mut state = self.lock_state();
+        self.insert_property(&mut state, name.clone(), value.into());
+        state.flush_properties.insert(name);
+        drop(state);
+        self
+    }
+
+    /// Set a property whose value is computed by `value` at each flush, for values that should always be fresh such
+    /// as queue depth or memory usage, without a background task updating them
+    /// * Replaces a property of the same name set via [Collector::set_property] and vice versa
+    /// * `value` is called before the collector's state is locked, so a panic doesn't leave the collector unusable
+    /// * Emitted as a plain property, not as a dimension via [Builder::with_property_dimension](super::Builder::with_property_dimension)
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// // Holding a weak reference lets the queue be dropped, the property is then emitted as null
+    /// let queue_depth = Arc::new(AtomicUsize::new(0));
+    /// let weak = Arc::downgrade(&queue_depth);
+    /// metrics.set_property_fn("QueueDepth", move || {
+    ///     weak.upgrade().map(|depth| depth.load(Ordering::Relaxed)).into()
+    /// });
+    /// ```
+    pub fn set_property_fn(
+        &self,
+        name: impl Into<SharedString>,
+        value: impl Fn() -> Value + Send + Sync + 'static,
+    ) -> &Self {
+        let name = name.into();
+        if let Some(reason) = reserved_property(&name, &self.config.default_dimensions) {
+            self.handle_error(format_args!("Unable to set property {name} as {reason}"));
+            return self;
         }
+        let mut state = self.lock_state();
+        state.properties.remove(&name);
+        self.property_fns_mut().insert(name, Arc::new(value));
+        drop(state);
         self
     }
 
+    /// The properties via [Collector::set_property_fn], taken while holding the state lock so they're updated in
+    /// step with the other properties
+    fn property_fns_mut(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<SharedString, PropertyFn>> {
+        self.property_fns.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The units metrics are described with, read on every [Collector::record_duration]
+    fn units(&self) -> std::sync::RwLockReadGuard<'_, HashMap<metrics::KeyName, metrics::Unit>> {
+        self.units.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn units_mut(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<metrics::KeyName, metrics::Unit>> {
+        self.units.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Evaluate the properties via [Collector::set_property_fn] without holding any lock, so a slow or panicking
+    /// callback can't hold up or poison the collector
+    fn computed_properties(&self) -> Vec<(SharedString, Value)> {
+        let property_fns: Vec<(SharedString, PropertyFn)> = self
+            .property_fns
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        property_fns.into_iter().map(|(name, value)| (name, value())).collect()
+    }
+
     /// Removes a property to emit with the metrics
     pub fn remove_property<'a>(&'a self, name: impl Into<&'a str>) -> &'a Self {
         {
-            let mut state = self.state.lock().unwrap();
-            state.properties.remove(name.into());
+            let mut state = self.lock_state();
+            let name = name.into();
+            state.properties.remove(name);
+            self.property_fns_mut().remove(name);
+            state.flush_properties.remove(name);
         }
         self
     }
 
+    /// Set the unit of a metric name, as if described via `metrics::describe_*!`
+    /// * Applies to every label set of the metric name from the next flush onwards
+    /// * Useful when the code path describing a metric isn't executed, or to correct a unit after registration
+    /// * Always replaces the current unit regardless of [Builder::with_unit_policy](super::Builder::with_unit_policy)
+    pub fn set_unit(&self, name: impl Into<metrics::KeyName>, unit: metrics::Unit) -> &Self {
+        let name = name.into();
+        if let Some(lint) = &self.lint {
+            let mut lint = lint.lock().unwrap_or_else(PoisonError::into_inner);
+            lint.described.insert(name.clone());
+        }
+        self.units_mut().insert(name, unit);
+        self
+    }
+
+    /// Copy of the current properties
+    pub(crate) fn properties(&self) -> BTreeMap<SharedString, Value> {
+        self.lock_state().properties.clone()
+    }
+
+    /// Registers a counter up front, returning a handle that records without building a [metrics::Key] and looking
+    /// it up in the recorder on every call
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// let requests = metrics.counter("requests", &[("Method", "Default")]);
+    ///
+    /// requests.increment(1);
+    /// ```
+    pub fn counter(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Counter {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_counter(&Recorder::from(self), &key, &HANDLE_METADATA)
+    }
+
+    /// Registers a gauge up front, returning a handle that records without building a [metrics::Key] and looking
+    /// it up in the recorder on every call
+    pub fn gauge(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Gauge {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_gauge(&Recorder::from(self), &key, &HANDLE_METADATA)
+    }
+
+    /// Registers a histogram up front, returning a handle that records without building a [metrics::Key] and
+    /// looking it up in the recorder on every call
+    pub fn histogram(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Histogram {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_histogram(&Recorder::from(self), &key, &HANDLE_METADATA)
+    }
+
+    /// Records a duration into a histogram in the unit the histogram is described with via
+    /// `metrics::describe_histogram!`
+    /// * [metrics::Unit::Milliseconds], [metrics::Unit::Microseconds] and [metrics::Unit::Nanoseconds] are honored,
+    ///   anything else records seconds
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics::describe_histogram!("latency", metrics::Unit::Milliseconds, "");
+    ///
+    /// let start = std::time::Instant::now();
+    /// // ... handle the request
+    /// metrics.record_duration("latency", &[("Method", "Get")], start.elapsed());
+    /// ```
+    pub fn record_duration(
+        &self,
+        name: impl Into<metrics::KeyName>,
+        labels: impl metrics::IntoLabels,
+        duration: Duration,
+    ) {
+        let name = name.into();
+        let value = self.duration_value(name.as_str(), duration);
+        self.histogram(name, labels).record(value);
+    }
+
+    /// Starts a [Timer] recording the elapsed time via [Collector::record_duration] when dropped
+    pub fn timer(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> Timer<'_> {
+        let name = name.into();
+        let histogram = self.histogram(name.clone(), labels);
+        Timer::new(self, name, histogram)
+    }
+
+    /// A duration converted to the unit a histogram is described with
+    pub(crate) fn duration_value(&self, name: &str, duration: Duration) -> f64 {
+        match self.units().get(name) {
+            Some(metrics::Unit::Milliseconds) => duration.as_secs_f64() * 1_000.0,
+            Some(metrics::Unit::Microseconds) => duration.as_secs_f64() * 1_000_000.0,
+            Some(metrics::Unit::Nanoseconds) => duration.as_nanos() as f64,
+            _ => duration.as_secs_f64(),
+        }
+    }
+
+    /// Registers a histogram up front like [Collector::histogram], returning a handle that reports when it can't
+    /// buffer more values until the next flush
+    /// * Values recorded via the handle aren't forwarded to [Builder::with_mirror_recorder](super::Builder::with_mirror_recorder)
+    pub fn bounded_histogram(
+        &self,
+        name: impl Into<metrics::KeyName>,
+        labels: impl metrics::IntoLabels,
+    ) -> BoundedHistogram {
+        let key = metrics::Key::from_parts(name, labels);
+        let recorder = Recorder::from(self);
+        BoundedHistogram {
+            handle: recorder
+                .prepare_key(&key)
+                .and_then(|key| recorder.histogram_handle(&key)),
+        }
+    }
+
+    /// Returns a [metrics::Recorder] bound to this collector, for use with [metrics::with_local_recorder] so tests
+    /// can exercise their metric emission in-process without installing a global recorder
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics::with_local_recorder(&metrics.recorder(), || {
+    ///     metrics::counter!("requests").increment(1);
+    /// });
+    /// ```
+    pub fn recorder(&self) -> Recorder<'_> {
+        Recorder::from(self)
+    }
+
+    /// Returns a [Subscope](crate::Subscope) registering metrics under `name`, for libraries to record into the host
+    /// application's namespace, see [Subscope](crate::Subscope)
+    pub fn subscope(&self, name: impl Into<SharedString>) -> crate::Subscope<'_> {
+        crate::Subscope::new(self, name.into())
+    }
+
+    /// Creates a [MetricsScope](logger::MetricsScope) with this collector's namespace, default dimensions and
+    /// current properties, which accumulates metrics independently and flushes to the given writer when dropped
+    pub fn scope<W: std::io::Write>(&self, writer: W) -> logger::MetricsScope<W> {
+        logger::MetricsLogger::from_collector(self).into_scope(writer)
+    }
+
+    /// Estimate the number of billable custom metrics registered so far and their projected monthly cost
+    /// * Every distinct metric name + label set is a distinct custom metric in CloudWatch
+    /// * Metrics written via [Collector::write_single] are not included
+    pub fn estimate_cost(&self) -> CostEstimate {
+        let custom_metrics = self.lock_state().custom_metrics;
+        CostEstimate {
+            custom_metrics,
+            monthly_cost: custom_metrics as f64 * CUSTOM_METRIC_MONTHLY_COST,
+        }
+    }
+
+    /// Every distinct (namespace, metric, dimension set) combination emitted since startup, sorted by
+    /// descending document count
+    /// * Empty unless enabled via [Builder::with_cardinality_audit](super::Builder::with_cardinality_audit)
+    pub fn cardinality_report(&self) -> Vec<CardinalityEntry> {
+        let Some(audit) = &self.cardinality_audit else {
+            return Vec::new();
+        };
+        let audit = audit.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut report: Vec<CardinalityEntry> = audit
+            .iter()
+            .map(|((namespace, metric, dimensions), documents)| CardinalityEntry {
+                namespace: namespace.clone(),
+                metric: metric.clone(),
+                dimensions: dimensions.clone(),
+                documents: *documents,
+            })
+            .collect();
+        report.sort_by_key(|entry| std::cmp::Reverse(entry.documents));
+        report
+    }
+
+    /// Current value of every registered metric without resetting them, e.g. for health endpoints or asserting on
+    /// in tests
+    /// * Sorted by label set and then by metric name
+    /// * Values reflect what the next flush would emit, counters and histograms are reset by each flush
+    ///
+    /// # Example
+    /// ```
+    /// use metrics_cloudwatch_embedded::SnapshotValue;
+    ///
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics::counter!("requests").increment(1);
+    /// assert_eq!(metrics.snapshot()[0].value, SnapshotValue::Counter(1));
+    /// ```
+    pub fn snapshot(&self) -> Vec<SnapshotEntry> {
+        let state = self.lock_state();
+
+        let mut snapshot = Vec::new();
+        for (labels, label_set) in &state.info_tree {
+            for (key, info) in &label_set.metrics {
+                let value = match info {
+                    MetricInfo::Counter(counter) => SnapshotValue::Counter(counter.value.load(Ordering::Relaxed)),
+                    MetricInfo::Gauge(gauge) => {
+                        SnapshotValue::Gauge(f64::from_bits(gauge.value.load(Ordering::Relaxed)))
+                    }
+                    MetricInfo::Histogram(histogram) => SnapshotValue::Histogram(histogram.handle.pending()),
+                };
+                snapshot.push(SnapshotEntry {
+                    name: key.name().to_owned(),
+                    labels: labels
+                        .iter()
+                        .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                        .collect(),
+                    value,
+                });
+            }
+        }
+        snapshot
+    }
+
+    /// Number of NaN and infinite gauge and histogram values dropped by flushes so far
+    /// * CloudWatch rejects a whole document with a non-finite value, so they're dropped at flush with a warning
+    ///   rather than losing the rest of the label set's metrics
+    /// * A histogram aggregated via [Builder::with_histogram_statistic_sets](super::Builder::with_histogram_statistic_sets)
+    ///   that overflowed with a non-finite value drops the values aggregated since the last flush
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics::gauge!("ratio").set(f64::NAN);
+    /// metrics.flush(std::io::sink()).unwrap();
+    /// assert_eq!(metrics.dropped_non_finite(), 1);
+    /// ```
+    pub fn dropped_non_finite(&self) -> u64 {
+        self.lock_state().dropped_non_finite
+    }
+
+    /// Count a newly registered metric, warning once when crossing the configured threshold
+    fn track_custom_metric(&self, state: &mut CollectorState, key: &metrics::Key) {
+        state.custom_metrics += 1;
+
+        if let Some(threshold) = self.config.custom_metric_warning_threshold {
+            if state.custom_metrics == threshold + 1 {
+                report_warn!("Registering {key} exceeds the threshold of {threshold} custom metrics");
+            }
+        }
+    }
+
+    /// Empty label set with its dimensions serialized up front, including the default dimensions
+    fn label_set_info(&self, state: &mut CollectorState, labels: &[metrics::Label]) -> LabelSetInfo {
+        let default_dimensions = self.config.default_dimensions.iter().map(|(k, v)| (&**k, &**v));
+        if let Some(TenantConfig {
+            key: TenantKey::Label(name),
+            ..
+        }) = &self.config.tenant
+        {
+            if let Some(tenant) = labels.iter().find(|label| label.key() == &**name) {
+                self.track_tenant_namespace(&mut state.tenants, tenant.value());
+            }
+        }
+
+        let labels = labels.iter().map(|label| (label.key(), label.value()));
+        state.label_sets_registered += 1;
+
+        LabelSetInfo {
+            metrics: BTreeMap::new(),
+            dimensions: Arc::new(emf::DimensionFragment::new(default_dimensions.chain(labels))),
+            registered: state.label_sets_registered,
+        }
+    }
+
+    /// Admit a tenant, returns false if it must be attributed to [TENANT_OVERFLOW] due to [Builder::max_tenants]
+    /// * Only locks the admitted tenants, never the state, as it's called on metric registration
+    fn admit_tenant(&self, tenant: &str) -> bool {
+        let Some(config) = &self.config.tenant else {
+            return true;
+        };
+
+        let mut tenants = self.admitted_tenants.lock().unwrap_or_else(PoisonError::into_inner);
+        if tenants.contains(tenant) {
+            return true;
+        }
+
+        if let Some(max_tenants) = config.max_tenants {
+            if tenants.len() >= max_tenants && tenant != TENANT_OVERFLOW {
+                report_warn!(
+                    "Attributing tenant {tenant} to {TENANT_OVERFLOW} as there are already {max_tenants} tenants"
+                );
+                tenants.insert(TENANT_OVERFLOW.into());
+                return false;
+            }
+        }
+
+        tenants.insert(tenant.to_string().into());
+        true
+    }
+
+    /// Record the namespace of an admitted tenant for [Collector::namespace]
+    fn track_tenant_namespace(&self, tenants: &mut BTreeMap<SharedString, SharedString>, tenant: &str) {
+        if !self.config.tenant.as_ref().is_some_and(|config| config.namespaces) || tenants.contains_key(tenant) {
+            return;
+        }
+        let namespace = format!("{}/{tenant}", self.config.cloudwatch_namespace);
+        tenants.insert(tenant.to_string().into(), namespace.into());
+    }
+
+    /// Namespace of a document with the given labels and properties, honoring [Builder::with_tenant_namespaces]
+    fn namespace<'a>(
+        &'a self,
+        tenants: &'a BTreeMap<SharedString, SharedString>,
+        labels: &'a [metrics::Label],
+        properties: &'a BTreeMap<SharedString, Value>,
+    ) -> &'a str {
+        let tenant = match &self.config.tenant {
+            Some(TenantConfig {
+                key: TenantKey::Label(name),
+                ..
+            }) => labels
+                .iter()
+                .find(|label| label.key() == &**name)
+                .map(|label| label.value()),
+            Some(TenantConfig {
+                key: TenantKey::Property(name),
+                ..
+            }) => properties.get(name).and_then(Value::as_str),
+            None => None,
+        };
+
+        match tenant.and_then(|tenant| tenants.get(tenant)) {
+            Some(namespace) => namespace,
+            None => &self.config.cloudwatch_namespace,
+        }
+    }
+
+    /// Properties emitted as dimensions instead, the tenant property and any set via
+    /// [Builder::with_property_dimension]
+    fn property_dimensions<'a>(
+        &'a self,
+        properties: &'a BTreeMap<SharedString, Value>,
+    ) -> impl Iterator<Item = (&'a str, &'a str, &'a str)> {
+        self.tenant_property(properties)
+            .map(|(name, value)| (name, name, value))
+            .into_iter()
+            .chain(
+                self.config
+                    .property_dimensions
+                    .iter()
+                    .filter_map(|(dimension, property)| {
+                        Some((&**property, &**dimension, properties.get(property)?.as_str()?))
+                    }),
+            )
+    }
+
+    /// Name and value of the tenant property, if configured via [Builder::with_tenant_property] and set
+    fn tenant_property<'a>(&'a self, properties: &'a BTreeMap<SharedString, Value>) -> Option<(&'a str, &'a str)> {
+        match &self.config.tenant {
+            Some(TenantConfig {
+                key: TenantKey::Property(name),
+                ..
+            }) => properties
+                .get(name)
+                .and_then(Value::as_str)
+                .map(|tenant| (&**name, tenant)),
+            _ => None,
+        }
+    }
+
     /// Compute the timestamp unless it was set via [Builder::with_timestamp]
     fn timestamp(&self) -> u64 {
         // Timestamp can be set to a
         match self.config.timestamp {
             Some(t) => t,
-            None => SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis() as u64,
+            None => self.config.clock.now(),
         }
     }
 
     /// Flush the current counter values to an implementation of std::io::Write
-    pub fn flush(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+    /// * Concurrent calls are serialized, each emits the values recorded since the previous flush completed
+    pub fn flush(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        // The buffer is cleared before use, so there's nothing to recover from a panic while holding the lock
+        let mut buffer = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.flush_locked(&mut buffer, writer, None, false, Scope::Global)
+            .map(|_| ())
+    }
+
+    /// Flush the current values to the [Sink](crate::Sink) set via [Builder::with_sink](super::Builder::with_sink),
+    /// stdout by default
+    pub fn flush_to_sink(&self) -> std::io::Result<()> {
+        let mut writer = self.sink_writer();
+        self.flush(&mut writer)?;
+        std::io::Write::flush(&mut writer)
+    }
+
+    /// Returns a writer emitting to the [Sink](crate::Sink) set via [Builder::with_sink](super::Builder::with_sink),
+    /// e.g. for [Collector::write_single]
+    pub fn sink_writer(&self) -> crate::SinkWriter {
+        crate::SinkWriter::new(self.config.sink.clone())
+    }
+
+    /// Flush the current values as one [serde_json::Value] per document instead of writing them
+    /// * Allows mutating documents (adding fields, redacting properties) before writing them through your own
+    ///   transport
+    pub fn flush_to_values(&self) -> Vec<Value> {
+        let _flush = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut values = Vec::new();
+        self.flush_documents(Scope::Global, false, None, None, |emf, _| {
+            values.push(Value::Object(self.document(emf)?));
+            Ok(())
+        })
+        .expect("serializing to a value is infallible");
+        values
+    }
+
+    /// Flush the current values to a string, one document per line exactly as [Collector::flush] writes them
+    /// * Intended for tests and tooling, see [Collector::flush_to_values] for inspecting individual fields
+    /// * Fails like [Collector::flush] when a document is dropped via
+    ///   [Builder::with_strict_validation](super::Builder::with_strict_validation)
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .with_timestamp(0)
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics::counter!("requests").increment(1);
+    /// assert_eq!(
+    ///     metrics.flush_to_string().unwrap(),
+    ///     r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"MyApplication","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}"#.to_owned() + "\n"
+    /// );
+    /// ```
+    pub fn flush_to_string(&self) -> std::io::Result<String> {
+        let mut output = Vec::new();
+        self.flush(&mut output)?;
+        Ok(String::from_utf8(output).expect("documents are valid UTF-8"))
+    }
+
+    /// Flush unless another flush is in progress, in which case this returns Ok(false) immediately
+    /// * Values recorded since are left for the next flush
+    /// * Useful for coalescing a periodic flush with flushes triggered elsewhere
+    pub fn try_flush(&self, writer: impl std::io::Write) -> std::io::Result<bool> {
+        let mut buffer = match self.flush_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(false),
+        };
+        self.flush_locked(&mut buffer, writer, None, false, Scope::Global)
+            .map(|_| true)
+    }
+
+    /// Flush to a [tokio::io::AsyncWrite] so async servers don't block the runtime on I/O
+    /// * Documents are serialized up front, the writes happen after the flush lock is released
+    ///
+    /// *requires the `tokio` feature flag*
+    #[cfg(feature = "tokio")]
+    pub async fn flush_async(&self, mut writer: impl tokio::io::AsyncWrite + Unpin) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut documents = Vec::new();
+        self.flush(&mut documents)?;
+        writer.write_all(&documents).await?;
+        writer.flush().await
+    }
+
+    /// Spawn a tokio task flushing to `writer` every `interval` until shut down, see
+    /// [BackgroundFlusher](crate::BackgroundFlusher)
+    ///
+    /// *requires the `tokio` feature flag*
+    #[cfg(feature = "tokio")]
+    pub fn spawn_flusher(
+        &'static self,
+        interval: Duration,
+        writer: impl tokio::io::AsyncWrite + Unpin + Send + 'static,
+    ) -> crate::flusher::BackgroundFlusher {
+        crate::flusher::BackgroundFlusher::spawn(self, interval, writer)
+    }
+
+    /// Flush, bounding the time spent waiting for another flush and writing documents, for callers near a deadline
+    /// such as the end of a Lambda invocation
+    /// * Returns the number of documents written, zero if another flush didn't finish in time
+    /// * Stops between label sets once the timeout has elapsed, the remaining values are left for the next flush
+    /// * Writes one document at a time until the timeout has elapsed, any left are written first by the next flush
+    /// * A write in progress isn't interrupted, a blocked writer can overrun the timeout by one document
+    pub fn try_flush_within(&self, timeout: Duration, writer: impl std::io::Write) -> std::io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = loop {
+            match self.flush_lock.try_lock() {
+                Ok(guard) => break guard,
+                Err(TryLockError::Poisoned(e)) => break e.into_inner(),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => return Ok(0),
+                Err(TryLockError::WouldBlock) => std::thread::sleep(Duration::from_millis(1)),
+            }
+        };
+        self.flush_locked(&mut buffer, writer, Some(deadline), false, Scope::Global)
+            .map(|report| report.documents)
+    }
+
+    /// Flush, reporting each label set visited along with the metrics emitted and skipped, for debugging why a
+    /// series is missing from CloudWatch
+    /// * Slower than [Collector::flush] as the names are copied into the report
+    pub fn flush_with_report(&self, writer: impl std::io::Write) -> std::io::Result<FlushReport> {
+        let mut buffer = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.flush_locked(&mut buffer, writer, None, true, Scope::Global)
+    }
+
+    /// Final flush, detaching the collector from the global recorder if it was installed via
+    /// [Builder::init_arc](super::Builder::init_arc)
+    /// * Metrics registered afterwards are discarded until another collector is installed via
+    ///   [Builder::init_arc](super::Builder::init_arc), handles registered before keep recording into this
+    ///   collector
+    /// * A collector installed via [Builder::init](super::Builder::init) can't be detached, it's only flushed
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init_arc()
+    ///      .unwrap();
+    ///
+    /// metrics::counter!("requests").increment(1);
+    ///
+    /// metrics.shutdown(std::io::stdout()).unwrap();
+    /// ```
+    pub fn shutdown(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        SharedRecorder::uninstall(self);
+        self.flush(writer)
+    }
+
+    /// Installs a panic hook that flushes pending metrics to the [Sink](crate::Sink) set via
+    /// [Builder::with_sink](super::Builder::with_sink), stdout by default, before chaining to the previous hook, so
+    /// the metrics leading up to a crash are preserved
+    /// * Also covers Lambda handlers, where the runtime catches the panic before the `lambda::MetricsLayer` would
+    ///   flush
+    /// * Skipped if a flush is already in progress or the panic happened while the collector was locked
+    pub fn install_panic_flush(&'static self) {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Err(e) = self.panic_flush(self.sink_writer()) {
+                self.handle_error(format_args!("Failed to flush metrics on panic: {e}"));
+            }
+            previous(info);
+        }));
+    }
+
+    /// Registers a process exit handler flushing pending metrics to the [Sink](crate::Sink) set via
+    /// [Builder::with_sink](super::Builder::with_sink), stdout by default, covering CLIs and batch jobs that end via
+    /// [std::process::exit] where destructors and code after the call never run
+    /// * Also runs when `main` returns normally
+    /// * Waits up to a second for a flush in progress on another thread
+    /// * Returns false where exit handlers aren't supported, registering more than once only flushes once
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyBatchJob")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics.flush_at_exit();
+    ///
+    /// metrics::counter!("records").increment(1);
+    /// // std::process::exit(0) would still emit records
+    /// ```
+    pub fn flush_at_exit(&'static self) -> bool {
+        #[cfg(any(unix, windows))]
+        {
+            extern "C" {
+                fn atexit(callback: extern "C" fn()) -> std::os::raw::c_int;
+            }
+
+            static REGISTERED: std::sync::Once = std::sync::Once::new();
+            static SUPPORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+            let mut collectors = EXIT_FLUSH.lock().unwrap_or_else(PoisonError::into_inner);
+            if !collectors.iter().any(|collector| std::ptr::eq(*collector, self)) {
+                collectors.push(self);
+            }
+            drop(collectors);
+
+            REGISTERED.call_once(|| {
+                // SAFETY: atexit is provided by the C runtime on every unix and windows target
+                let registered = unsafe { atexit(exit_flush) } == 0;
+                SUPPORTED.store(registered, Ordering::Relaxed);
+            });
+            SUPPORTED.load(Ordering::Relaxed)
+        }
+        #[cfg(not(any(unix, windows)))]
+        false
+    }
+
+    /// Flush from a panic hook, which must not block on a lock the panicking thread may already hold
+    pub(crate) fn panic_flush(&self, writer: impl std::io::Write) -> std::io::Result<bool> {
+        if self.state.try_lock().is_err() {
+            return Ok(false);
+        }
+        self.try_flush(writer)
+    }
+
+    /// Flush implementation, the caller must hold the flush lock
+    /// * The report only lists label sets when detailed
+    fn flush_locked(
+        &self,
+        buffer: &mut Vec<u8>,
+        writer: impl std::io::Write,
+        deadline: Option<Instant>,
+        detailed: bool,
+        scope: Scope,
+    ) -> std::io::Result<FlushReport> {
+        // Dimensions are serialized once per label set, unless they vary between flushes or are needed for the audit
+        let cached_dimensions = !self.config.cardinality_audit
+            && !self.config.limit_warnings
+            && self.config.on_document.is_none()
+            && self.config.property_dimensions.is_empty()
+            && self.config.dimension_rollups.is_empty()
+            && !matches!(
+                self.config.tenant,
+                Some(TenantConfig {
+                    key: TenantKey::Property(_),
+                    ..
+                })
+            );
+
+        // Instrumented so the overhead of large flushes is visible in traces
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "metrics flush",
+            documents = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            duration_us = tracing::field::Empty
+        );
+        #[cfg(feature = "tracing")]
+        let _span = span.enter();
+        let start = Instant::now();
+        #[cfg(feature = "perf")]
+        let allocations = crate::perf::allocations();
+        let mut writer = CountingWriter {
+            inner: writer,
+            bytes: 0,
+        };
+        let mut documents: Vec<std::ops::Range<usize>> = Vec::new();
+        let mut label_sets = Vec::new();
+        let mut invalid = None;
+        // Reported once every document is built, so the error handler may use the collector
+        let mut errors = Vec::new();
+
+        // Documents are built from a snapshot taken under the state lock, so serializing and a slow writer don't
+        // hold up setting properties, and written in one go once built unless there's a deadline
+        // * Documents a deadline left in the buffer are written first
+        let pending = buffer.iter().filter(|&&byte| byte == b'\n').count();
+        let result = self.flush_documents(
+            scope,
+            cached_dimensions,
+            deadline,
+            detailed.then_some(&mut label_sets),
+            |emf, dimensions| {
+                let start = buffer.len();
+                if cached_dimensions {
+                    emf.write_with_dimensions(&mut *buffer, dimensions)?;
+                } else {
+                    self.write_document(&mut *buffer, emf)?;
+                }
+                if self.config.strict_validation {
+                    if let Err(e) = crate::validate_document(&buffer[start..]) {
+                        errors.push(format!("Dropping invalid metrics document: {e}"));
+                        invalid.get_or_insert(e);
+                        buffer.truncate(start);
+                        return Ok(());
+                    }
+                }
+                if self.config.limit_warnings {
+                    check_limits(emf, buffer.len() - start, &mut errors);
+                }
+                documents.push(start..buffer.len());
+                Ok(())
+            },
+        );
+        for error in errors {
+            self.handle_error(format_args!("{error}"));
+        }
+        let result = result.and_then(|()| {
+            for document in &documents {
+                if let Some(mut mirror) = self.config.debug_mirror.as_ref().and_then(DebugMirror::sample) {
+                    if let Err(e) = mirror.0.write_all(&buffer[document.clone()]) {
+                        self.handle_error(format_args!("Failed to write metrics to the debug mirror: {e}"));
+                    }
+                }
+            }
+            match deadline {
+                None if buffer.is_empty() => Ok(0),
+                None => std::io::Write::write_all(&mut writer, buffer).map(|()| pending + documents.len()),
+                // One document at a time so the deadline bounds the writes too
+                Some(deadline) => {
+                    let (mut written, mut bytes) = (0, 0);
+                    for document in buffer.split_inclusive(|&byte| byte == b'\n') {
+                        if Instant::now() >= deadline {
+                            break;
+                        }
+                        std::io::Write::write_all(&mut writer, document)?;
+                        written += 1;
+                        bytes += document.len();
+                    }
+                    buffer.drain(..bytes);
+                    Ok(written)
+                }
+            }
+        });
+
+        // Keep documents left by the deadline for the next flush, those of a failed write are dropped
+        if deadline.is_none() || result.is_err() {
+            buffer.clear();
+        }
+        // Keep the buffer between flushes, unless an unusually large flush grew it
+        if buffer.capacity() > MAX_RETAINED_BUFFER {
+            buffer.shrink_to_fit();
+        }
+
+        // Nothing happened since the last flush, emit a heartbeat so alarms can tell the process is still alive
+        let mut documents = *result.as_ref().unwrap_or(&0);
+        let result = match result {
+            Ok(0) if buffer.is_empty() && self.config.heartbeat => {
+                documents += 1;
+                self.write_single(HEARTBEAT, Some(metrics::Unit::Count), 1, &mut writer)
+            }
+            result => result.map(|_| ()),
+        };
+        // Documents exceeding a limit were skipped via with_strict_validation, fail once the rest are written
+        let result = match invalid {
+            Some(e) => result.and(Err(e.into())),
+            None => result,
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("documents", documents);
+            span.record("bytes", writer.bytes);
+            span.record("duration_us", start.elapsed().as_micros() as u64);
+        }
+        #[cfg(feature = "perf")]
+        self.perf.record_flush(
+            start.elapsed(),
+            documents,
+            writer.bytes,
+            crate::perf::allocations() - allocations,
+        );
+        #[cfg(not(any(feature = "tracing", feature = "perf")))]
+        let _ = start;
+        result.map(|_| FlushReport {
+            documents,
+            bytes: writer.bytes,
+            label_sets,
+        })
+    }
+
+    /// Convert a document to a [serde_json::Map], passing it through the on_document hook
+    fn document(&self, emf: &emf::EmbeddedMetrics) -> serde_json::Result<EmfDocument> {
+        let Value::Object(mut document) = serde_json::to_value(emf)? else {
+            unreachable!("embedded metrics serialize to an object");
+        };
+        if let Some(on_document) = &self.config.on_document {
+            on_document(&mut document);
+        }
+        Ok(document)
+    }
+
+    /// Serialize a document followed by a newline, passing it through the on_document hook if configured
+    fn write_document(&self, mut writer: impl std::io::Write, emf: &emf::EmbeddedMetrics) -> std::io::Result<()> {
+        if self.config.on_document.is_some() {
+            serde_json::to_writer(&mut writer, &self.document(emf)?)?;
+        } else {
+            serde_json::to_writer(&mut writer, emf)?;
+        }
+        writeln!(writer)
+    }
+
+    /// Build each document with data to flush and hand it to emit, the caller must hold the flush lock
+    /// * Values are copied under the state lock and documents built once it's released
+    /// * With cached_dimensions the document omits the dimensions, emit must write them from the fragment
+    /// * Stops between label sets once the deadline has passed, leaving the rest for the next flush
+    /// * Appends an entry per label set visited to report if given
+    fn flush_documents(
+        &self,
+        scope: Scope,
+        cached_dimensions: bool,
+        deadline: Option<Instant>,
+        report: Option<&mut Vec<LabelSetReport>>,
+        mut emit: impl FnMut(&emf::EmbeddedMetrics, &emf::DimensionFragment) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        let computed_properties = self.computed_properties();
+        let timestamp = self.timestamp();
+        let snapshot = self.flush_snapshot(scope, timestamp, deadline, report);
+
         let mut emf = emf::EmbeddedMetrics {
             aws: emf::EmbeddedMetricsAws {
-                timestamp: self.timestamp(),
-                cloudwatch_metrics: [emf::EmbeddedNamespace {
+                timestamp,
+                cloudwatch_metrics: vec![emf::EmbeddedNamespace {
                     namespace: &self.config.cloudwatch_namespace,
-                    dimensions: [Vec::with_capacity(MAX_DIMENSIONS)],
+                    dimensions: vec![Vec::with_capacity(MAX_DIMENSIONS)],
                     metrics: Vec::new(),
                 }],
             },
@@ -164,87 +1900,273 @@ impl Collector {
             values: BTreeMap::new(),
         };
 
-        for dimension in &self.config.default_dimensions {
-            emf.aws.cloudwatch_metrics[0].dimensions[0].push(&dimension.0);
-            emf.dimensions.insert(&dimension.0, &dimension.1);
+        if !cached_dimensions {
+            for dimension in &self.config.default_dimensions {
+                emf.aws.cloudwatch_metrics[0].dimensions[0].push(&dimension.0);
+                emf.dimensions.insert(&dimension.0, &dimension.1);
+            }
         }
 
-        // Delay aquiring the mutex until we need it
-        let state = self.state.lock().unwrap();
+        for (key, value) in &snapshot.properties {
+            emf.properties.insert(key, self.property_value(key, value));
+        }
+        for (key, value) in &computed_properties {
+            emf.properties.insert(key, self.property_value(key, value));
+        }
 
-        for (key, value) in &state.properties {
-            emf.properties.insert(key, value.clone());
+        if self.config.iso_time {
+            emf.properties.insert(ISO_TIME, emf::iso8601(emf.aws.timestamp).into());
+        }
+
+        if self.config.flush_sequence {
+            emf.properties.insert(FLUSH_SEQUENCE, snapshot.flush_sequence.into());
+            emf.properties.insert(PROCESS_START_TIME, self.start_time.into());
         }
 
-        // Emit an embedded metrics document for each distinct label set
-        for (labels, metrics) in &state.info_tree {
-            emf.aws.cloudwatch_metrics[0].metrics.clear();
-            emf.values.clear();
-            let mut should_flush = false;
+        // Tenant and dimension properties are emitted as dimensions instead
+        for (property, dimension, value) in self.property_dimensions(&snapshot.properties) {
+            emf.properties.remove(property);
+            emf.aws.cloudwatch_metrics[0].dimensions[0].push(dimension);
+            emf.dimensions.insert(dimension, value);
+        }
+
+        // Emit an embedded metrics document for each distinct label set
+        for label_set in &snapshot.label_sets {
+            emf.clear_metrics();
+            emf.aws.cloudwatch_metrics[0].namespace = &label_set.namespace;
+
+            if !cached_dimensions {
+                for label in &label_set.labels {
+                    emf.aws.cloudwatch_metrics[0].dimensions[0].push(label.key());
+                    emf.dimensions.insert(label.key(), label.value());
+                }
+                self.add_dimension_rollups(&mut emf);
+            }
+
+            for metric in &label_set.metrics {
+                // Split label sets with more metrics than fit in one document, emitting what's accumulated before
+                // listing a metric that would exceed the limit
+                if emf.metrics_len() >= self.config.max_metrics {
+                    self.check_document(&emf);
+                    emit(&emf, &label_set.dimensions)?;
+                    emf.clear_metrics();
+                }
+                directive(&mut emf, metric.route).metrics.push(emf::EmbeddedMetric {
+                    name: metric.name.as_str(),
+                    unit: metric.unit,
+                });
+                emf.values.insert(metric.name.as_str(), metric.value.to_value());
+            }
+
+            self.check_document(&emf);
+            emit(&emf, &label_set.dimensions)?;
+
+            // Rollback our labels/dimensions (but keep any default dimensions)
+            if !cached_dimensions {
+                emf.aws.cloudwatch_metrics[0].dimensions.truncate(1);
+                for label in &label_set.labels {
+                    emf.aws.cloudwatch_metrics[0].dimensions[0].pop();
+                    emf.dimensions.remove(&label.key());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the values to flush and everything needed to build their documents under the state lock
+    /// * Only label sets with values to emit are included, every label set visited is appended to report if given
+    /// * Stops between label sets once the deadline has passed
+    fn flush_snapshot(
+        &self,
+        scope: Scope,
+        timestamp: u64,
+        deadline: Option<Instant>,
+        mut report: Option<&mut Vec<LabelSetReport>>,
+    ) -> FlushSnapshot<'_> {
+        let mut guard = self.lock_state();
+        let state = &mut *guard;
+        let units = self.units();
+
+        // Seconds since the last flush for rates, at least a millisecond so they stay finite
+        let interval = timestamp.saturating_sub(state.last_flush).max(1) as f64 / 1000.0;
+        state.last_flush = timestamp;
+
+        let tree = match scope {
+            Scope::Global => &mut state.info_tree,
+            Scope::Invocation => &mut state.invocation_tree,
+        };
+        let mut label_sets: Vec<_> = tree.iter_mut().collect();
+        match &self.config.flush_order {
+            FlushOrder::Labels => {}
+            FlushOrder::Registration => label_sets.sort_by_key(|(_, label_set)| label_set.registered),
+            FlushOrder::Label(name) => label_sets.sort_by_cached_key(|(labels, _)| {
+                let value = labels
+                    .iter()
+                    .find(|label| label.key() == name.as_ref())
+                    .map(|label| label.value());
+                (value.is_none(), value)
+            }),
+        }
 
-            for label in labels {
-                emf.aws.cloudwatch_metrics[0].dimensions[0].push(label.key());
-                emf.dimensions.insert(label.key(), label.value());
+        let mut snapshot = FlushSnapshot {
+            properties: state.properties.clone(),
+            flush_sequence: state.flush_sequence,
+            label_sets: Vec::new(),
+        };
+        for (labels, label_set) in label_sets {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
             }
 
-            for (key, info) in metrics {
+            let mut metrics = Vec::new();
+            let mut entry = report.is_some().then(|| LabelSetReport {
+                labels: labels
+                    .iter()
+                    .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                    .collect(),
+                ..Default::default()
+            });
+
+            for (key, info) in &mut label_set.metrics {
+                let listed = metrics.len();
+                let route = self.namespace_route(key.name());
+                let unit = units.get(key.name()).map(emf::unit_to_str);
+                let mut push = |name: metrics::KeyName, unit: Option<&'static str>, value: FlushValue| {
+                    metrics.push(MetricSnapshot {
+                        name,
+                        unit,
+                        route,
+                        value,
+                    })
+                };
+
                 match info {
                     MetricInfo::Counter(counter) => {
                         let value = counter.value.swap(0, Ordering::Relaxed);
-
-                        // Omit this metric if there is no delta since last flushed
-                        if value != 0 {
-                            emf.aws.cloudwatch_metrics[0].metrics.push(emf::EmbeddedMetric {
-                                name: key.name(),
-                                unit: state.units.get(key.name()).map(emf::unit_to_str),
-                            });
-                            emf.values.insert(key.name(), value.into());
-                            should_flush = true;
+                        let emission = self.counter_emission(key.name());
+
+                        // Omit this metric if there is no delta since last flushed, unless configured otherwise
+                        if value != 0 || emission != CounterEmission::Delta || self.emit_zeros(key.name()) {
+                            match emission {
+                                CounterEmission::Rate => push(
+                                    key.name_shared(),
+                                    Some(emf::unit_to_str(&metrics::Unit::CountPerSecond)),
+                                    FlushValue::Gauge(value as f64 / interval),
+                                ),
+                                _ => push(key.name_shared(), unit, FlushValue::Counter(value)),
+                            }
                         }
                     }
+                    MetricInfo::Gauge(gauge) if !f64::from_bits(gauge.value.load(Ordering::Relaxed)).is_finite() => {
+                        report_warn!("Dropping non-finite value of gauge {}", key.name());
+                        state.dropped_non_finite += 1;
+                    }
                     MetricInfo::Gauge(gauge) => {
-                        let value = f64::from_bits(gauge.value.load(Ordering::Relaxed));
-
-                        emf.aws.cloudwatch_metrics[0].metrics.push(emf::EmbeddedMetric {
-                            name: key.name(),
-                            unit: state.units.get(key.name()).map(emf::unit_to_str),
+                        let bits = gauge.value.load(Ordering::Relaxed);
+
+                        // Omit an unchanged gauge until the heartbeat interval has elapsed
+                        let unchanged = self.config.gauge_heartbeat.is_some_and(|heartbeat| {
+                            gauge.last_emitted.is_some_and(|(last_bits, last_timestamp)| {
+                                last_bits == bits
+                                    && timestamp.saturating_sub(last_timestamp) < heartbeat.as_millis() as u64
+                            })
                         });
-                        emf.values.insert(key.name(), value.into());
-                        should_flush = true;
+                        if !unchanged {
+                            push(key.name_shared(), unit, FlushValue::Gauge(f64::from_bits(bits)));
+                            gauge.last_emitted = Some((bits, timestamp));
+                        }
                     }
                     MetricInfo::Histogram(histogram) => {
-                        let mut values: Vec<f64> = Vec::new();
-                        while let Ok(value) = histogram.receiver.try_recv() {
-                            values.push(value);
+                        if let Some(sketch) = &histogram.handle.sketch {
+                            let mut sketch = sketch.lock().unwrap_or_else(PoisonError::into_inner);
+
+                            let dropped = sketch.non_finite();
+                            if dropped > 0 {
+                                report_warn!("Dropping {dropped} non-finite values of histogram {}", key.name());
+                                state.dropped_non_finite += dropped;
+                            }
+
+                            // Omit this metric if there is no new values since last flushed
+                            let emit = !sketch.is_empty();
+                            if emit && histogram.percentiles.is_empty() {
+                                let (values, counts) = sketch.distribution(MAX_HISTOGRAM_VALUES);
+                                push(key.name_shared(), unit, FlushValue::Distribution(values, counts));
+                            } else if emit {
+                                for (quantile, name) in &histogram.percentiles {
+                                    let value = sketch.quantile(*quantile).unwrap_or_default();
+                                    push(name.clone().into(), unit, FlushValue::Gauge(value));
+                                }
+                            }
+                            sketch.clear();
+                        } else {
+                            let overflow = histogram.handle.take_overflow();
+                            let values = histogram.swap();
+
+                            // A single non-finite value would make the whole document unparseable
+                            let mut dropped = values.iter().filter(|value| !value.is_finite()).count() as u64;
+                            let overflow = match overflow {
+                                Some(set) if !set.is_finite() => {
+                                    dropped += set.count;
+                                    None
+                                }
+                                overflow => overflow,
+                            };
+                            if dropped > 0 {
+                                report_warn!("Dropping {dropped} non-finite values of histogram {}", key.name());
+                                state.dropped_non_finite += dropped;
+                            }
+                            let values = values.iter().copied().filter(|value| value.is_finite());
+
+                            // Emit a statistic set when values overflowed the buffer since last flushed, and omit
+                            // this metric if there is no new values since last flushed
+                            match overflow {
+                                Some(mut set) => {
+                                    values.for_each(|value| set.record(value));
+                                    push(key.name_shared(), unit, FlushValue::Statistics(set));
+                                }
+                                None => {
+                                    let values: Vec<f64> = values.collect();
+                                    if !values.is_empty() {
+                                        push(key.name_shared(), unit, FlushValue::Histogram(values));
+                                    }
+                                }
+                            }
                         }
+                    }
+                }
 
-                        // Omit this metric if there is no new values since last flushed
-                        if !values.is_empty() {
-                            emf.aws.cloudwatch_metrics[0].metrics.push(emf::EmbeddedMetric {
-                                name: key.name(),
-                                unit: state.units.get(key.name()).map(emf::unit_to_str),
-                            });
-                            emf.values.insert(key.name(), values.into());
-                            should_flush = true;
-                        }
+                if let Some(entry) = &mut entry {
+                    match metrics.len() > listed {
+                        true => entry.emitted.push(key.name().to_owned()),
+                        false => entry.skipped.push(key.name().to_owned()),
                     }
                 }
             }
 
-            // Skip if we have no data to flush
-            if should_flush {
-                serde_json::to_writer(&mut writer, &emf)?;
-                writeln!(writer)?;
+            if let (Some(report), Some(entry)) = (&mut report, entry) {
+                report.push(entry);
             }
 
-            // Rollback our labels/dimensions (but keep any default dimensions)
-            for label in labels {
-                emf.aws.cloudwatch_metrics[0].dimensions[0].pop();
-                emf.dimensions.remove(&label.key());
+            // Skip if we have no data to flush
+            if !metrics.is_empty() {
+                snapshot.label_sets.push(LabelSetSnapshot {
+                    labels: labels.clone(),
+                    namespace: self.namespace(&state.tenants, labels, &state.properties).to_owned(),
+                    dimensions: label_set.dimensions.clone(),
+                    metrics,
+                });
             }
         }
 
-        Ok(())
+        // The sequence number is only taken once a document is emitted, so empty flushes don't look like gaps
+        if self.config.flush_sequence && !snapshot.label_sets.is_empty() {
+            state.flush_sequence += 1;
+            snapshot.flush_sequence = state.flush_sequence;
+        }
+        for name in std::mem::take(&mut state.flush_properties) {
+            state.properties.remove(&name);
+        }
+        snapshot
     }
 
     /// Write a single metric to an implementation of [std::io::Write], avoids the overhead of
@@ -254,14 +2176,133 @@ impl Collector {
         name: impl Into<SharedString>,
         unit: Option<metrics::Unit>,
         value: impl Into<Value>,
+        writer: impl std::io::Write,
+    ) -> std::io::Result<()> {
+        let name = name.into();
+        self.write_metrics(None, &[(&name, unit, value.into())], &WriteOptions::default(), writer)
+    }
+
+    /// Write several metrics in a single document like [Collector::write_single], rather than one document and one
+    /// write per metric
+    /// * Split across documents of up to 100 metrics (see
+    ///   [Builder::with_max_metrics_per_document](super::Builder::with_max_metrics_per_document)), written at once
+    /// * Fails with [std::io::ErrorKind::InvalidInput] if a name is given more than once
+    ///
+    /// # Example
+    /// ```
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics
+    ///     .write_batch(
+    ///         &[
+    ///             ("BatchSize", Some(metrics::Unit::Count), 250.0),
+    ///             ("BatchDuration", Some(metrics::Unit::Milliseconds), 1200.0),
+    ///         ],
+    ///         std::io::stdout(),
+    ///     )
+    ///     .unwrap();
+    /// ```
+    pub fn write_batch<N: AsRef<str>, V: Clone + Into<Value>>(
+        &self,
+        metrics: &[(N, Option<metrics::Unit>, V)],
+        writer: impl std::io::Write,
+    ) -> std::io::Result<()> {
+        let metrics: Vec<(&str, Option<metrics::Unit>, Value)> = metrics
+            .iter()
+            .map(|(name, unit, value)| (name.as_ref(), *unit, value.clone().into()))
+            .collect();
+        for (i, (name, _, _)) in metrics.iter().enumerate() {
+            if metrics[..i].iter().any(|(other, _, _)| other == name) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("metric {name} is given more than once"),
+                ));
+            }
+        }
+        self.write_metrics(None, &metrics, &WriteOptions::default(), writer)
+    }
+
+    /// Write a single metric like [Collector::write_single] with extra dimensions, a timestamp and properties, for
+    /// one-off events such as a cold start attributed to a tenant
+    /// * Fails with [std::io::ErrorKind::InvalidInput] if a dimension overlaps a default dimension, a property would
+    ///   overwrite a dimension or there'd be more than 30 dimensions
+    ///
+    /// # Example
+    /// ```
+    /// use metrics_cloudwatch_embedded::WriteOptions;
+    ///
+    /// let metrics = metrics_cloudwatch_embedded::Builder::new()
+    ///      .cloudwatch_namespace("MyApplication")
+    ///      .init()
+    ///      .unwrap();
+    ///
+    /// metrics
+    ///     .write_single_with(
+    ///         "ColdStart",
+    ///         Some(metrics::Unit::Count),
+    ///         1,
+    ///         &WriteOptions::new().dimension("Tenant", "tenant-1"),
+    ///         std::io::stdout(),
+    ///     )
+    ///     .unwrap();
+    /// ```
+    pub fn write_single_with(
+        &self,
+        name: impl Into<SharedString>,
+        unit: Option<metrics::Unit>,
+        value: impl Into<Value>,
+        options: &WriteOptions,
+        writer: impl std::io::Write,
+    ) -> std::io::Result<()> {
+        if let Some(reason) = options.check(&self.config.default_dimensions) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, reason));
+        }
+        let name = name.into();
+        self.write_metrics(None, &[(&name, unit, value.into())], options, writer)
+    }
+
+    /// Write a single metric like [Collector::write_single] but under another namespace, for rare administrative
+    /// metrics (e.g. deploy markers) without constructing a second collector
+    /// * Takes precedence over [Builder::with_tenant_namespaces](super::Builder::with_tenant_namespaces)
+    pub fn write_single_to_namespace(
+        &self,
+        namespace: &str,
+        name: impl Into<SharedString>,
+        unit: Option<metrics::Unit>,
+        value: impl Into<Value>,
+        writer: impl std::io::Write,
+    ) -> std::io::Result<()> {
+        let decorated = NamespaceEnvironment::apply(&self.config.namespace_environment, namespace);
+        let namespace = decorated.as_deref().unwrap_or(namespace);
+        let name = name.into();
+        self.write_metrics(
+            Some(namespace),
+            &[(&name, unit, value.into())],
+            &WriteOptions::default(),
+            writer,
+        )
+    }
+
+    /// Write metrics outside of the recorder, in documents of up to max_metrics
+    /// * Each document's first directive is the namespace of its first metric, metrics routed to other namespaces
+    ///   are added as further directives
+    fn write_metrics(
+        &self,
+        namespace: Option<&str>,
+        metrics: &[(&str, Option<metrics::Unit>, Value)],
+        options: &WriteOptions,
         mut writer: impl std::io::Write,
     ) -> std::io::Result<()> {
+        let computed_properties = self.computed_properties();
         let mut emf = emf::EmbeddedMetrics {
             aws: emf::EmbeddedMetricsAws {
-                timestamp: self.timestamp(),
-                cloudwatch_metrics: [emf::EmbeddedNamespace {
+                timestamp: options.timestamp.unwrap_or_else(|| self.timestamp()),
+                cloudwatch_metrics: vec![emf::EmbeddedNamespace {
                     namespace: &self.config.cloudwatch_namespace,
-                    dimensions: [Vec::with_capacity(MAX_DIMENSIONS)],
+                    dimensions: vec![Vec::with_capacity(MAX_DIMENSIONS)],
                     metrics: Vec::new(),
                 }],
             },
@@ -270,212 +2311,781 @@ impl Collector {
             values: BTreeMap::new(),
         };
 
-        for dimension in &self.config.default_dimensions {
+        for dimension in self.config.default_dimensions.iter().chain(&options.dimensions) {
             emf.aws.cloudwatch_metrics[0].dimensions[0].push(&dimension.0);
             emf.dimensions.insert(&dimension.0, &dimension.1);
         }
 
         // Delay aquiring the mutex until we need it
-        let state = self.state.lock().unwrap();
+        let mut guard = self.lock_state();
+        let state = &mut *guard;
 
         for (key, value) in &state.properties {
-            emf.properties.insert(key, value.clone());
+            emf.properties.insert(key, self.property_value(key, value));
+        }
+        for (key, value) in &computed_properties {
+            emf.properties.insert(key, self.property_value(key, value));
+        }
+        for (key, value) in &options.properties {
+            emf.properties.insert(key, self.property_value(key, value));
         }
 
-        let name = name.into();
-        emf.aws.cloudwatch_metrics[0].metrics.push(emf::EmbeddedMetric {
-            name: &name,
-            unit: unit.map(|u| emf::unit_to_str(&u)),
-        });
-        emf.values.insert(&name, value.into());
+        if self.config.iso_time {
+            emf.properties.insert(ISO_TIME, emf::iso8601(emf.aws.timestamp).into());
+        }
 
-        serde_json::to_writer(&mut writer, &emf)?;
-        writeln!(writer)
+        // Tenant and dimension properties are emitted as dimensions instead
+        for (property, dimension, value) in self.property_dimensions(&state.properties) {
+            emf.properties.remove(property);
+            emf.aws.cloudwatch_metrics[0].dimensions[0].push(dimension);
+            emf.dimensions.insert(dimension, value);
+        }
+        self.add_dimension_rollups(&mut emf);
+        let default_namespace = self.namespace(&state.tenants, &[], &state.properties);
+        let metric_namespace = |name: &str| {
+            namespace
+                .or_else(|| self.namespace_route(name))
+                .unwrap_or(default_namespace)
+        };
+
+        // Write once the state is unlocked, like flush
+        let mut documents = Vec::new();
+        for chunk in metrics.chunks(self.config.max_metrics) {
+            emf.clear_metrics();
+            emf.aws.cloudwatch_metrics[0].namespace = metric_namespace(chunk[0].0);
+            for (name, unit, value) in chunk {
+                emf.namespace_mut(metric_namespace(name))
+                    .metrics
+                    .push(emf::EmbeddedMetric {
+                        name,
+                        unit: unit.map(|u| emf::unit_to_str(&u)),
+                    });
+                emf.values.insert(name, value.clone());
+            }
+
+            if let Some(audit) = &self.cardinality_audit {
+                audit_document(&mut audit.lock().unwrap_or_else(PoisonError::into_inner), &emf);
+            }
+            self.write_document(&mut documents, &emf)?;
+        }
+        drop(emf);
+        drop(guard);
+        writer.write_all(&documents)
+    }
+
+    /// Copy of a property value for a document, redacted via [Builder::redact_property](super::Builder::redact_property)
+    /// or [Builder::with_redaction](super::Builder::with_redaction)
+    fn property_value(&self, name: &str, value: &Value) -> Value {
+        if self
+            .config
+            .redacted_properties
+            .iter()
+            .any(|redacted| redacted.as_ref() == name)
+        {
+            return REDACTED.into();
+        }
+        let mut value = value.clone();
+        if let Some(redaction) = &self.config.redaction {
+            redaction(name, &mut value);
+        }
+        value
+    }
+
+    /// Whether a counter is emitted without a delta, via [Builder::with_emit_zeros_for](super::Builder::with_emit_zeros_for)
+    fn emit_zeros(&self, name: &str) -> bool {
+        self.config.emit_zeros.iter().any(|pattern| name_matches(pattern, name))
+    }
+
+    /// Namespace a metric is routed to via [Builder::with_namespace_route](super::Builder::with_namespace_route)
+    fn namespace_route(&self, name: &str) -> Option<&str> {
+        self.config
+            .namespace_routes
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_ref()))
+            .map(|(_, namespace)| namespace.as_ref())
+    }
+
+    /// Run the cardinality audit and lint over a document about to be emitted, if enabled
+    fn check_document(&self, emf: &emf::EmbeddedMetrics) {
+        if let Some(audit) = &self.cardinality_audit {
+            audit_document(&mut audit.lock().unwrap_or_else(PoisonError::into_inner), emf);
+        }
+        if let Some(lint) = &self.lint {
+            lint_document(&mut lint.lock().unwrap_or_else(PoisonError::into_inner), emf);
+        }
+    }
+
+    /// Add the dimension sets via [Builder::with_dimension_rollup](super::Builder::with_dimension_rollup) whose
+    /// dimensions are all in the document
+    fn add_dimension_rollups<'a>(&'a self, emf: &mut emf::EmbeddedMetrics<'a>) {
+        let dimensions = &mut emf.aws.cloudwatch_metrics[0].dimensions;
+        for rollup in &self.config.dimension_rollups {
+            let rollup: Vec<&str> = rollup.iter().map(|dimension| &**dimension).collect();
+            if rollup.iter().all(|dimension| dimensions[0].contains(dimension)) && !dimensions.contains(&rollup) {
+                dimensions.push(rollup);
+            }
+        }
+    }
+
+    /// How a counter is emitted, via [Builder::with_counter_emission](super::Builder::with_counter_emission)
+    fn counter_emission(&self, name: &str) -> CounterEmission {
+        self.config
+            .counter_emission
+            .iter()
+            .find(|(pattern, _)| name_matches(pattern, name))
+            .map_or(CounterEmission::Delta, |(_, emission)| *emission)
     }
 
-    /// update the unit for a metric name, disregard what metric type it is
+    /// Report an error via [Builder::on_error](super::Builder::on_error) if configured, otherwise via tracing or log
+    pub(crate) fn handle_error(&self, message: std::fmt::Arguments) {
+        match &self.config.on_error {
+            Some(on_error) => on_error(&message.to_string()),
+            None => report_error!("{message}"),
+        }
+    }
+
+    /// update the unit for a metric name from describe_xxx, disregard what metric type it is
+    /// * Describing without a unit leaves the current unit in place
+    /// * Conflicting units are resolved by the unit policy and reported as an error
     fn update_unit(&self, key: metrics::KeyName, unit: Option<metrics::Unit>) {
-        let mut state = self.state.lock().unwrap();
+        if let Some(lint) = &self.lint {
+            let mut lint = lint.lock().unwrap_or_else(PoisonError::into_inner);
+            lint.described.insert(key.clone());
+        }
+        let Some(unit) = unit else {
+            return;
+        };
 
-        if let Some(unit) = unit {
-            state.units.insert(key, unit);
-        } else {
-            state.units.remove(&key);
+        let mut units = self.units_mut();
+        let conflict = match units.get(&key) {
+            Some(current) if *current != unit => Some(*current),
+            _ => None,
+        };
+        if conflict.is_none() || self.config.unit_policy == UnitPolicy::LatestWins {
+            units.insert(key.clone(), unit);
+        }
+        drop(units);
+
+        if let Some(current) = conflict {
+            let kept = match self.config.unit_policy {
+                UnitPolicy::LatestWins => unit,
+                UnitPolicy::FirstWins => current,
+            };
+            self.handle_error(format_args!(
+                "Metric {} described as {} after {}, keeping {}",
+                key.as_str(),
+                emf::unit_to_str(&unit),
+                emf::unit_to_str(&current),
+                emf::unit_to_str(&kept)
+            ));
         }
     }
 
     #[cfg(feature = "lambda")]
     pub fn take_cold_start_span(&self) -> Option<tracing::span::Span> {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.lock_state();
         state.lambda_cold_start_span.take()
     }
+
+    /// Skips the lambda layer's automatic flush at the end of the current invocation, e.g. when the handler has
+    /// already flushed manually or is batching across several related invocations
+    /// * Values recorded are kept for the next flush
+    /// * Invocations ending in a panic are still flushed via [Builder::lambda_panic_metric](super::Builder::lambda_panic_metric)
+    ///
+    /// *requires the `lambda` feature flag*
+    #[cfg(feature = "lambda")]
+    pub fn defer_flush(&self) {
+        self.lock_state().lambda_flush_deferred = true;
+    }
+
+    /// Returns and clears the flag set via [Collector::defer_flush]
+    #[cfg(feature = "lambda")]
+    pub(crate) fn take_defer_flush(&self) -> bool {
+        std::mem::take(&mut self.lock_state().lambda_flush_deferred)
+    }
+
+    /// [metrics::Recorder] for metrics recorded within an invocation, which are only emitted via
+    /// [Collector::flush_invocation]
+    #[cfg(any(feature = "lambda", test))]
+    pub(crate) fn invocation_recorder(&self) -> Recorder<'_> {
+        Recorder {
+            collector: self,
+            scope: Scope::Invocation,
+        }
+    }
+
+    /// Flush the metrics recorded via [Collector::invocation_recorder] only, leaving the rest for the next
+    /// [Collector::flush]
+    #[cfg(any(feature = "lambda", test))]
+    pub(crate) fn flush_invocation(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        let mut buffer = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.flush_locked(&mut buffer, writer, None, false, Scope::Invocation)
+            .map(|_| ())
+    }
+
+    /// Returns true for the first invoke handled by this collector only
+    #[cfg(feature = "lambda")]
+    pub(crate) fn take_cold_start(&self) -> bool {
+        let mut state = self.lock_state();
+        !std::mem::replace(&mut state.lambda_cold_start_done, true)
+    }
 }
 
-pub struct Recorder {
-    collector: &'static Collector,
+/// [std::io::Write] adapter counting the bytes written for the flush span
+struct CountingWriter<W> {
+    inner: W,
+    bytes: usize,
 }
 
-impl From<&'static Collector> for Recorder {
-    fn from(collector: &'static Collector) -> Self {
-        Self { collector }
+impl<W: std::io::Write> std::io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
-impl metrics::Recorder for Recorder {
-    fn describe_counter(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, _description: SharedString) {
-        self.collector.update_unit(key, unit)
+/// Whether a metric name matches a pattern of either an exact name or a prefix followed by `*`
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
     }
+}
 
-    fn describe_gauge(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, _description: SharedString) {
-        self.collector.update_unit(key, unit)
+/// Collectors registered via [Collector::flush_at_exit]
+static EXIT_FLUSH: Mutex<Vec<&'static Collector>> = Mutex::new(Vec::new());
+
+/// Process exit handler registered via [Collector::flush_at_exit]
+extern "C" fn exit_flush() {
+    let collectors = std::mem::take(&mut *EXIT_FLUSH.lock().unwrap_or_else(PoisonError::into_inner));
+    for collector in collectors {
+        if let Err(e) = collector.try_flush_within(Duration::from_secs(1), collector.sink_writer()) {
+            collector.handle_error(format_args!("Failed to flush metrics at exit: {e}"));
+        }
     }
+}
 
-    fn describe_histogram(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, _description: SharedString) {
-        self.collector.update_unit(key, unit)
+/// Gauges are stored as f64 bits, whole values are emitted as integers to keep documents compact
+/// Histogram values as emitted, beyond the 100 values per metric as distinct values and their counts, or else as a
+/// statistic set
+fn histogram_value(values: &[f64]) -> Value {
+    if values.len() <= MAX_HISTOGRAM_VALUES {
+        return values.into();
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut distinct: Vec<(f64, u64)> = Vec::new();
+    for value in sorted {
+        match distinct.last_mut() {
+            Some((last, count)) if *last == value => *count += 1,
+            _ => distinct.push((value, 1)),
+        }
+    }
+
+    if distinct.len() <= MAX_HISTOGRAM_VALUES {
+        let (values, counts): (Vec<f64>, Vec<u64>) = distinct.into_iter().unzip();
+        serde_json::json!({"Values": values, "Counts": counts})
+    } else {
+        let mut set = StatisticSet::new(values[0]);
+        values[1..].iter().for_each(|&value| set.record(value));
+        set.to_value()
     }
+}
 
-    #[allow(clippy::mutable_key_type)] // metrics::Key has interior mutability
-    fn register_counter(&self, key: &metrics::Key, _metadata: &metrics::Metadata) -> metrics::Counter {
-        // Build our own copy of the labels before aquiring the mutex
-        let labels: Vec<metrics::Label> = key.labels().cloned().collect();
+fn gauge_value(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
 
-        if self.collector.config.default_dimensions.len() + labels.len() > MAX_DIMENSIONS {
-            error!("Unable to register counter {key} as it has more than {MAX_DIMENSIONS} dimensions/labels");
-            return metrics::Counter::noop();
+/// Why a property name can't be used, if it would overwrite the `_aws` metadata or a default dimension's value
+pub(crate) fn reserved_property(
+    name: &str,
+    default_dimensions: &[(SharedString, SharedString)],
+) -> Option<&'static str> {
+    if name == "_aws" {
+        Some("it is reserved for the document metadata")
+    } else if default_dimensions
+        .iter()
+        .any(|(dimension, _)| dimension.as_ref() == name)
+    {
+        Some("it is already a default dimension")
+    } else {
+        None
+    }
+}
+
+/// The directive of the document a metric is listed in, by its namespace route if any
+fn directive<'a, 'e>(
+    emf: &'e mut emf::EmbeddedMetrics<'a>,
+    route: Option<&'a str>,
+) -> &'e mut emf::EmbeddedNamespace<'a> {
+    match route {
+        Some(namespace) => emf.namespace_mut(namespace),
+        None => &mut emf.aws.cloudwatch_metrics[0],
+    }
+}
+
+/// Describe a document approaching the limits via [Builder::with_limit_warnings](super::Builder::with_limit_warnings)
+fn check_limits(emf: &emf::EmbeddedMetrics, bytes: usize, errors: &mut Vec<String>) {
+    let namespace = &emf.aws.cloudwatch_metrics[0];
+    let dimensions = &namespace.dimensions[0];
+    let metrics = emf.metrics_len();
+
+    if metrics * 10 >= MAX_METRICS * 9 {
+        errors.push(format!(
+            "Document in {} with dimensions {dimensions:?} has {metrics} metrics, approaching the limit of \
+             {MAX_METRICS}, consider splitting the metrics across label sets",
+            namespace.namespace
+        ));
+    }
+    if dimensions.len() >= 25 {
+        errors.push(format!(
+            "Document in {} has {} dimensions {dimensions:?}, approaching the limit of {MAX_DIMENSIONS}, metrics \
+             with more are rejected at registration",
+            namespace.namespace,
+            dimensions.len()
+        ));
+    }
+    if bytes * 10 >= MAX_DOCUMENT_BYTES * 9 {
+        errors.push(format!(
+            "Document in {} with dimensions {dimensions:?} is {bytes} bytes, approaching the CloudWatch Logs limit \
+             of {MAX_DOCUMENT_BYTES} bytes per event, large properties or histograms with many values are the \
+             usual cause",
+            namespace.namespace
+        ));
+    }
+}
+
+/// Count a document against each (namespace, metric, dimension set) combination it contains
+fn audit_document(audit: &mut BTreeMap<CardinalityKey, u64>, emf: &emf::EmbeddedMetrics) {
+    for namespace in &emf.aws.cloudwatch_metrics {
+        let dimensions: Vec<(String, String)> = namespace.dimensions[0]
+            .iter()
+            .map(|name| (name.to_string(), emf.dimensions[name].to_string()))
+            .collect();
+
+        for metric in &namespace.metrics {
+            let key = (
+                namespace.namespace.to_string(),
+                metric.name.to_string(),
+                dimensions.clone(),
+            );
+            *audit.entry(key).or_default() += 1;
         }
+    }
+}
 
-        let mut state = self.collector.state.lock().unwrap();
+/// Warn about each metric in a document emitted without a unit or without ever being described, once per metric name
+fn lint_document(lint: &mut Lint, emf: &emf::EmbeddedMetrics) {
+    for metric in emf
+        .aws
+        .cloudwatch_metrics
+        .iter()
+        .flat_map(|namespace| &namespace.metrics)
+    {
+        if lint.reported.contains(metric.name) {
+            continue;
+        }
+        if !lint.described.contains(metric.name) {
+            report_warn!("Metric {} was emitted without being described", metric.name);
+        } else if metric.unit.is_none() {
+            report_warn!("Metric {} was emitted without a unit", metric.name);
+        } else {
+            continue;
+        }
+        lint.reported.insert(metric.name.to_owned());
+    }
+}
 
-        // Does this metric already exist?
-        if let Some(label_info) = state.info_tree.get_mut(&labels) {
-            if let Some(info) = label_info.get(key) {
-                match info {
-                    MetricInfo::Counter(info) => {
-                        return metrics::Counter::from_arc(info.value.clone());
-                    }
-                    MetricInfo::Gauge(_) => {
-                        error!("Unable to register counter {key} as it was already registered as a gauge");
-                        return metrics::Counter::noop();
-                    }
-                    MetricInfo::Histogram(_) => {
-                        error!("Unable to register counter {key} as it was already registered as a histogram");
-                        return metrics::Counter::noop();
-                    }
+/// [metrics::Recorder] registering metrics with a [Collector], installed globally by
+/// [Builder::init](super::Builder::init) or obtained via [Collector::recorder]
+pub struct Recorder<'a> {
+    collector: &'a Collector,
+    scope: Scope,
+}
+
+impl<'a> From<&'a Collector> for Recorder<'a> {
+    fn from(collector: &'a Collector) -> Self {
+        Self {
+            collector,
+            scope: Scope::Global,
+        }
+    }
+}
+
+impl Recorder<'_> {
+    /// Applies [Builder::with_global_label], [Builder::with_key_rewrite], [Builder::with_name_sanitizer] and
+    /// [Builder::max_tenants] to a key before registration, returns None if the key was rejected
+    fn prepare_key<'a>(&self, key: &'a metrics::Key) -> Option<Cow<'a, metrics::Key>> {
+        let key = self.global_key(key);
+
+        let key = match &self.collector.config.key_rewrite {
+            Some(rewrite) => Cow::Owned(rewrite(&key)?),
+            None => key,
+        };
+
+        let key = match &self.collector.config.name_sanitizer {
+            Some(sanitizer) => match sanitizer.key(key) {
+                Ok(key) => key,
+                Err(e) => {
+                    self.collector.handle_error(format_args!("Rejecting metric: {e}"));
+                    return None;
                 }
-            } else {
-                // Label exists, counter does not
-                let value = Arc::new(AtomicU64::new(0));
-                label_info.insert(key.clone(), MetricInfo::Counter(CounterInfo { value: value.clone() }));
+            },
+            None => key,
+        };
 
-                return metrics::Counter::from_arc(value);
-            }
+        Some(self.sorted_key(self.tenant_key(key)))
+    }
+
+    /// Applies [Builder::with_sorted_labels] to a key
+    fn sorted_key<'a>(&self, key: Cow<'a, metrics::Key>) -> Cow<'a, metrics::Key> {
+        let labels: Vec<&metrics::Label> = key.labels().collect();
+        if !self.collector.config.sort_labels || labels.windows(2).all(|pair| pair[0] <= pair[1]) {
+            return key;
+        }
+
+        let mut labels: Vec<metrics::Label> = labels.into_iter().cloned().collect();
+        labels.sort();
+        Cow::Owned(metrics::Key::from_parts(key.name_shared(), labels))
+    }
+
+    /// Applies [Builder::with_key_rewrite] and [Builder::with_name_sanitizer] to the name of a described metric,
+    /// returns None if it was rejected
+    fn prepare_key_name(&self, key: metrics::KeyName) -> Option<metrics::KeyName> {
+        let key = match &self.collector.config.key_rewrite {
+            Some(rewrite) => rewrite(&metrics::Key::from_name(key))?.name_shared(),
+            None => key,
+        };
+        match &self.collector.config.name_sanitizer {
+            Some(sanitizer) => sanitizer.name(key.as_str()).map(|name| name.into_owned().into()),
+            None => Some(key),
+        }
+    }
+
+    /// Adds the [Builder::with_global_label] labels the key doesn't already have
+    fn global_key<'a>(&self, key: &'a metrics::Key) -> Cow<'a, metrics::Key> {
+        let global_labels: Vec<metrics::Label> = self
+            .collector
+            .config
+            .global_labels
+            .iter()
+            .filter(|global| !key.labels().any(|label| label.key() == global.key()))
+            .cloned()
+            .collect();
+
+        match global_labels.is_empty() {
+            true => Cow::Borrowed(key),
+            false => Cow::Owned(key.with_extra_labels(global_labels)),
         }
+    }
+
+    /// Applies the [Builder::max_tenants] guardrail to a key with a tenant label
+    fn tenant_key<'a>(&self, key: Cow<'a, metrics::Key>) -> Cow<'a, metrics::Key> {
+        let Some(TenantConfig {
+            key: TenantKey::Label(name),
+            ..
+        }) = &self.collector.config.tenant
+        else {
+            return key;
+        };
+
+        let Some(tenant) = key.labels().find(|label| label.key() == &**name) else {
+            return key;
+        };
 
-        // Neither the label nor the counter exists
-        let value = Arc::new(AtomicU64::new(0));
-        let mut label_info = BTreeMap::new();
-        label_info.insert(key.clone(), MetricInfo::Counter(CounterInfo { value: value.clone() }));
-        state.info_tree.insert(labels, label_info);
+        if self.collector.admit_tenant(tenant.value()) {
+            return key;
+        }
 
-        metrics::Counter::from_arc(value)
+        let labels: Vec<metrics::Label> = key
+            .labels()
+            .map(|label| match label.key() == &**name {
+                true => metrics::Label::new(name.clone(), TENANT_OVERFLOW),
+                false => label.clone(),
+            })
+            .collect();
+        Cow::Owned(metrics::Key::from_parts(key.name_shared(), labels))
     }
 
-    #[allow(clippy::mutable_key_type)] // metrics::Key has interior mutability
-    fn register_gauge(&self, key: &metrics::Key, _metadata: &metrics::Metadata) -> metrics::Gauge {
-        // Build our own copy of the labels before aquiring the mutex
-        let labels: Vec<metrics::Label> = key.labels().cloned().collect();
+    fn counter(&self, key: &metrics::Key) -> metrics::Counter {
+        if !self.check_dimensions("counter", key) {
+            return metrics::Counter::noop();
+        }
 
-        if self.collector.config.default_dimensions.len() + labels.len() > MAX_DIMENSIONS {
-            error!(
-                "Unable to register counter {key} as a gauge as it has more than {MAX_DIMENSIONS} dimensions/labels"
-            );
+        let registered = self.register(key, || {
+            MetricInfo::Counter(CounterInfo {
+                value: Arc::new(AtomicU64::new(0)),
+            })
+        });
+        match registered {
+            Registered::Counter(value) => metrics::Counter::from_arc(value),
+            Registered::Gauge(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register counter {key} as it was already registered as a gauge"
+                ));
+                metrics::Counter::noop()
+            }
+            Registered::Histogram(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register counter {key} as it was already registered as a histogram"
+                ));
+                metrics::Counter::noop()
+            }
+        }
+    }
+
+    fn gauge(&self, key: &metrics::Key) -> metrics::Gauge {
+        if !self.check_dimensions("gauge", key) {
             return metrics::Gauge::noop();
         }
 
-        let mut state = self.collector.state.lock().unwrap();
+        let registered = self.register(key, || {
+            MetricInfo::Gauge(GaugeInfo {
+                value: Arc::new(AtomicU64::new(0)),
+                last_emitted: None,
+            })
+        });
+        match registered {
+            Registered::Gauge(value) => metrics::Gauge::from_arc(value),
+            Registered::Counter(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register gauge {key} as it was already registered as a counter"
+                ));
+                metrics::Gauge::noop()
+            }
+            Registered::Histogram(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register gauge {key} as it was already registered as a histogram"
+                ));
+                metrics::Gauge::noop()
+            }
+        }
+    }
 
-        // Does this metric already exist?
-        if let Some(label_info) = state.info_tree.get_mut(&labels) {
-            if let Some(info) = label_info.get(key) {
-                match info {
-                    MetricInfo::Gauge(info) => {
-                        return metrics::Gauge::from_arc(info.value.clone());
-                    }
-                    MetricInfo::Counter(_) => {
-                        error!("Unable to register gauge {key} as it was already registered as a counter");
-                        return metrics::Gauge::noop();
-                    }
-                    MetricInfo::Histogram(_) => {
-                        error!("Unable to register gauge {key} as it was already registered as a histogram");
-                        return metrics::Gauge::noop();
-                    }
-                }
-            } else {
-                // Label exists, gauge does not
-                let value = Arc::new(AtomicU64::new(0));
-                label_info.insert(key.clone(), MetricInfo::Counter(CounterInfo { value: value.clone() }));
+    fn histogram(&self, key: &metrics::Key) -> metrics::Histogram {
+        match self.histogram_handle(key) {
+            Some(handle) => metrics::Histogram::from_arc(handle),
+            None => metrics::Histogram::noop(),
+        }
+    }
+
+    fn histogram_handle(&self, key: &metrics::Key) -> Option<Arc<HistogramHandle>> {
+        if !self.check_dimensions("histogram", key) {
+            return None;
+        }
 
-                return metrics::Gauge::from_arc(value);
+        let registered = self.register(key, || {
+            MetricInfo::Histogram(HistogramInfo::new(&self.collector.config, key.name()))
+        });
+        match registered {
+            Registered::Histogram(handle) => Some(handle),
+            Registered::Counter(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register histogram {key} as it was already registered as a counter"
+                ));
+                None
             }
+            Registered::Gauge(_) => {
+                self.collector.handle_error(format_args!(
+                    "Unable to register histogram {key} as it was already registered as a gauge"
+                ));
+                None
+            }
+        }
+    }
+
+    /// Whether the default dimensions and labels fit within the Embedded Metric Format limit, reporting an error if not
+    fn check_dimensions(&self, kind: &str, key: &metrics::Key) -> bool {
+        let fits = self.collector.config.default_dimensions.len() + key.labels().len() <= MAX_DIMENSIONS;
+        if !fits {
+            self.collector.handle_error(format_args!(
+                "Unable to register {kind} {key} as it has more than {MAX_DIMENSIONS} dimensions/labels"
+            ));
+        }
+        fits
+    }
+
+    /// The handle registered for a key, or else register the metric from `info`
+    /// * New metrics are merged into the collector's state the next time it's locked, so registering never waits on
+    ///   a flush
+    fn register(&self, key: &metrics::Key, info: impl FnOnce() -> MetricInfo) -> Registered {
+        self.collector.registry(self.scope).get_or_register(key, || {
+            let info = info();
+            let registered = match &info {
+                MetricInfo::Counter(counter) => Registered::Counter(counter.value.clone()),
+                MetricInfo::Gauge(gauge) => Registered::Gauge(gauge.value.clone()),
+                MetricInfo::Histogram(histogram) => Registered::Histogram(histogram.handle.clone()),
+            };
+            let registration = Registration {
+                labels: key.labels().cloned().collect(),
+                key: key.clone(),
+                info,
+            };
+            (registered, registration)
+        })
+    }
+}
+
+impl metrics::Recorder for Recorder<'_> {
+    fn describe_counter(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        let Some(key) = self.prepare_key_name(key) else {
+            return;
+        };
+        if let Some(mirror) = &self.collector.config.mirror_recorder {
+            mirror.describe_counter(key.clone(), unit, description);
+        }
+        self.collector.update_unit(key, unit)
+    }
+
+    fn describe_gauge(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        let Some(key) = self.prepare_key_name(key) else {
+            return;
+        };
+        if let Some(mirror) = &self.collector.config.mirror_recorder {
+            mirror.describe_gauge(key.clone(), unit, description);
         }
+        self.collector.update_unit(key, unit)
+    }
 
-        // Neither the label nor the gauge exists
-        let value = Arc::new(AtomicU64::new(0));
-        let mut label_info = BTreeMap::new();
-        label_info.insert(key.clone(), MetricInfo::Gauge(GaugeInfo { value: value.clone() }));
-        state.info_tree.insert(labels, label_info);
+    fn describe_histogram(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        let Some(key) = self.prepare_key_name(key) else {
+            return;
+        };
+        if let Some(mirror) = &self.collector.config.mirror_recorder {
+            mirror.describe_histogram(key.clone(), unit, description);
+        }
+        self.collector.update_unit(key, unit)
+    }
 
-        metrics::Gauge::from_arc(value)
+    fn register_counter(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Counter {
+        let Some(key) = self.prepare_key(key) else {
+            return metrics::Counter::noop();
+        };
+        let counter = self.counter(&key);
+
+        match &self.collector.config.mirror_recorder {
+            Some(mirror) => metrics::Counter::from_arc(Arc::new(MirroredCounter(
+                counter,
+                mirror.register_counter(&key, metadata),
+            ))),
+            None => counter,
+        }
     }
 
-    #[allow(clippy::mutable_key_type)] // metrics::Key has interior mutability
-    fn register_histogram(&self, key: &metrics::Key, _metadata: &metrics::Metadata) -> metrics::Histogram {
-        // Build our own copy of the labels before aquiring the mutex
-        let labels: Vec<metrics::Label> = key.labels().cloned().collect();
+    fn register_gauge(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Gauge {
+        let Some(key) = self.prepare_key(key) else {
+            return metrics::Gauge::noop();
+        };
+        let gauge = self.gauge(&key);
+
+        match &self.collector.config.mirror_recorder {
+            Some(mirror) => {
+                metrics::Gauge::from_arc(Arc::new(MirroredGauge(gauge, mirror.register_gauge(&key, metadata))))
+            }
+            None => gauge,
+        }
+    }
 
-        if self.collector.config.default_dimensions.len() + labels.len() > MAX_DIMENSIONS {
-            error!("Unable to register histogram {key} as it has more than {MAX_DIMENSIONS} dimensions/labels");
+    fn register_histogram(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Histogram {
+        let Some(key) = self.prepare_key(key) else {
             return metrics::Histogram::noop();
+        };
+        let histogram = self.histogram(&key);
+
+        match &self.collector.config.mirror_recorder {
+            Some(mirror) => metrics::Histogram::from_arc(Arc::new(MirroredHistogram(
+                histogram,
+                mirror.register_histogram(&key, metadata),
+            ))),
+            None => histogram,
         }
+    }
+}
 
-        let mut state = self.collector.state.lock().unwrap();
+/// Collector the [SharedRecorder] forwards to, set by [Builder::init_arc](super::Builder::init_arc) and cleared by
+/// [Collector::shutdown]
+static SHARED_COLLECTOR: RwLock<Option<Arc<Collector>>> = RwLock::new(None);
 
-        // Does this metric already exist?
-        if let Some(label_info) = state.info_tree.get_mut(&labels) {
-            if let Some(info) = label_info.get(key) {
-                match info {
-                    MetricInfo::Histogram(info) => {
-                        let histogram = Arc::new(HistogramHandle {
-                            sender: info.sender.clone(),
-                        });
-                        return metrics::Histogram::from_arc(histogram);
-                    }
-                    MetricInfo::Counter(_) => {
-                        error!("Unable to register histogram {key} as it was already registered as a counter");
-                        return metrics::Histogram::noop();
-                    }
-                    MetricInfo::Gauge(_) => {
-                        error!("Unable to register histogram {key} as it was already registered as a gauge");
-                        return metrics::Histogram::noop();
-                    }
-                }
-            } else {
-                // Label exists, histogram does not
-                let (sender, receiver) = mpsc::sync_channel(MAX_HISTOGRAM_VALUES);
-                let histogram = Arc::new(HistogramHandle { sender: sender.clone() });
-                label_info.insert(key.clone(), MetricInfo::Histogram(HistogramInfo { sender, receiver }));
+/// Whether the [SharedRecorder] has been installed as the global recorder, which can only happen once
+static SHARED_RECORDER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Global [metrics::Recorder] forwarding to the collector in [SHARED_COLLECTOR], metrics registered while it's empty
+/// are discarded
+pub(crate) struct SharedRecorder;
+
+impl SharedRecorder {
+    /// Install `collector` behind the global recorder, fails if another collector is installed
+    pub(crate) fn install(collector: Arc<Collector>) -> Result<(), crate::Error> {
+        let mut shared = SHARED_COLLECTOR.write().unwrap_or_else(PoisonError::into_inner);
+        if shared.is_some() {
+            return Err("a collector is already installed, call Collector::shutdown() first".into());
+        }
+        if !SHARED_RECORDER_INSTALLED.load(Ordering::Acquire) {
+            metrics::set_global_recorder(SharedRecorder).map_err(|e| e.to_string())?;
+            SHARED_RECORDER_INSTALLED.store(true, Ordering::Release);
+        }
+        *shared = Some(collector);
+        Ok(())
+    }
 
-                return metrics::Histogram::from_arc(histogram);
+    /// Detach `collector` if it's the one installed, returns whether it was
+    fn uninstall(collector: &Collector) -> bool {
+        let mut shared = SHARED_COLLECTOR.write().unwrap_or_else(PoisonError::into_inner);
+        match &*shared {
+            Some(installed) if std::ptr::eq(Arc::as_ptr(installed), collector) => {
+                *shared = None;
+                true
             }
+            _ => false,
+        }
+    }
+
+    fn with<T>(f: impl FnOnce(Recorder) -> T, noop: impl FnOnce() -> T) -> T {
+        match &*SHARED_COLLECTOR.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(collector) => f(Recorder::from(&**collector)),
+            None => noop(),
         }
+    }
+}
+
+impl metrics::Recorder for SharedRecorder {
+    fn describe_counter(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        Self::with(|recorder| recorder.describe_counter(key, unit, description), || ())
+    }
+
+    fn describe_gauge(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        Self::with(|recorder| recorder.describe_gauge(key, unit, description), || ())
+    }
+
+    fn describe_histogram(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        Self::with(|recorder| recorder.describe_histogram(key, unit, description), || ())
+    }
+
+    fn register_counter(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Counter {
+        Self::with(
+            |recorder| recorder.register_counter(key, metadata),
+            metrics::Counter::noop,
+        )
+    }
 
-        // Neither the label nor the gauge exists
-        let (sender, receiver) = mpsc::sync_channel(MAX_HISTOGRAM_VALUES);
-        let histogram = Arc::new(HistogramHandle { sender: sender.clone() });
-        let mut label_info = BTreeMap::new();
-        label_info.insert(key.clone(), MetricInfo::Histogram(HistogramInfo { sender, receiver }));
-        state.info_tree.insert(labels, label_info);
+    fn register_gauge(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Gauge {
+        Self::with(|recorder| recorder.register_gauge(key, metadata), metrics::Gauge::noop)
+    }
 
-        metrics::Histogram::from_arc(histogram)
+    fn register_histogram(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Histogram {
+        Self::with(
+            |recorder| recorder.register_histogram(key, metadata),
+            metrics::Histogram::noop,
+        )
     }
 }
@@ -0,0 +1,93 @@
+//! # Compat
+//!
+//! Macros with the semantics of the `metrics` facade before 0.22, forwarding to the current handle based API, for
+//! upgrading large codebases incrementally
+//!
+//! Importing from this module in place of `metrics` keeps old call sites compiling, the old names that are still
+//! macros in `metrics` with different arguments ([counter], [gauge] and [histogram]) are only available from here
+//!
+//! *this module requires the `compat` feature flag*
+//!
+//! # Example
+//! ```
+//! use metrics_cloudwatch_embedded::compat::{counter, gauge, histogram, increment_counter};
+//!
+//! let metrics = metrics_cloudwatch_embedded::Builder::new()
+//!      .cloudwatch_namespace("MyApplication")
+//!      .init()
+//!      .unwrap();
+//!
+//! increment_counter!("requests", "Method" => "Default");
+//! counter!("bytes", 512, "Method" => "Default");
+//! gauge!("connections", 10.0);
+//! histogram!("latency", 12.5, "Method" => "Default");
+//! ```
+
+/// Increments a counter by one, `increment_counter!(name, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_increment_counter {
+    ($name:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::counter!($name $(, $($labels)+)?).increment(1)
+    };
+}
+
+/// Increments a counter, `counter!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_counter {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::counter!($name $(, $($labels)+)?).increment($value)
+    };
+}
+
+/// Sets a counter to an absolute value, `absolute_counter!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_absolute_counter {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::counter!($name $(, $($labels)+)?).absolute($value)
+    };
+}
+
+/// Sets a gauge, `gauge!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_gauge {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::gauge!($name $(, $($labels)+)?).set($value)
+    };
+}
+
+/// Increments a gauge, `increment_gauge!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_increment_gauge {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::gauge!($name $(, $($labels)+)?).increment($value)
+    };
+}
+
+/// Decrements a gauge, `decrement_gauge!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_decrement_gauge {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::gauge!($name $(, $($labels)+)?).decrement($value)
+    };
+}
+
+/// Records a histogram value, `histogram!(name, value, labels...)`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_histogram {
+    ($name:expr, $value:expr $(, $($labels:tt)+)?) => {
+        $crate::__metrics::histogram!($name $(, $($labels)+)?).record($value)
+    };
+}
+
+pub use {
+    __compat_absolute_counter as absolute_counter, __compat_counter as counter,
+    __compat_decrement_gauge as decrement_gauge, __compat_gauge as gauge, __compat_histogram as histogram,
+    __compat_increment_counter as increment_counter, __compat_increment_gauge as increment_gauge,
+};
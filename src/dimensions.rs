@@ -0,0 +1,89 @@
+//! # Dimensions
+//!
+//! Strongly-typed label sets, usually implemented via `#[derive(Dimensions)]`
+
+use metrics::{Label, SharedString};
+
+/// A set of [labels](metrics::Label) defined by a type rather than spelled out at each call site
+///
+/// `#[derive(Dimensions)]` (*requires the `derive` feature flag*) implements this along with
+/// [metrics::IntoLabels] so the type can be passed directly to the [metrics] macros
+/// * Structs emit one dimension per named field, named after the field in PascalCase, with values via [DimensionValue]
+/// * Enums of unit variants emit a single dimension named after the enum, with the variant name as the value, and
+///   also implement [DimensionValue] for use as a struct field
+/// * `#[dimension(name = "...")]` on a field, enum or variant overrides the name or value
+/// * Deriving for a struct with more than 30 fields or duplicate dimension names fails to compile
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # {
+/// use metrics_cloudwatch_embedded::Dimensions;
+///
+/// #[derive(Dimensions)]
+/// enum Method {
+///     Get,
+///     Post,
+/// }
+///
+/// #[derive(Dimensions)]
+/// struct RequestDimensions {
+///     method: Method,
+///     #[dimension(name = "API")]
+///     api: &'static str,
+/// }
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests", &RequestDimensions { method: Method::Get, api: "orders" }).increment(1);
+/// # }
+/// ```
+pub trait Dimensions {
+    fn labels(&self) -> Vec<Label>;
+}
+
+/// Conversion of a [Dimensions] struct field into a dimension value
+pub trait DimensionValue {
+    fn dimension_value(&self) -> SharedString;
+}
+
+impl DimensionValue for &'static str {
+    fn dimension_value(&self) -> SharedString {
+        SharedString::const_str(self)
+    }
+}
+
+impl DimensionValue for String {
+    fn dimension_value(&self) -> SharedString {
+        SharedString::from_owned(self.clone())
+    }
+}
+
+impl DimensionValue for SharedString {
+    fn dimension_value(&self) -> SharedString {
+        self.clone()
+    }
+}
+
+impl DimensionValue for bool {
+    fn dimension_value(&self) -> SharedString {
+        SharedString::const_str(if *self { "true" } else { "false" })
+    }
+}
+
+macro_rules! display_dimension_value {
+    ($($ty:ty),*) => {
+        $(
+            impl DimensionValue for $ty {
+                fn dimension_value(&self) -> SharedString {
+                    SharedString::from_owned(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+display_dimension_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
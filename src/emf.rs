@@ -4,9 +4,11 @@
 //!
 //! <https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html>
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::value::Value;
 use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Serialize)]
 pub struct EmbeddedMetrics<'a> {
@@ -24,18 +26,18 @@ pub struct EmbeddedMetrics<'a> {
 pub struct EmbeddedMetricsAws<'a> {
     #[serde(rename = "Timestamp")]
     pub timestamp: u64,
-    // This crate never uses more than one namespace in a metrics document
+    // The first namespace holds the dimensions, any others share them, see EmbeddedMetrics::namespace_mut
     #[serde(rename = "CloudWatchMetrics")]
-    pub cloudwatch_metrics: [EmbeddedNamespace<'a>; 1],
+    pub cloudwatch_metrics: Vec<EmbeddedNamespace<'a>>,
 }
 
 #[derive(Serialize)]
 pub struct EmbeddedNamespace<'a> {
     #[serde(rename = "Namespace")]
     pub namespace: &'a str,
-    // This create builds a single dimension set with all dimensions
+    // The first dimension set holds all dimensions, any others are rollups via Builder::with_dimension_rollup
     #[serde(rename = "Dimensions")]
-    pub dimensions: [Vec<&'a str>; 1],
+    pub dimensions: Vec<Vec<&'a str>>,
     #[serde(rename = "Metrics")]
     pub metrics: Vec<EmbeddedMetric<'a>>,
 }
@@ -49,6 +51,277 @@ pub struct EmbeddedMetric<'a> {
     pub unit: Option<&'a str>,
 }
 
+impl<'a> EmbeddedMetrics<'a> {
+    /// The directive for a namespace, adding one sharing the first directive's dimensions if the document doesn't
+    /// have one yet, so metrics for several namespaces can be emitted in a single document
+    pub fn namespace_mut(&mut self, namespace: &'a str) -> &mut EmbeddedNamespace<'a> {
+        let index = match self
+            .aws
+            .cloudwatch_metrics
+            .iter()
+            .position(|n| n.namespace == namespace)
+        {
+            Some(index) => index,
+            None => {
+                let dimensions = self.aws.cloudwatch_metrics[0].dimensions.clone();
+                self.aws.cloudwatch_metrics.push(EmbeddedNamespace {
+                    namespace,
+                    dimensions,
+                    metrics: Vec::new(),
+                });
+                self.aws.cloudwatch_metrics.len() - 1
+            }
+        };
+        &mut self.aws.cloudwatch_metrics[index]
+    }
+
+    /// Number of metrics across all namespaces
+    pub fn metrics_len(&self) -> usize {
+        self.aws.cloudwatch_metrics.iter().map(|n| n.metrics.len()).sum()
+    }
+
+    /// Remove all metrics and values, along with any namespaces beyond the first
+    pub fn clear_metrics(&mut self) {
+        self.aws.cloudwatch_metrics.truncate(1);
+        self.aws.cloudwatch_metrics[0].metrics.clear();
+        self.values.clear();
+    }
+
+    /// Write the document followed by a newline, splicing in pre-serialized dimensions
+    /// * Produces the same output as [serde_json::to_writer] with the dimensions inserted into the document
+    /// * The document itself should not contain any dimensions
+    pub fn write_with_dimensions(&self, mut writer: impl Write, dimensions: &DimensionFragment) -> std::io::Result<()> {
+        write!(
+            writer,
+            r#"{{"_aws":{{"Timestamp":{},"CloudWatchMetrics":["#,
+            self.aws.timestamp
+        )?;
+        for (i, namespace) in self.aws.cloudwatch_metrics.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(br#"{"Namespace":"#)?;
+            serde_json::to_writer(&mut writer, namespace.namespace)?;
+            write!(writer, r#","Dimensions":[[{}]],"Metrics":"#, dimensions.names)?;
+            serde_json::to_writer(&mut writer, &namespace.metrics)?;
+            writer.write_all(b"}")?;
+        }
+        writer.write_all(b"]}")?;
+
+        if !dimensions.values.is_empty() {
+            write!(writer, ",{}", dimensions.values)?;
+        }
+        for (key, value) in self.properties.iter() {
+            writer.write_all(b",")?;
+            serde_json::to_writer(&mut writer, key)?;
+            writer.write_all(b":")?;
+            serde_json::to_writer(&mut writer, value)?;
+        }
+        for (key, value) in self.values.iter() {
+            writer.write_all(b",")?;
+            serde_json::to_writer(&mut writer, key)?;
+            writer.write_all(b":")?;
+            serde_json::to_writer(&mut writer, value)?;
+        }
+
+        writer.write_all(b"}\n")
+    }
+}
+
+/// Owned embedded metrics document parsed back from output, e.g. to assert on typed fields in tests instead of
+/// comparing JSON strings
+/// * Top level fields are split by the directives into dimensions (string values named in a dimension set), metric
+///   values (named in a directive's metrics) and properties (everything else)
+/// * Serializes back to the same output the collector writes
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::ParsedDocument;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests", "Method" => "Default").increment(1);
+///
+/// let output = metrics.flush_to_string().unwrap();
+/// let document: ParsedDocument = output.parse().unwrap();
+/// assert_eq!(document.aws.cloudwatch_metrics[0].namespace, "MyApplication");
+/// assert_eq!(document.dimensions["Method"], "Default");
+/// assert_eq!(document.values["requests"], 1);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawDocument")]
+pub struct ParsedDocument {
+    #[serde(rename = "_aws")]
+    pub aws: ParsedAws,
+    #[serde(flatten)]
+    pub dimensions: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub properties: BTreeMap<String, Value>,
+    #[serde(flatten)]
+    pub values: BTreeMap<String, Value>,
+}
+
+/// `_aws` metadata of a [ParsedDocument]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedAws {
+    #[serde(rename = "Timestamp")]
+    pub timestamp: u64,
+    #[serde(rename = "CloudWatchMetrics")]
+    pub cloudwatch_metrics: Vec<ParsedDirective>,
+}
+
+/// Metric directive of a [ParsedDocument]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedDirective {
+    #[serde(rename = "Namespace")]
+    pub namespace: String,
+    #[serde(rename = "Dimensions")]
+    pub dimensions: Vec<Vec<String>>,
+    #[serde(rename = "Metrics")]
+    pub metrics: Vec<ParsedMetric>,
+}
+
+/// Metric definition within a [ParsedDirective]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedMetric {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Unit")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+/// [ParsedDocument] before its top level fields are split by the directives
+#[derive(Deserialize)]
+struct RawDocument {
+    #[serde(rename = "_aws")]
+    aws: ParsedAws,
+    #[serde(flatten)]
+    fields: BTreeMap<String, Value>,
+}
+
+impl From<RawDocument> for ParsedDocument {
+    fn from(raw: RawDocument) -> Self {
+        let mut document = ParsedDocument {
+            aws: raw.aws,
+            dimensions: BTreeMap::new(),
+            properties: BTreeMap::new(),
+            values: BTreeMap::new(),
+        };
+        let directives = &document.aws.cloudwatch_metrics;
+        let is_dimension = |name: &str| {
+            directives
+                .iter()
+                .flat_map(|directive| directive.dimensions.iter().flatten())
+                .any(|dimension| dimension == name)
+        };
+        let is_metric = |name: &str| {
+            directives
+                .iter()
+                .flat_map(|directive| &directive.metrics)
+                .any(|metric| metric.name == name)
+        };
+
+        let mut dimensions = BTreeMap::new();
+        let mut properties = BTreeMap::new();
+        let mut values = BTreeMap::new();
+        for (name, value) in raw.fields {
+            match value {
+                Value::String(value) if is_dimension(&name) => {
+                    dimensions.insert(name, value);
+                }
+                value if is_metric(&name) => {
+                    values.insert(name, value);
+                }
+                value => {
+                    properties.insert(name, value);
+                }
+            }
+        }
+        document.dimensions = dimensions;
+        document.properties = properties;
+        document.values = values;
+        document
+    }
+}
+
+impl std::str::FromStr for ParsedDocument {
+    type Err = serde_json::Error;
+
+    /// Parse a single document, surrounding whitespace including the trailing newline is ignored
+    fn from_str(document: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(document.trim())
+    }
+}
+
+/// Dimensions of a label set serialized once, for use with [EmbeddedMetrics::write_with_dimensions]
+pub struct DimensionFragment {
+    /// Comma separated dimension names in order for the `Dimensions` array
+    names: String,
+    /// Comma separated `"name":"value"` pairs sorted by name
+    values: String,
+}
+
+impl DimensionFragment {
+    pub fn new<'a>(dimensions: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut names = Vec::new();
+        let mut values = BTreeMap::new();
+        for (name, value) in dimensions {
+            names.push(serde_json::to_string(name).expect("strings always serialize"));
+            values.insert(name, value);
+        }
+
+        let values: Vec<String> = values
+            .into_iter()
+            .map(|(name, value)| {
+                let name = serde_json::to_string(name).expect("strings always serialize");
+                let value = serde_json::to_string(value).expect("strings always serialize");
+                format!("{name}:{value}")
+            })
+            .collect();
+
+        Self {
+            names: names.join(","),
+            values: values.join(","),
+        }
+    }
+}
+
+/// Current time in milliseconds since the unix epoch, as used for `_aws.Timestamp`
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+/// Format milliseconds since the unix epoch as an ISO-8601 UTC timestamp, e.g. `2023-06-25T01:45:45.423Z`
+pub fn iso8601(timestamp: u64) -> String {
+    let millis = timestamp % 1000;
+    let seconds = timestamp / 1000;
+    let (hour, minute, second) = (seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = seconds / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")
+}
+
 /// Convert a metrics::Unit into the cloudwatch string
 ///
 /// <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_MetricDatum.html>
@@ -84,9 +357,9 @@ mod tests {
         let mut metrics_test = EmbeddedMetrics {
             aws: EmbeddedMetricsAws {
                 timestamp: 0,
-                cloudwatch_metrics: [EmbeddedNamespace {
+                cloudwatch_metrics: vec![EmbeddedNamespace {
                     namespace: "GameServerMetrics",
-                    dimensions: [vec!["Address", "Port"]],
+                    dimensions: vec![vec!["Address", "Port"]],
                     metrics: Vec::new(),
                 }],
             },
@@ -121,9 +394,85 @@ mod tests {
         });
         metrics_test.values.insert("MemoryUsage", json!(10 * 1024));
 
+        let mut fragment_output = Vec::new();
+        let fragment = DimensionFragment::new([("Address", "10.172.207.225"), ("Port", "7779")]);
+        let dimensions = std::mem::take(&mut metrics_test.dimensions);
+        let names = std::mem::take(&mut metrics_test.aws.cloudwatch_metrics[0].dimensions[0]);
+        metrics_test
+            .write_with_dimensions(&mut fragment_output, &fragment)
+            .unwrap();
+        metrics_test.dimensions = dimensions;
+        metrics_test.aws.cloudwatch_metrics[0].dimensions[0] = names;
+
+        assert_eq!(
+            std::str::from_utf8(&fragment_output).unwrap(),
+            serde_json::to_string(&metrics_test).unwrap() + "\n"
+        );
+
         assert_eq!(
             serde_json::to_string(&metrics_test).unwrap(),
             r#"{"_aws":{"Timestamp":1687394207903,"CloudWatchMetrics":[{"Namespace":"GameServerMetrics","Dimensions":[["Address","Port"]],"Metrics":[{"Name":"FrameTime","Unit":"Milliseconds"},{"Name":"CpuUsage","Unit":"Percent"},{"Name":"MemoryUsage","Unit":"Kilobytes"}]}]},"Address":"10.172.207.225","Port":"7779","CpuUsage":5.5,"FrameTime":10.0,"MemoryUsage":10240}"#
         );
     }
+
+    #[test]
+    fn iso8601_timestamps() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(951782400000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(iso8601(1687657545423), "2023-06-25T01:45:45.423Z");
+    }
+
+    #[test]
+    fn multiple_namespaces() {
+        let mut metrics_test = EmbeddedMetrics {
+            aws: EmbeddedMetricsAws {
+                timestamp: 0,
+                cloudwatch_metrics: vec![EmbeddedNamespace {
+                    namespace: "MyApplication",
+                    dimensions: vec![vec!["Method"]],
+                    metrics: Vec::new(),
+                }],
+            },
+            dimensions: BTreeMap::from([("Method", "Get")]),
+            properties: BTreeMap::new(),
+            values: BTreeMap::new(),
+        };
+
+        for (namespace, name) in [("MyApplication", "requests"), ("MyApplication/Database", "queries")] {
+            metrics_test
+                .namespace_mut(namespace)
+                .metrics
+                .push(EmbeddedMetric { name, unit: None });
+            metrics_test.values.insert(name, json!(1));
+        }
+        metrics_test
+            .namespace_mut("MyApplication/Database")
+            .metrics
+            .push(EmbeddedMetric {
+                name: "connections",
+                unit: None,
+            });
+        metrics_test.values.insert("connections", json!(2));
+        assert_eq!(metrics_test.metrics_len(), 3);
+
+        let mut fragment_output = Vec::new();
+        let fragment = DimensionFragment::new([("Method", "Get")]);
+        let dimensions = std::mem::take(&mut metrics_test.dimensions);
+        metrics_test
+            .write_with_dimensions(&mut fragment_output, &fragment)
+            .unwrap();
+        metrics_test.dimensions = dimensions;
+
+        let expected = r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"MyApplication","Dimensions":[["Method"]],"Metrics":[{"Name":"requests"}]},{"Namespace":"MyApplication/Database","Dimensions":[["Method"]],"Metrics":[{"Name":"queries"},{"Name":"connections"}]}]},"Method":"Get","connections":2,"queries":1,"requests":1}"#;
+        assert_eq!(serde_json::to_string(&metrics_test).unwrap(), expected);
+        assert_eq!(
+            std::str::from_utf8(&fragment_output).unwrap(),
+            expected.to_owned() + "\n"
+        );
+
+        metrics_test.clear_metrics();
+        assert_eq!(metrics_test.aws.cloudwatch_metrics.len(), 1);
+        assert_eq!(metrics_test.metrics_len(), 0);
+        assert!(metrics_test.values.is_empty());
+    }
 }
@@ -0,0 +1,218 @@
+//! # Firehose
+//!
+//! Shipping documents through Amazon Data Firehose rather than stdout
+
+use super::sink::Sink;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Records per `PutRecordBatch` request
+const MAX_BATCH_RECORDS: usize = 500;
+
+/// Bytes per `PutRecordBatch` request
+const MAX_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/// Bytes per record
+const MAX_RECORD_BYTES: usize = 1000 * 1024;
+
+/// Submits `PutRecordBatch` requests for a [FirehoseSink], e.g. by wrapping an `aws_sdk_firehose::Client`
+/// * Implemented for closures
+pub trait PutRecordBatch: Send + Sync {
+    /// Submit records to the delivery stream, returning the indices of any records that failed
+    /// (the entries of a partially failed response with an `ErrorCode`)
+    /// * Returning an error fails the whole request
+    fn put_record_batch(&self, records: &[Vec<u8>]) -> io::Result<Vec<usize>>;
+}
+
+impl<F: Fn(&[Vec<u8>]) -> io::Result<Vec<usize>> + Send + Sync> PutRecordBatch for F {
+    fn put_record_batch(&self, records: &[Vec<u8>]) -> io::Result<Vec<usize>> {
+        self(records)
+    }
+}
+
+/// [Sink] batching documents into `PutRecordBatch` requests, for delivery streams shipping embedded metrics to
+/// CloudWatch Logs
+/// * Each document is a record, records are submitted once a batch reaches the API limits (500 records or 4 MiB)
+///   and at the end of each flush
+/// * Failed requests and failed records are kept and resubmitted by a later emit or flush once their backoff has
+///   elapsed, 3 times backing off from 100ms up to 1s by default, records still failing are then discarded and
+///   reported as an error
+/// * Never sleeps, so the flushing thread (e.g. a Lambda response or an async executor) isn't blocked by an outage,
+///   flush returns an error while records await a retry
+/// * Only submits records, wrap your own client (e.g. an `aws_sdk_firehose::Client`) via [PutRecordBatch]
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::FirehoseSink;
+///
+/// let sink = FirehoseSink::new(|records: &[Vec<u8>]| {
+///     // Submit via aws_sdk_firehose::Client::put_record_batch
+///     println!("submitting {} records", records.len());
+///     Ok(Vec::new())
+/// });
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyService")
+///      .with_sink(sink)
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush_to_sink().unwrap();
+/// ```
+pub struct FirehoseSink<C> {
+    client: C,
+    batch: Mutex<Batch>,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+#[derive(Default)]
+struct Batch {
+    records: Vec<Vec<u8>>,
+    bytes: usize,
+    retries: Vec<Retry>,
+}
+
+/// Records of a failed request awaiting resubmission, within the limits of one request
+struct Retry {
+    records: Vec<Vec<u8>>,
+    retries: u32,
+    at: Instant,
+}
+
+impl<C: PutRecordBatch> FirehoseSink<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            batch: Mutex::new(Batch::default()),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Retries per record after the first attempt fails
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for each retry up to max
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Batch> {
+        self.batch.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take the requests awaiting a retry whose backoff has elapsed
+    fn take_due_retries(batch: &mut Batch) -> Vec<Retry> {
+        let now = Instant::now();
+        let (due, waiting) = std::mem::take(&mut batch.retries)
+            .into_iter()
+            .partition(|retry| retry.at <= now);
+        batch.retries = waiting;
+        due
+    }
+
+    /// Submit records, keeping the request or just its failed records for a retry once the backoff has elapsed
+    /// * Only fails once records are discarded after the last retry
+    fn submit(&self, records: Vec<Vec<u8>>, retries: u32) -> io::Result<()> {
+        let (records, error) = match self.client.put_record_batch(&records) {
+            Ok(failed) if failed.is_empty() => return Ok(()),
+            Ok(failed) => {
+                let records: Vec<_> = failed
+                    .into_iter()
+                    .filter_map(|index| records.get(index).cloned())
+                    .collect();
+                let error = io::Error::new(io::ErrorKind::Other, format!("{} records failed", records.len()));
+                (records, error)
+            }
+            Err(e) => (records, e),
+        };
+
+        if retries == self.max_retries {
+            return Err(io::Error::new(
+                error.kind(),
+                format!("discarding {} records after {retries} retries: {error}", records.len()),
+            ));
+        }
+        let backoff = (self.initial_backoff * 2u32.saturating_pow(retries)).min(self.max_backoff);
+        self.lock().retries.push(Retry {
+            records,
+            retries: retries + 1,
+            at: Instant::now() + backoff,
+        });
+        Ok(())
+    }
+}
+
+impl<C: PutRecordBatch> Sink for FirehoseSink<C> {
+    fn emit(&self, document: &[u8]) -> io::Result<()> {
+        if document.len() > MAX_RECORD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "document of {} bytes exceeds the Firehose limit of {MAX_RECORD_BYTES} bytes per record",
+                    document.len()
+                ),
+            ));
+        }
+
+        // Submit outside the lock so emitting doesn't wait on another thread's request
+        let (full, retries) = {
+            let mut batch = self.lock();
+            let full = match batch.records.len() == MAX_BATCH_RECORDS || batch.bytes + document.len() > MAX_BATCH_BYTES
+            {
+                true => {
+                    batch.bytes = 0;
+                    Some(std::mem::take(&mut batch.records))
+                }
+                false => None,
+            };
+            batch.bytes += document.len();
+            batch.records.push(document.to_vec());
+            (full, Self::take_due_retries(&mut batch))
+        };
+
+        let mut result = Ok(());
+        for retry in retries {
+            result = result.and(self.submit(retry.records, retry.retries));
+        }
+        if let Some(full) = full {
+            result = result.and(self.submit(full, 0));
+        }
+        result
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let (records, retries) = {
+            let mut batch = self.lock();
+            batch.bytes = 0;
+            (std::mem::take(&mut batch.records), Self::take_due_retries(&mut batch))
+        };
+
+        let mut result = Ok(());
+        for retry in retries {
+            result = result.and(self.submit(retry.records, retry.retries));
+        }
+        if !records.is_empty() {
+            result = result.and(self.submit(records, 0));
+        }
+
+        let waiting: usize = self.lock().retries.iter().map(|retry| retry.records.len()).sum();
+        if waiting > 0 {
+            result = result.and(Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{waiting} records failed, retrying with a later flush"),
+            )));
+        }
+        result
+    }
+}
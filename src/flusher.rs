@@ -0,0 +1,87 @@
+//! # Flusher
+//!
+//! Flushing from a background tokio task on a schedule, for long-running services outside of Lambda
+
+use super::collector::Collector;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Handle to a background task flushing a [Collector] on a schedule, returned from
+/// [Collector::spawn_flusher](crate::Collector::spawn_flusher)
+/// * Each wait is the interval give or take up to 5%, so many processes started together don't flush in lockstep
+/// * Flush errors are reported via [Builder::on_error](crate::Builder::on_error) and the schedule continues
+/// * Call [BackgroundFlusher::shutdown] before exiting so values recorded since the last flush aren't lost, dropping
+///   the handle also stops the task after a final flush but without waiting for it
+///
+/// *requires the `tokio` feature flag*
+///
+/// # Example
+/// ```no_run
+/// # async fn run() {
+/// use std::time::Duration;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyService")
+///      .init()
+///      .unwrap();
+///
+/// let flusher = metrics.spawn_flusher(Duration::from_secs(60), tokio::io::stdout());
+///
+/// // Serve requests
+///
+/// flusher.shutdown().await;
+/// # }
+/// ```
+pub struct BackgroundFlusher {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl BackgroundFlusher {
+    pub(crate) fn spawn(
+        collector: &'static Collector,
+        interval: Duration,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> Self {
+        let (shutdown, stopped) = oneshot::channel();
+        Self {
+            shutdown,
+            task: tokio::spawn(run(collector, interval, writer, stopped)),
+        }
+    }
+
+    /// Stop the task after a final flush, waiting for the flush to complete
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+async fn run(
+    collector: &'static Collector,
+    interval: Duration,
+    mut writer: impl AsyncWrite + Unpin,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    loop {
+        // The receiver also completes when the handle is dropped
+        let stop = tokio::time::timeout(jitter(interval), &mut stopped).await.is_ok();
+        if let Err(e) = collector.flush_async(&mut writer).await {
+            collector.handle_error(format_args!("Failed to flush metrics in the background: {e}"));
+        }
+        if stop {
+            break;
+        }
+    }
+}
+
+/// The interval give or take up to 5%
+fn jitter(interval: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = (random % 1001) as f64 / 1000.0;
+    interval.mul_f64(0.95 + fraction * 0.1)
+}
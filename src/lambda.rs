@@ -1,8 +1,9 @@
-//! Additional functionality for integration with [lambda_runtime] and [lambda_http]
+//! Additional functionality for integration with [lambda_runtime] and `lambda_http`
 //!
 //! Inspired by Lambda Power Tools
 //!
-//! *this module requires the `lambda` feature flag*
+//! *this module requires the `lambda` feature flag, the `run_http` helpers additionally require the `lambda-http`
+//! feature flag*
 //!
 //! # Simple Example
 //!
@@ -41,8 +42,8 @@
 //!         .init();
 //!
 //!     let metrics = metrics_cloudwatch_embedded::Builder::new()
-//!         .cloudwatch_namespace("MetricsExample")
-//!         .with_dimension("function", std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap())
+//!         .cloudwatch_namespace("MetricsTest")
+//!         .with_dimension("Function", std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap())
 //!         .lambda_cold_start_span(info_span!("cold start"))
 //!         .lambda_cold_start_metric("ColdStart")
 //!         .with_lambda_request_id("RequestId")
@@ -76,21 +77,59 @@ use super::collector::Collector;
 use lambda_runtime::{LambdaEvent, LambdaInvocation};
 use pin_project::pin_project;
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tower::Layer;
 
+/// When the lambda layer flushes each invocation's metrics, via
+/// [Builder::lambda_flush_trigger](super::Builder::lambda_flush_trigger)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlushTrigger {
+    /// After the response has been posted to the runtime API, keeping the flush off the response's critical path,
+    /// the default
+    #[default]
+    AfterResponse,
+    /// As soon as the handler's future resolves, before the response is serialized and posted, so the metrics are
+    /// written even if posting the response fails
+    HandlerCompletion,
+}
+
+/// Creates the writer each flush is written to
+type MakeWriter = Arc<dyn Fn() -> Box<dyn std::io::Write> + Send + Sync>;
+
+/// Default [MakeWriter] emitting to the collector's [Sink](crate::Sink), stdout unless set via
+/// [Builder::with_sink](super::Builder::with_sink)
+fn sink_writer(collector: &'static Collector) -> MakeWriter {
+    Arc::new(move || Box::new(collector.sink_writer()))
+}
+
 /// [tower::Layer] for automatically [flushing](super::Collector::flush()) after each request and enabling
 /// `lambda` features in [Builder](super::Builder)
 ///
 /// For composing your own [tower] stacks to input into the Rust Lambda Runtime
 pub struct MetricsLayer {
     pub(crate) collector: &'static Collector,
+    make_writer: MakeWriter,
 }
 
 impl MetricsLayer {
     pub fn new(collector: &'static Collector) -> Self {
-        Self { collector }
+        Self {
+            collector,
+            make_writer: sink_writer(collector),
+        }
+    }
+
+    /// Writes metrics to writers created by make_writer instead of the collector's [Sink](crate::Sink), e.g. a
+    /// [CaptureWriter](crate::testing::CaptureWriter) to assert on them in tests
+    pub fn with_writer<W: std::io::Write + 'static>(
+        mut self,
+        make_writer: impl Fn() -> W + Send + Sync + 'static,
+    ) -> Self {
+        self.make_writer = Arc::new(move || Box::new(make_writer()));
+        self
     }
 }
 
@@ -100,6 +139,7 @@ impl<S> Layer<S> for MetricsLayer {
     fn layer(&self, inner: S) -> Self::Service {
         MetricsService {
             metrics: self.collector,
+            make_writer: self.make_writer.clone(),
             inner,
         }
     }
@@ -111,6 +151,7 @@ impl<S> Layer<S> for MetricsLayer {
 /// For composing your own [tower] stacks to input into the Rust Lambda Runtime
 pub struct MetricsService<S> {
     metrics: &'static Collector,
+    make_writer: MakeWriter,
     inner: S,
 }
 
@@ -121,7 +162,11 @@ impl<S> MetricsService<S> {
     where
         S: tower::Service<LambdaEvent<Request>>,
     {
-        Self { metrics, inner }
+        Self {
+            metrics,
+            make_writer: sink_writer(metrics),
+            inner,
+        }
     }
 }
 
@@ -138,38 +183,207 @@ where
     }
 
     fn call(&mut self, req: LambdaInvocation) -> Self::Future {
+        // Deferring only applies to the invocation it was requested in
+        self.metrics.take_defer_flush();
+
         if let Some(prop_name) = self.metrics.config.lambda_request_id {
             self.metrics.set_property(prop_name, req.context.request_id.clone());
         }
         if let Some(prop_name) = self.metrics.config.lambda_xray_trace_id {
             self.metrics.set_property(prop_name, req.context.xray_trace_id.clone());
         }
+        if let Some(arn) = FunctionArn::parse(&req.context.invoked_function_arn) {
+            if let Some(prop_name) = self.metrics.config.lambda_account_id {
+                self.metrics.set_property(prop_name, arn.account_id);
+            }
+            if let Some(prop_name) = self.metrics.config.lambda_region {
+                self.metrics.set_property(prop_name, arn.region);
+            }
+            if let Some(prop_name) = self.metrics.config.lambda_qualifier {
+                self.metrics.set_property(prop_name, arn.qualifier.unwrap_or("$LATEST"));
+            }
+        }
+
+        if !self.metrics.config.lambda_event_fields.is_empty() {
+            let event = serde_json::from_slice::<serde_json::Value>(&req.body).ok();
+            for field in &self.metrics.config.lambda_event_fields {
+                match event.as_ref().and_then(|event| event.pointer(field.pointer)) {
+                    Some(serde_json::Value::String(value)) => {
+                        self.metrics.set_property(field.name, value.clone());
+                    }
+                    Some(value) if field.dimension => {
+                        self.metrics.set_property(field.name, value.to_string());
+                    }
+                    Some(value) => {
+                        self.metrics.set_property(field.name, value.clone());
+                    }
+                    None => {
+                        self.metrics.remove_property(field.name);
+                    }
+                }
+            }
+        }
 
         let mut cold_start_span = None;
         if let Some(counter_name) = self.metrics.config.lambda_cold_start {
-            static COLD_START_BEGIN: std::sync::Once = std::sync::Once::new();
-            COLD_START_BEGIN.call_once(|| {
+            if self.metrics.take_cold_start() {
                 cold_start_span = self.metrics.take_cold_start_span().map(|span| span.entered());
-                self.metrics
-                    .write_single(counter_name, Some(metrics::Unit::Count), 1, std::io::stdout())
-                    .expect("failed to flush cold start metric");
-            });
+                let writer = (self.make_writer)();
+                if let Err(e) = self
+                    .metrics
+                    .write_single(counter_name, Some(metrics::Unit::Count), 1, writer)
+                {
+                    self.metrics
+                        .handle_error(format_args!("Failed to write cold start metric: {e}"));
+                }
+            }
         }
 
         // Wrap the inner Future so we can flush after it's done
         MetricsServiceFuture {
             metrics: self.metrics,
+            make_writer: self.make_writer.clone(),
             inner: self.inner.call(req),
             cold_start_span,
         }
     }
 }
 
+/// Components of an invoked function ARN, `arn:<partition>:lambda:<region>:<account id>:function:<name>[:<qualifier>]`
+struct FunctionArn<'a> {
+    region: &'a str,
+    account_id: &'a str,
+    qualifier: Option<&'a str>,
+}
+
+impl<'a> FunctionArn<'a> {
+    fn parse(arn: &'a str) -> Option<Self> {
+        let mut parts = arn.split(':');
+        let (
+            Some("arn"),
+            Some(_partition),
+            Some("lambda"),
+            Some(region),
+            Some(account_id),
+            Some("function"),
+            Some(_name),
+        ) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        )
+        else {
+            return None;
+        };
+
+        Some(Self {
+            region,
+            account_id,
+            qualifier: parts.next(),
+        })
+    }
+}
+
+/// Flush the metrics of the invocation that just completed
+/// * A failing [Sink](crate::Sink) is reported via [Builder::on_error](super::Builder::on_error) rather than failing
+///   the invocation
+fn flush_invocation(collector: &Collector, writer: Box<dyn std::io::Write>) {
+    let result = match collector.config.lambda_invocation_isolation {
+        true => collector.flush_invocation(writer),
+        false => collector.flush(writer),
+    };
+    if let Err(e) = result {
+        collector.handle_error(format_args!("Failed to flush metrics: {e}"));
+    }
+}
+
+/// [tower::Service] wrapping the handler to flush as soon as it completes, for [FlushTrigger::HandlerCompletion]
+/// * Defers the flush of the enclosing [MetricsService] so the invocation isn't flushed twice
+/// * Catches panics for [Builder::lambda_panic_metric](super::Builder::lambda_panic_metric), the runtime turns them
+///   into errors before they reach the [MetricsService]
+struct HandlerFlushService<S> {
+    metrics: &'static Collector,
+    make_writer: MakeWriter,
+    inner: S,
+}
+
+impl<S, Request> tower::Service<LambdaEvent<Request>> for HandlerFlushService<S>
+where
+    S: tower::Service<LambdaEvent<Request>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = HandlerFlushFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: LambdaEvent<Request>) -> Self::Future {
+        HandlerFlushFuture {
+            metrics: self.metrics,
+            make_writer: self.make_writer.clone(),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+#[pin_project]
+#[doc(hidden)]
+pub struct HandlerFlushFuture<F> {
+    metrics: &'static Collector,
+    make_writer: MakeWriter,
+    #[pin]
+    inner: F,
+}
+
+impl<F: Future> Future for HandlerFlushFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let collector = *this.metrics;
+
+        let mut inner = this.inner;
+        let poll = match collector.config.lambda_panic {
+            Some(counter_name) => match std::panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+                Ok(poll) => poll,
+                Err(panic) => {
+                    // Count the panic and keep the invocation's metrics before the runtime reports the error
+                    let counter = match collector.config.lambda_invocation_isolation {
+                        true => metrics::with_local_recorder(&collector.invocation_recorder(), || {
+                            metrics::counter!(counter_name)
+                        }),
+                        false => collector.counter(counter_name, Vec::<metrics::Label>::new()),
+                    };
+                    counter.increment(1);
+                    flush_invocation(collector, (this.make_writer)());
+                    collector.defer_flush();
+                    std::panic::resume_unwind(panic);
+                }
+            },
+            None => inner.poll(cx),
+        };
+        if poll.is_ready() && collector.config.lambda_flush_trigger == FlushTrigger::HandlerCompletion {
+            if !collector.take_defer_flush() {
+                flush_invocation(collector, (this.make_writer)());
+            }
+            collector.defer_flush();
+        }
+        poll
+    }
+}
+
 #[pin_project]
 #[doc(hidden)]
 pub struct MetricsServiceFuture<F> {
     #[pin]
     metrics: &'static Collector,
+    make_writer: MakeWriter,
     #[pin]
     inner: F,
     cold_start_span: Option<tracing::span::EnteredSpan>,
@@ -184,17 +398,26 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
+        let collector: &'static Collector = *this.metrics;
+        let isolation = collector.config.lambda_invocation_isolation;
+
+        // Metrics recorded while polling the handler go to the invocation's own tree when isolated
+        let mut inner = this.inner;
+        let poll = match isolation {
+            true => metrics::with_local_recorder(&collector.invocation_recorder(), || inner.as_mut().poll(cx)),
+            false => inner.as_mut().poll(cx),
+        };
 
-        if let Poll::Ready(result) = this.inner.poll(cx) {
+        if let Poll::Ready(result) = poll {
             let result = result.map_err(Into::into);
 
-            // Flush our metrics after the inner service is finished
-            this.metrics.flush(std::io::stdout()).expect("failed to flush metrics");
+            // Flush our metrics after the inner service is finished, unless the handler deferred it
+            if !collector.take_defer_flush() {
+                flush_invocation(collector, (this.make_writer)());
+            }
 
-            static COLD_START_END: std::sync::Once = std::sync::Once::new();
-            COLD_START_END.call_once(|| {
-                let _span = this.cold_start_span.take();
-            });
+            // Only the first invoke holds the cold start span
+            let _span = this.cold_start_span.take();
 
             return Poll::Ready(result);
         }
@@ -233,14 +456,41 @@ pub mod service {
         D: Into<bytes::Bytes> + Send,
         E: Into<lambda_runtime::Error> + Send + Debug,
     {
+        run_layered(MetricsLayer::new(metrics), handler).await
+    }
+
+    /// [run] with a given [MetricsLayer], e.g. one writing elsewhere than the collector's sink
+    pub(crate) async fn run_layered<A, F, R, B, S, D, E>(
+        layer: MetricsLayer,
+        handler: F,
+    ) -> Result<(), lambda_runtime::Error>
+    where
+        F: Service<LambdaEvent<A>, Response = R>,
+        F::Future: Future<Output = Result<R, F::Error>>,
+        F::Error: Into<Diagnostic> + std::fmt::Debug,
+        A: for<'de> Deserialize<'de>,
+        R: IntoFunctionResponse<B, S>,
+        B: Serialize,
+        S: Stream<Item = Result<D, E>> + Unpin + Send + 'static,
+        D: Into<bytes::Bytes> + Send,
+        E: Into<lambda_runtime::Error> + Send + Debug,
+    {
+        let handler = HandlerFlushService {
+            metrics: layer.collector,
+            make_writer: layer.make_writer.clone(),
+            inner: handler,
+        };
         let runtime = lambda_runtime::Runtime::new(handler)
             .layer(TracingLayer::new())
-            .layer(MetricsLayer::new(metrics));
+            .layer(layer);
         runtime.run().await
     }
 
     /// Start the Lambda Rust runtime with a given [tower::Service<lambda_http::Request>]
     /// which is then layered with [TracingLayer] and [MetricsLayer] with a given [Collector]
+    ///
+    /// *requires the `lambda-http` feature flag*
+    #[cfg(feature = "lambda-http")]
     pub async fn run_http<'a, R, S, E>(metrics: &'static Collector, handler: S) -> Result<(), lambda_runtime::Error>
     where
         S: Service<lambda_http::Request, Response = R, Error = E>,
@@ -259,8 +509,6 @@ pub mod service {
 ///
 pub mod handler {
 
-    use lambda_http::service_fn;
-
     use super::*;
 
     /// Start the Lambda Rust runtime with a given [LambdaEvent] handler function
@@ -280,6 +528,9 @@ pub mod handler {
 
     /// Start the Lambda Rust runtime with a given [lambda_http::Request] handler function
     /// which is then layered with [lambda_runtime::layers::TracingLayer] and [MetricsLayer] with a given [Collector]
+    ///
+    /// *requires the `lambda-http` feature flag*
+    #[cfg(feature = "lambda-http")]
     pub async fn run_http<'a, T, F, Response>(
         metrics: &'static Collector,
         handler: T,
@@ -289,6 +540,6 @@ pub mod handler {
         F: Future<Output = Result<Response, lambda_runtime::Error>> + Send + 'a,
         Response: lambda_http::IntoResponse,
     {
-        super::service::run(metrics, lambda_http::Adapter::from(service_fn(handler))).await
+        super::service::run(metrics, lambda_http::Adapter::from(lambda_http::service_fn(handler))).await
     }
 }
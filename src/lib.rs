@@ -17,6 +17,28 @@
 //!      .flush(std::io::stdout());
 //! ```
 //!
+//! Users porting from the aws-embedded-metrics libraries who prefer an imperative API over the [metrics] macros
+//! can use [MetricsLogger] instead, or a per-request [MetricsScope] via [Collector::scope].
+//!
+//! A [Collector] can also be constructed without installing a global recorder via
+//! [Builder::collector()](builder::Builder::collector) and recorded into via [metrics::with_local_recorder] and
+//! [Collector::recorder], e.g. for tests running in parallel.
+//!
+//! Libraries can record into the host application's namespace under their own name via [Collector::subscope].
+//!
+//! Metrics can also be declared up front as statics via [static_metrics!], and label sets as types implementing
+//! [Dimensions] via `#[derive(Dimensions)]` (*requires the `derive` feature flag*).
+//!
+//! The overhead of collection can be measured from a service's own benchmarks via [Collector::bench_stats]
+//! (*requires the `perf` feature flag*).
+//!
+//! Async servers can flush to a [tokio::io::AsyncWrite] via [Collector::flush_async], or from a background task via
+//! [Collector::spawn_flusher] (*requires the `tokio` feature flag*).
+//!
+//! Documents can be shipped through Amazon Data Firehose via [FirehoseSink] wrapping your own client, or the same
+//! metrics published via `PutMetricData` where logs aren't routed to CloudWatch via [PutMetricDataSink] wrapping any
+//! client implementing [PutMetricData].
+//!
 //! # Implementation Details
 //!
 //! Intended for use with the [lambda_runtime], however [Collector::flush(...)](collector::Collector::flush)
@@ -24,35 +46,106 @@
 //!
 //! * Counters are Guages are implented as [AtomicU64](std::sync::atomic::AtomicU64) via the
 //!   [CounterFn](metrics::CounterFn) and [GaugeFn](metrics::GaugeFn) implementations in the [metrics crate](metrics)
-//! * Histograms are implemented as [mpsc::SyncSender](std::sync::mpsc::SyncSender)
+//! * Histograms are implemented as a lock-free double buffer, recording into one half while the other is flushed
 //! * [serde_json] is used to serialize metric documents to simplify maintainence and for consistancy with other
 //!   crates in the ecosystem
-//! * Registering and flushing of metrics uses state within a [Mutex](std::sync::Mutex), recording previously
-//!   registered metrics should not block on this [Mutex](std::sync::Mutex)
+//! * Dimensions (default dimensions + labels) are serialized once per label set at registration and spliced into
+//!   each document at flush
+//! * Flushing of metrics uses state within a [Mutex](std::sync::Mutex), registering looks up metrics in a sharded
+//!   index and new metrics are merged into the state at the next flush, so neither registering nor recording
+//!   blocks on this [Mutex](std::sync::Mutex)
 //! * Metric names are mapped to [metrics::Unit] regardless of their type and [labels](metrics::Label)
 //! * Metric descriptions are unused
 //!
 //! # Limitations
-//! * Histograms retain up to 100 values (the maximum for a single metric document) between calls to
-//!   [Collector::flush()](collector::Collector::flush), overflow will report an error via the [tracing] crate
+//! * Histograms retain up to 100 values (configurable via
+//!   [Builder::with_histogram_capacity()][builder::Builder::with_histogram_capacity]) between calls to
+//!   [Collector::flush()](collector::Collector::flush), overflow will report an error unless aggregated via
+//!   [Builder::with_histogram_statistic_sets()][builder::Builder::with_histogram_statistic_sets] or recorded into a
+//!   sketch via [Builder::with_histogram_sketch()][builder::Builder::with_histogram_sketch]
 //! * Dimensions set at initialization via [Builder::with_dimension(...)][builder::Builder::with_dimension]
 //!   may not overlap with metric [labels](metrics::Label)
+//! * NaN and infinite gauge and histogram values are dropped at flush with a warning, see
+//!   [Collector::dropped_non_finite]
 //! * Only the subset of metric units in [metrics::Unit] are supported
 //!   <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_MetricDatum.html>
-//! * Registering different metric types with the same [metrics::Key] will fail with an error via the [tracing] crate
+//! * Registering different metric types with the same [metrics::Key] will fail with an error
 //! * The Embedded Metric Format supports a maximum of 30 dimensions per metric, attempting to register a metric with
-//!   more than 30 dimensions/labels will fail with an error via the [tracing] crate
+//!   more than 30 dimensions/labels will fail with an error
+//! * Errors are reported via the `tracing` crate, or the `log` crate if the `tracing` feature is disabled, unless
+//!   handled by [Builder::on_error(...)](builder::Builder::on_error)
 //!
 
-pub use {builder::Builder, collector::Collector};
+pub use {
+    builder::Builder,
+    clock::{Clock, MockClock, SystemClock},
+    cloudwatch::{MetricDatum, MetricValue, PutMetricData, PutMetricDataSink},
+    collector::{
+        BoundedHistogram, CardinalityEntry, Collector, CostEstimate, CounterEmission, EmfDocument, FlushOrder,
+        FlushReport, LabelSetReport, Recorder, Saturated, SketchOutput, SnapshotEntry, SnapshotValue, UnitPolicy,
+        WriteOptions, CUSTOM_METRIC_MONTHLY_COST, FLUSH_SEQUENCE, HEARTBEAT, ISO_TIME, PROCESS_START_TIME, REDACTED,
+        TENANT_OVERFLOW,
+    },
+    dimensions::{DimensionValue, Dimensions},
+    emf::{ParsedAws, ParsedDirective, ParsedDocument, ParsedMetric},
+    firehose::{FirehoseSink, PutRecordBatch},
+    logger::{MetricsLogger, MetricsScope},
+    macros::StaticMetric,
+    router::Router,
+    sanitize::NameSanitizer,
+    sink::{
+        AgentSink, DropPolicy, PrettyWriter, RetryStats, RetryWriter, Sink, SinkWriter, StdoutSink, AGENT_ENDPOINT_ENV,
+    },
+    subscope::{Subscope, COMPONENT},
+    tick::TickFlusher,
+    timer::Timer,
+    validate::{validate_document, ValidationError},
+};
+
+#[cfg(feature = "derive")]
+pub use metrics_cloudwatch_embedded_derive::Dimensions;
+
+#[cfg(feature = "tokio")]
+pub use flusher::BackgroundFlusher;
+
+// Lets `#[derive(Dimensions)]` output resolve within our own tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as metrics_cloudwatch_embedded;
+
+#[doc(hidden)]
+pub use metrics as __metrics;
 
 #[doc(hidden)]
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+mod buffer;
 mod builder;
+mod clock;
+mod cloudwatch;
 mod collector;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod dimensions;
 mod emf;
+mod firehose;
+#[cfg(feature = "tokio")]
+mod flusher;
 #[cfg(feature = "lambda")]
 pub mod lambda;
+mod logger;
+mod macros;
+#[cfg(feature = "perf")]
+pub mod perf;
+mod registry;
+mod report;
+mod router;
+mod sanitize;
+mod sink;
+mod sketch;
+mod subscope;
 #[cfg(test)]
 mod test;
+pub mod testing;
+mod tick;
+mod timer;
+mod validate;
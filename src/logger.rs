@@ -0,0 +1,225 @@
+//! # Logger
+//!
+//! Imperative metrics logger mirroring the aws-embedded-metrics libraries for Node, Python and Java
+//!
+//! <https://github.com/awslabs/aws-embedded-metrics-node>
+
+#![allow(dead_code)]
+use super::report::report_error;
+use super::{collector::Collector, emf};
+use metrics::SharedString;
+use serde_json::value::Value;
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+
+/// The Embedded Metric Format supports a maximum of 30 dimensions per metric
+const MAX_DIMENSIONS: usize = 30;
+
+/// Metric values and unit accumulated by [MetricsLogger::put_metric]
+struct LoggedMetric {
+    unit: Option<metrics::Unit>,
+    values: Vec<f64>,
+}
+
+/// Imperative alternative to the [metrics] macros, for users porting from the aws-embedded-metrics libraries
+///
+/// Each call to [MetricsLogger::flush] writes a single document with every metric put since the last flush
+///
+/// # Example
+/// ```
+/// let mut logger = metrics_cloudwatch_embedded::MetricsLogger::new("MyApplication");
+///
+/// logger
+///     .put_dimension("Method", "Default")
+///     .set_property("RequestId", "ABC123")
+///     .put_metric("ProcessingLatency", 100.0, Some(metrics::Unit::Milliseconds))
+///     .flush(std::io::stdout())
+///     .unwrap();
+/// ```
+pub struct MetricsLogger {
+    namespace: SharedString,
+    timestamp: Option<u64>,
+    dimensions: Vec<(SharedString, SharedString)>,
+    properties: BTreeMap<SharedString, Value>,
+    metrics: BTreeMap<SharedString, LoggedMetric>,
+}
+
+impl MetricsLogger {
+    /// Constructs an empty logger for the given CloudWatch namespace
+    pub fn new(namespace: impl Into<SharedString>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            timestamp: None,
+            dimensions: Vec::new(),
+            properties: BTreeMap::new(),
+            metrics: BTreeMap::new(),
+        }
+    }
+
+    /// Constructs a logger with the namespace, default dimensions, properties and timestamp of a [Collector]
+    pub fn from_collector(collector: &Collector) -> Self {
+        Self {
+            namespace: collector.config.cloudwatch_namespace.clone(),
+            timestamp: collector.config.timestamp,
+            dimensions: collector.config.default_dimensions.clone(),
+            properties: collector.properties(),
+            metrics: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the CloudWatch namespace for subsequent flushes
+    pub fn set_namespace(&mut self, namespace: impl Into<SharedString>) -> &mut Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Adds a dimension (name, value) to every document written by this logger
+    /// * Putting a dimension with the same name multiple times will overwrite the previous value
+    /// * Documents can have no more than 30 dimensions
+    pub fn put_dimension(&mut self, name: impl Into<SharedString>, value: impl Into<SharedString>) -> &mut Self {
+        let name = name.into();
+        let value = value.into();
+
+        if let Some(dimension) = self.dimensions.iter_mut().find(|(n, _)| *n == name) {
+            dimension.1 = value;
+        } else if self.dimensions.len() < MAX_DIMENSIONS {
+            self.dimensions.push((name, value));
+        } else {
+            report_error!("Unable to put dimension {name} as there are already {MAX_DIMENSIONS} dimensions");
+        }
+        self
+    }
+
+    /// Set a property to emit with the metrics
+    /// * Properties persist accross flush calls
+    pub fn set_property(&mut self, name: impl Into<SharedString>, value: impl Into<Value>) -> &mut Self {
+        self.properties.insert(name.into(), value.into());
+        self
+    }
+
+    /// Puts a metric value, putting the same name multiple times before a flush emits every value
+    /// * The unit of the latest call for a given name wins
+    pub fn put_metric(&mut self, name: impl Into<SharedString>, value: f64, unit: Option<metrics::Unit>) -> &mut Self {
+        let metric = self.metrics.entry(name.into()).or_insert_with(|| LoggedMetric {
+            unit: None,
+            values: Vec::new(),
+        });
+        metric.unit = unit;
+        metric.values.push(value);
+        self
+    }
+
+    /// Converts this logger into a [MetricsScope] that flushes to the given writer when dropped
+    pub fn into_scope<W: std::io::Write>(self, writer: W) -> MetricsScope<W> {
+        MetricsScope {
+            logger: self,
+            writer: Some(writer),
+        }
+    }
+
+    /// Write a document with every metric put since the last flush to an implementation of [std::io::Write]
+    /// * Dimensions and properties are retained, metrics are cleared
+    /// * Nothing is written if no metrics were put
+    pub fn flush(&mut self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        if self.metrics.is_empty() {
+            return Ok(());
+        }
+
+        let mut emf = emf::EmbeddedMetrics {
+            aws: emf::EmbeddedMetricsAws {
+                timestamp: self.timestamp.unwrap_or_else(emf::now),
+                cloudwatch_metrics: vec![emf::EmbeddedNamespace {
+                    namespace: &self.namespace,
+                    dimensions: vec![Vec::with_capacity(self.dimensions.len())],
+                    metrics: Vec::with_capacity(self.metrics.len()),
+                }],
+            },
+            dimensions: BTreeMap::new(),
+            properties: BTreeMap::new(),
+            values: BTreeMap::new(),
+        };
+
+        for dimension in &self.dimensions {
+            emf.aws.cloudwatch_metrics[0].dimensions[0].push(&dimension.0);
+            emf.dimensions.insert(&dimension.0, &dimension.1);
+        }
+
+        for (key, value) in &self.properties {
+            emf.properties.insert(key, value.clone());
+        }
+
+        for (name, metric) in &self.metrics {
+            emf.aws.cloudwatch_metrics[0].metrics.push(emf::EmbeddedMetric {
+                name,
+                unit: metric.unit.as_ref().map(emf::unit_to_str),
+            });
+            match metric.values.as_slice() {
+                [value] => emf.values.insert(name, (*value).into()),
+                values => emf.values.insert(name, values.into()),
+            };
+        }
+
+        serde_json::to_writer(&mut writer, &emf)?;
+        writeln!(writer)?;
+
+        self.metrics.clear();
+        Ok(())
+    }
+}
+
+/// [MetricsLogger] scoped to a single unit of work (e.g. a request) that flushes when dropped
+///
+/// Scopes are independent of each other and of the global recorder, giving correct isolation when processing
+/// concurrent requests outside of Lambda
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// {
+///     let mut scope = metrics.scope(std::io::stdout());
+///     scope
+///         .set_property("RequestId", "ABC123")
+///         .put_metric("ProcessingLatency", 100.0, Some(metrics::Unit::Milliseconds));
+/// } // flushed here
+/// ```
+pub struct MetricsScope<W: std::io::Write> {
+    logger: MetricsLogger,
+    writer: Option<W>,
+}
+
+impl<W: std::io::Write> MetricsScope<W> {
+    /// Flushes now and ends the scope, returning the writer and any error instead of reporting it via [tracing]
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let mut writer = self.writer.take().expect("writer is only taken on finish or drop");
+        self.logger.flush(&mut writer)?;
+        Ok(writer)
+    }
+}
+
+impl<W: std::io::Write> Deref for MetricsScope<W> {
+    type Target = MetricsLogger;
+
+    fn deref(&self) -> &MetricsLogger {
+        &self.logger
+    }
+}
+
+impl<W: std::io::Write> DerefMut for MetricsScope<W> {
+    fn deref_mut(&mut self) -> &mut MetricsLogger {
+        &mut self.logger
+    }
+}
+
+impl<W: std::io::Write> Drop for MetricsScope<W> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = self.logger.flush(writer) {
+                report_error!("Failed to flush metrics scope: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! # Macros
+//!
+//! Declarative helpers for defining a service's metrics in one place
+
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+/// Metric handle declared via [static_metrics!](crate::static_metrics), registered with the current recorder on
+/// first use
+pub struct StaticMetric<T> {
+    handle: OnceLock<T>,
+    init: fn() -> T,
+}
+
+impl<T> StaticMetric<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            handle: OnceLock::new(),
+            init,
+        }
+    }
+}
+
+impl<T> Deref for StaticMetric<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.handle.get_or_init(self.init)
+    }
+}
+
+/// Declares a set of metrics as statics, each described and registered on first use
+///
+/// Each metric is declared as `name: kind("metric name", Unit, "description", labels...)` where kind is one of
+/// `counter`, `gauge` or `histogram` and Unit is a [metrics::Unit] variant
+///
+/// Using the statics rather than the [metrics] macros at call sites turns a typo'd metric name into a
+/// compile error and gives a single place to audit a service's metrics
+///
+/// # Example
+/// ```
+/// metrics_cloudwatch_embedded::static_metrics! {
+///     /// Requests handled by the default method
+///     pub REQUESTS: counter("requests", Count, "Requests handled", "Method" => "Default");
+///     pub LATENCY: histogram("latency", Milliseconds, "Request latency");
+/// }
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// REQUESTS.increment(1);
+/// LATENCY.record(10.0);
+/// ```
+#[macro_export]
+macro_rules! static_metrics {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis $ident:ident : $kind:ident ( $name:literal, $unit:ident, $description:literal $(, $key:literal => $value:literal)* $(,)? );
+    )*) => {
+        $(
+            $(#[$attr])*
+            $vis static $ident: $crate::StaticMetric<$crate::__static_metric!(@type $kind)> =
+                $crate::StaticMetric::new(|| $crate::__static_metric!(@init $kind, $name, $unit, $description $(, $key => $value)*));
+        )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __static_metric {
+    (@type counter) => { $crate::__metrics::Counter };
+    (@type gauge) => { $crate::__metrics::Gauge };
+    (@type histogram) => { $crate::__metrics::Histogram };
+    (@init counter, $name:literal, $unit:ident, $description:literal $(, $key:literal => $value:literal)*) => {{
+        $crate::__metrics::describe_counter!($name, $crate::__metrics::Unit::$unit, $description);
+        $crate::__metrics::counter!($name $(, $key => $value)*)
+    }};
+    (@init gauge, $name:literal, $unit:ident, $description:literal $(, $key:literal => $value:literal)*) => {{
+        $crate::__metrics::describe_gauge!($name, $crate::__metrics::Unit::$unit, $description);
+        $crate::__metrics::gauge!($name $(, $key => $value)*)
+    }};
+    (@init histogram, $name:literal, $unit:ident, $description:literal $(, $key:literal => $value:literal)*) => {{
+        $crate::__metrics::describe_histogram!($name, $crate::__metrics::Unit::$unit, $description);
+        $crate::__metrics::histogram!($name $(, $key => $value)*)
+    }};
+}
+
+/// Calls [Builder::with_build_info](crate::Builder::with_build_info) with the calling crate's `CARGO_PKG_VERSION`
+/// and the `GIT_SHA` environment variable, both read at compile time
+///
+/// `GIT_SHA` is typically set by CI or exported from a build script, the `GitSha` property is omitted if unset
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::with_build_info!(
+///     metrics_cloudwatch_embedded::Builder::new().cloudwatch_namespace("MyApplication")
+/// )
+/// .init()
+/// .unwrap();
+/// ```
+#[macro_export]
+macro_rules! with_build_info {
+    ($builder:expr) => {
+        $builder.with_build_info(
+            env!("CARGO_PKG_VERSION"),
+            match option_env!("GIT_SHA") {
+                Some(git_sha) => git_sha,
+                None => "",
+            },
+        )
+    };
+}
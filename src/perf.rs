@@ -0,0 +1,122 @@
+//! # Perf
+//!
+//! Measuring the overhead of metrics collection from within a service's own benchmarks and CI perf gates
+//!
+//! *this module requires the `perf` feature flag*
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Allocations made via [CountingAllocator] since the process started
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// [GlobalAlloc] wrapping [System] that counts allocations, so [BenchStats::flush_allocations] is populated
+/// * Counts are process wide, allocations from other threads while flushing are included
+///
+/// # Example
+/// ```
+/// #[global_allocator]
+/// static ALLOCATOR: metrics_cloudwatch_embedded::perf::CountingAllocator =
+///     metrics_cloudwatch_embedded::perf::CountingAllocator;
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Allocations counted so far, always 0 unless [CountingAllocator] is the global allocator
+pub fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Overhead of a [Collector](crate::Collector) since it was created, via
+/// [Collector::bench_stats](crate::Collector::bench_stats)
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush(std::io::sink()).unwrap();
+///
+/// let stats = metrics.bench_stats();
+/// assert_eq!(stats.flushes, 1);
+/// println!("{:?} per flush", stats.flush_time / stats.flushes as u32);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BenchStats {
+    pub flushes: u64,
+    /// Total time spent flushing, including writing documents
+    pub flush_time: Duration,
+    pub documents: u64,
+    pub bytes: u64,
+    /// Allocations while flushing, requires [CountingAllocator]
+    pub flush_allocations: u64,
+    /// Times the collector's state was locked, by recording into new series, flushing or setting properties
+    pub lock_acquisitions: u64,
+    /// Total time spent waiting to lock the collector's state
+    pub lock_wait_time: Duration,
+}
+
+/// Counters behind [BenchStats], updated by the collector
+#[derive(Debug, Default)]
+pub(crate) struct PerfCounters {
+    flushes: AtomicU64,
+    flush_nanos: AtomicU64,
+    documents: AtomicU64,
+    bytes: AtomicU64,
+    flush_allocations: AtomicU64,
+    lock_acquisitions: AtomicU64,
+    lock_wait_nanos: AtomicU64,
+}
+
+impl PerfCounters {
+    pub(crate) fn record_flush(&self, time: Duration, documents: usize, bytes: usize, allocations: u64) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.flush_nanos.fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+        self.documents.fetch_add(documents as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.flush_allocations.fetch_add(allocations, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_lock(&self, wait: Duration) {
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.lock_wait_nanos
+            .fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> BenchStats {
+        BenchStats {
+            flushes: self.flushes.load(Ordering::Relaxed),
+            flush_time: Duration::from_nanos(self.flush_nanos.load(Ordering::Relaxed)),
+            documents: self.documents.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            flush_allocations: self.flush_allocations.load(Ordering::Relaxed),
+            lock_acquisitions: self.lock_acquisitions.load(Ordering::Relaxed),
+            lock_wait_time: Duration::from_nanos(self.lock_wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
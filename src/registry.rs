@@ -0,0 +1,88 @@
+//! # Registry
+//!
+//! Sharded index of registered metrics, so registering never waits on a flush holding the collector's state
+
+use std::collections::hash_map::{DefaultHasher, Entry, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// Registrations to distinct keys only contend when they hash to the same shard
+const SHARDS: usize = 16;
+
+struct Shard<V, P> {
+    index: HashMap<metrics::Key, V>,
+    /// Registrations not yet taken via [Registry::drain], with their sequence number
+    pending: Vec<(u64, P)>,
+}
+
+/// Handles of registered metrics by key, along with the registrations not yet merged into the collector's state
+/// * Looking up an existing key takes a shard's read lock, registering a new one its write lock
+/// * Registrations are drained into the collector's state the next time it's locked, in the order they happened
+pub(crate) struct Registry<V, P> {
+    shards: Box<[RwLock<Shard<V, P>>]>,
+    sequence: AtomicU64,
+    pending: AtomicUsize,
+}
+
+#[allow(clippy::mutable_key_type)] // metrics::Key has interior mutability
+impl<V: Clone, P> Registry<V, P> {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    RwLock::new(Shard {
+                        index: HashMap::new(),
+                        pending: Vec::new(),
+                    })
+                })
+                .collect(),
+            sequence: AtomicU64::new(0),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &metrics::Key) -> &RwLock<Shard<V, P>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    /// The handle registered for a key, or else register the handle and pending registration from `register`
+    pub fn get_or_register(&self, key: &metrics::Key, register: impl FnOnce() -> (V, P)) -> V {
+        let shard = self.shard(key);
+        if let Some(value) = shard.read().unwrap_or_else(PoisonError::into_inner).index.get(key) {
+            return value.clone();
+        }
+
+        let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+        let shard = &mut *shard;
+        match shard.index.entry(key.clone()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let (value, pending) = register();
+                let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+                shard.pending.push((sequence, pending));
+                self.pending.fetch_add(1, Ordering::Release);
+                entry.insert(value).clone()
+            }
+        }
+    }
+
+    /// Whether there are registrations to drain
+    pub fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire) > 0
+    }
+
+    /// Take the pending registrations in the order they happened
+    pub fn drain(&self) -> Vec<P> {
+        let mut pending = Vec::new();
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+            self.pending.fetch_sub(shard.pending.len(), Ordering::Release);
+            pending.append(&mut shard.pending);
+        }
+        pending.sort_unstable_by_key(|(sequence, _)| *sequence);
+        pending.into_iter().map(|(_, pending)| pending).collect()
+    }
+}
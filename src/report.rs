@@ -0,0 +1,30 @@
+//! # Report
+//!
+//! Internal error and warning reporting via the `tracing` crate, falling back to the `log` crate when the `tracing`
+//! feature is disabled and dropping the message when neither feature is enabled
+
+/// Report an error message
+macro_rules! report_error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)+);
+        #[cfg(all(not(feature = "tracing"), feature = "log"))]
+        log::error!($($arg)+);
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+/// Report a warning message
+macro_rules! report_warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+        #[cfg(all(not(feature = "tracing"), feature = "log"))]
+        log::warn!($($arg)+);
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+pub(crate) use {report_error, report_warn};
@@ -0,0 +1,95 @@
+//! # Router
+//!
+//! Routing metrics to several independent collectors within one process
+
+use super::collector::{Collector, Recorder};
+use super::Error;
+use metrics::SharedString;
+
+/// [metrics::Recorder] routing each metric by name prefix to one of several [Collector]s, each with its own
+/// namespace, dimensions and flush destination
+///
+/// * Routes are checked in the order added, the first matching prefix wins
+/// * Metrics matching no route go to the default collector
+/// * Metric names are passed through unchanged
+///
+/// Collectors are constructed via [Builder::collector](super::Builder::collector), which doesn't install a global
+/// recorder, and may also be used on their own via [metrics::with_local_recorder] and [Collector::recorder]
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::{Builder, Router};
+///
+/// let app = Box::leak(Box::new(Builder::new().cloudwatch_namespace("MyApplication").collector().unwrap()));
+/// let plugin = Box::leak(Box::new(Builder::new().cloudwatch_namespace("MyPlugin").collector().unwrap()));
+///
+/// Router::new(app).route("plugin.", plugin).init().unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics::counter!("plugin.loads").increment(1);
+///
+/// app.flush(std::io::stdout()).unwrap();
+/// plugin.flush(std::io::stderr()).unwrap();
+/// ```
+pub struct Router<'a> {
+    routes: Vec<(SharedString, Recorder<'a>)>,
+    default: Recorder<'a>,
+}
+
+impl<'a> Router<'a> {
+    /// Constructs a router sending every metric to `default` until routes are added
+    pub fn new(default: &'a Collector) -> Self {
+        Self {
+            routes: Vec::new(),
+            default: default.into(),
+        }
+    }
+
+    /// Send metrics whose name starts with `prefix` to `collector`
+    pub fn route(mut self, prefix: impl Into<SharedString>, collector: &'a Collector) -> Self {
+        self.routes.push((prefix.into(), collector.into()));
+        self
+    }
+
+    /// Recorder for a metric name
+    fn recorder(&self, name: &str) -> &Recorder<'a> {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_ref()))
+            .map_or(&self.default, |(_, recorder)| recorder)
+    }
+}
+
+impl Router<'static> {
+    /// Install the router via [metrics::set_global_recorder]
+    pub fn init(self) -> Result<(), Error> {
+        metrics::set_global_recorder(self).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+impl metrics::Recorder for Router<'_> {
+    fn describe_counter(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder(key.as_str()).describe_counter(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder(key.as_str()).describe_gauge(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder(key.as_str()).describe_histogram(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Counter {
+        self.recorder(key.name()).register_counter(key, metadata)
+    }
+
+    fn register_gauge(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Gauge {
+        self.recorder(key.name()).register_gauge(key, metadata)
+    }
+
+    fn register_histogram(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Histogram {
+        self.recorder(key.name()).register_histogram(key, metadata)
+    }
+}
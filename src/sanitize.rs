@@ -0,0 +1,135 @@
+//! # Sanitize
+//!
+//! Fixing up metric names and labels CloudWatch would reject via
+//! [Builder::with_name_sanitizer](super::Builder::with_name_sanitizer)
+
+use metrics::SharedString;
+use std::borrow::Cow;
+
+/// Sanitizes metric names, label names and label values at registration to the characters and lengths CloudWatch
+/// accepts, passed to [Builder::with_name_sanitizer](crate::Builder::with_name_sanitizer)
+/// * Names are restricted to printable ASCII without a leading `:`, values to non-control characters
+/// * Other characters are replaced, with `_` by default, or stripped
+/// * Names are truncated to 255 characters and values to 1024 by default
+/// * Metrics with a name, label name or label value that's empty or whitespace after sanitizing are rejected
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::NameSanitizer;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///     .cloudwatch_namespace("MyApplication")
+///     .with_name_sanitizer(NameSanitizer::new().replacement('-').max_value_chars(256));
+/// ```
+#[derive(Debug, Clone)]
+pub struct NameSanitizer {
+    replacement: Option<char>,
+    max_name_chars: usize,
+    max_value_chars: usize,
+}
+
+impl Default for NameSanitizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NameSanitizer {
+    pub fn new() -> Self {
+        Self {
+            replacement: Some('_'),
+            max_name_chars: 255,
+            max_value_chars: 1024,
+        }
+    }
+
+    /// Replace invalid characters with another character, `_` by default
+    pub fn replacement(mut self, replacement: char) -> Self {
+        self.replacement = Some(replacement);
+        self
+    }
+
+    /// Strip invalid characters rather than replacing them
+    pub fn strip(mut self) -> Self {
+        self.replacement = None;
+        self
+    }
+
+    /// Truncate metric and label names longer than this many characters, 255 by default
+    pub fn max_name_chars(mut self, max: usize) -> Self {
+        self.max_name_chars = max;
+        self
+    }
+
+    /// Truncate label values longer than this many characters, 1024 by default
+    pub fn max_value_chars(mut self, max: usize) -> Self {
+        self.max_value_chars = max;
+        self
+    }
+
+    /// Sanitize a metric or label name, None if nothing usable is left
+    pub(crate) fn name<'a>(&self, name: &'a str) -> Option<Cow<'a, str>> {
+        let name = name.trim_start_matches(':');
+        self.clean(name, |c| c.is_ascii() && !c.is_ascii_control(), self.max_name_chars)
+    }
+
+    /// Sanitize a label value, None if nothing usable is left
+    pub(crate) fn value<'a>(&self, value: &'a str) -> Option<Cow<'a, str>> {
+        self.clean(value, |c| !c.is_control(), self.max_value_chars)
+    }
+
+    /// Sanitize the name and labels of a key, or describe why it's rejected
+    pub(crate) fn key<'a>(&self, key: Cow<'a, metrics::Key>) -> Result<Cow<'a, metrics::Key>, String> {
+        let sanitized = {
+            let name = self
+                .name(key.name())
+                .ok_or_else(|| format!("metric name {:?} is empty after sanitizing", key.name()))?;
+
+            let mut changed = name != key.name();
+            let mut labels = Vec::new();
+            for label in key.labels() {
+                let label_name = self.name(label.key()).ok_or_else(|| {
+                    format!(
+                        "label name {:?} of {} is empty after sanitizing",
+                        label.key(),
+                        key.name()
+                    )
+                })?;
+                let value = self.value(label.value()).ok_or_else(|| {
+                    format!(
+                        "label {} of {} has an empty value {:?}",
+                        label.key(),
+                        key.name(),
+                        label.value()
+                    )
+                })?;
+                changed |= label_name != label.key() || value != label.value();
+                labels.push(metrics::Label::new(shared(label_name), shared(value)));
+            }
+            changed.then(|| metrics::Key::from_parts(shared(name), labels))
+        };
+
+        Ok(sanitized.map_or(key, Cow::Owned))
+    }
+
+    /// Replace or strip the characters not allowed and truncate, borrowing if nothing changed
+    fn clean<'a>(&self, s: &'a str, allowed: impl Fn(char) -> bool, max: usize) -> Option<Cow<'a, str>> {
+        let s = match s.chars().all(&allowed) && s.chars().count() <= max {
+            true => Cow::Borrowed(s),
+            false => Cow::Owned(
+                s.chars()
+                    .filter_map(|c| match allowed(c) {
+                        true => Some(c),
+                        false => self.replacement,
+                    })
+                    .take(max)
+                    .collect(),
+            ),
+        };
+        (!s.trim().is_empty()).then_some(s)
+    }
+}
+
+fn shared(s: Cow<'_, str>) -> SharedString {
+    SharedString::from(s.into_owned())
+}
@@ -0,0 +1,520 @@
+//! # Sink
+//!
+//! The [Sink] documents are emitted to and [std::io::Write] adapters for the destinations documents are flushed to
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Destination documents are emitted to, configured once via [Builder::with_sink](crate::Builder::with_sink) and
+/// used by [Collector::flush_to_sink](crate::Collector::flush_to_sink) and the
+/// [MetricsLayer](crate::lambda::MetricsLayer)
+/// * Implemented for closures taking each document
+/// * Defaults to [StdoutSink]
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .with_sink(|document: &[u8]| {
+///          eprint!("{}", String::from_utf8_lossy(document));
+///          Ok(())
+///      })
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush_to_sink().unwrap();
+/// ```
+pub trait Sink: Send + Sync {
+    /// Emit one complete, newline terminated document
+    fn emit(&self, document: &[u8]) -> io::Result<()>;
+
+    /// Deliver any documents buffered by the sink, called at the end of each flush
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: Fn(&[u8]) -> io::Result<()> + Send + Sync> Sink for F {
+    fn emit(&self, document: &[u8]) -> io::Result<()> {
+        self(document)
+    }
+}
+
+/// [Sink] writing to stdout, where Lambda picks up embedded metrics, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn emit(&self, document: &[u8]) -> io::Result<()> {
+        // Written under one lock so documents from concurrent flushes don't interleave
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(document)?;
+        stdout.flush()
+    }
+}
+
+/// Environment variable read by [AgentSink::from_env], e.g. `udp://127.0.0.1:25888`
+pub const AGENT_ENDPOINT_ENV: &str = "AWS_EMF_AGENT_ENDPOINT";
+
+/// CloudWatch Agent EMF listener endpoint when [AGENT_ENDPOINT_ENV] isn't set
+const DEFAULT_AGENT_ENDPOINT: &str = "tcp://127.0.0.1:25888";
+
+/// Largest UDP payload over IPv4
+const MAX_DATAGRAM_BYTES: usize = 65_507;
+
+type Connect = Box<dyn FnMut() -> io::Result<TcpStream> + Send>;
+
+enum AgentTransport {
+    Tcp(Mutex<RetryWriter<TcpStream, Connect>>),
+    Udp { socket: UdpSocket, address: String },
+}
+
+/// [Sink] sending documents to the CloudWatch Agent's EMF listener, how services outside of Lambda (EC2, ECS, on
+/// premises) publish embedded metrics
+/// * `tcp://host:port` endpoints connect lazily, buffer and reconnect via a [RetryWriter] with its defaults, a
+///   failed emit returns the error and the document is retried with the next one
+/// * `udp://host:port` endpoints send one datagram per document, documents larger than a datagram are rejected
+///   with an error, lower [Builder::with_max_metrics_per_document](crate::Builder::with_max_metrics_per_document)
+///   to keep them smaller
+///
+/// # Example
+/// ```no_run
+/// use metrics_cloudwatch_embedded::AgentSink;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::ecs_defaults()
+///      .cloudwatch_namespace("MyService")
+///      .with_sink(AgentSink::from_env().unwrap())
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush_to_sink().unwrap();
+/// ```
+pub struct AgentSink {
+    transport: AgentTransport,
+}
+
+impl AgentSink {
+    /// Constructs a sink for the endpoint in [AGENT_ENDPOINT_ENV], or `tcp://127.0.0.1:25888` if it isn't set
+    pub fn from_env() -> io::Result<Self> {
+        match std::env::var(AGENT_ENDPOINT_ENV) {
+            Ok(endpoint) if !endpoint.is_empty() => Self::new(&endpoint),
+            _ => Self::new(DEFAULT_AGENT_ENDPOINT),
+        }
+    }
+
+    /// Constructs a sink for a `tcp://host:port` or `udp://host:port` endpoint
+    pub fn new(endpoint: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid agent endpoint {endpoint}, expected tcp://host:port or udp://host:port"),
+            )
+        };
+        let (scheme, address) = endpoint.split_once("://").ok_or_else(invalid)?;
+        let address = address.trim_end_matches('/').to_owned();
+        if address.is_empty() {
+            return Err(invalid());
+        }
+
+        let transport = match scheme {
+            "tcp" => {
+                let connect: Connect = Box::new(move || TcpStream::connect(&address));
+                AgentTransport::Tcp(Mutex::new(RetryWriter::new(connect)))
+            }
+            "udp" => {
+                let local = if address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                AgentTransport::Udp {
+                    socket: UdpSocket::bind(local)?,
+                    address,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Self { transport })
+    }
+}
+
+impl Sink for AgentSink {
+    fn emit(&self, document: &[u8]) -> io::Result<()> {
+        match &self.transport {
+            AgentTransport::Tcp(writer) => {
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                writer.write_all(document)?;
+                writer.flush()
+            }
+            AgentTransport::Udp { socket, address } => {
+                if document.len() > MAX_DATAGRAM_BYTES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "document of {} bytes exceeds the UDP limit of {MAX_DATAGRAM_BYTES} bytes",
+                            document.len()
+                        ),
+                    ));
+                }
+                socket.send_to(document, address.as_str()).map(|_| ())
+            }
+        }
+    }
+}
+
+/// [std::io::Write] emitting each newline terminated document written through it to a [Sink], obtained via
+/// [Collector::sink_writer](crate::Collector::sink_writer) for APIs taking a writer such as
+/// [Collector::write_single](crate::Collector::write_single)
+/// * Anything left without a trailing newline is emitted when dropped, followed by [Sink::flush]
+pub struct SinkWriter {
+    sink: Arc<dyn Sink>,
+    line: Vec<u8>,
+}
+
+impl SinkWriter {
+    pub(crate) fn new(sink: Arc<dyn Sink>) -> Self {
+        Self { sink, line: Vec::new() }
+    }
+}
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..=end]);
+            rest = &rest[end + 1..];
+            let document = std::mem::take(&mut self.line);
+            self.sink.emit(&document)?;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+impl Drop for SinkWriter {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.sink.emit(&self.line);
+        }
+        let _ = self.sink.flush();
+    }
+}
+
+/// Writer re-indenting each newline terminated document written through it, for reading documents in a terminal or
+/// file while debugging
+/// * Keep writing compact documents to stdout for CloudWatch, which expects one document per line
+/// * Property order is preserved, only whitespace is added
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::PrettyWriter;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush(PrettyWriter::new(std::io::stderr())).unwrap();
+/// ```
+pub struct PrettyWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+}
+
+impl<W: Write> PrettyWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+        }
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for PrettyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..end]);
+            rest = &rest[end + 1..];
+            let pretty = indent(&self.line);
+            self.line.clear();
+            self.inner.write_all(&pretty)?;
+            self.inner.write_all(b"\n")?;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Re-indent compact JSON with two spaces, leaving the contents of strings untouched
+fn indent(json: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(json.len() * 2);
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    let newline = |output: &mut Vec<u8>, depth: usize| {
+        output.push(b'\n');
+        output.resize(output.len() + depth * 2, b' ');
+    };
+
+    for (i, &b) in json.iter().enumerate() {
+        if in_string {
+            output.push(b);
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => {
+                in_string = true;
+                output.push(b);
+            }
+            b'{' | b'[' => {
+                output.push(b);
+                // Keep empty objects and arrays on one line
+                if !matches!(json.get(i + 1), Some(b'}' | b']')) {
+                    depth += 1;
+                    newline(&mut output, depth);
+                }
+            }
+            b'}' | b']' => {
+                if !matches!(json.get(i.wrapping_sub(1)), Some(b'{' | b'[')) {
+                    depth = depth.saturating_sub(1);
+                    newline(&mut output, depth);
+                }
+                output.push(b);
+            }
+            b',' => {
+                output.push(b);
+                newline(&mut output, depth);
+            }
+            b':' => output.extend_from_slice(b": "),
+            _ => output.push(b),
+        }
+    }
+    output
+}
+
+/// What [RetryWriter] discards once its buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Discard the oldest buffered documents to make room, the default
+    #[default]
+    DropOldest,
+    /// Discard the document being written
+    DropNewest,
+}
+
+/// Counters reported by [RetryWriter::stats], e.g. for emitting as gauges to monitor the sink itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Documents delivered
+    pub sent: u64,
+    /// Failed attempts that were retried
+    pub retries: u64,
+    /// Documents discarded because the buffer was full
+    pub dropped: u64,
+    /// Documents currently buffered awaiting delivery
+    pub buffered: usize,
+}
+
+/// Writer for network destinations buffering documents in memory and retrying failed sends with exponential
+/// backoff, reconnecting via the given function after each failure
+/// * Pass by `&mut` so buffered documents carry over between flushes
+/// * Documents are sent as they're written, those that couldn't be delivered after the retries are kept for the
+///   next write or [Write::flush], which returns the error while any remain
+/// * Once the retries are exhausted no further attempt is made until the maximum backoff has elapsed, so an outage
+///   doesn't stall every flush
+/// * Sleeps between retries, blocking the flushing thread
+///
+/// # Example
+/// ```no_run
+/// use metrics_cloudwatch_embedded::RetryWriter;
+/// use std::io::Write;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// let mut writer = RetryWriter::new(|| std::net::TcpStream::connect("127.0.0.1:25888")).max_retries(5);
+///
+/// metrics::counter!("requests").increment(1);
+/// metrics.flush(&mut writer).unwrap();
+/// writer.flush().unwrap();
+/// ```
+pub struct RetryWriter<W, C> {
+    connect: C,
+    connection: Option<W>,
+    line: Vec<u8>,
+    pending: VecDeque<Vec<u8>>,
+    pending_bytes: usize,
+    max_buffer_bytes: usize,
+    drop_policy: DropPolicy,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    paused_until: Option<Instant>,
+    stats: RetryStats,
+}
+
+impl<W: Write, C: FnMut() -> io::Result<W>> RetryWriter<W, C> {
+    /// Constructs a writer connecting lazily via `connect`, buffering up to 1 MiB with 3 retries backing off from
+    /// 10ms up to 1s
+    pub fn new(connect: C) -> Self {
+        Self {
+            connect,
+            connection: None,
+            line: Vec::new(),
+            pending: VecDeque::new(),
+            pending_bytes: 0,
+            max_buffer_bytes: 1024 * 1024,
+            drop_policy: DropPolicy::DropOldest,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            paused_until: None,
+            stats: RetryStats::default(),
+        }
+    }
+
+    /// Bytes of undelivered documents to buffer before discarding by the [DropPolicy]
+    pub fn max_buffer_bytes(mut self, max_buffer_bytes: usize) -> Self {
+        self.max_buffer_bytes = max_buffer_bytes;
+        self
+    }
+
+    pub fn drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Retries per document after the first attempt fails
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for each retry up to max
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            buffered: self.pending.len(),
+            ..self.stats
+        }
+    }
+
+    /// Buffer a complete document, discarding by the drop policy if it doesn't fit
+    fn enqueue(&mut self, document: Vec<u8>) {
+        if self.drop_policy == DropPolicy::DropOldest {
+            while self.pending_bytes + document.len() > self.max_buffer_bytes {
+                let Some(oldest) = self.pending.pop_front() else {
+                    break;
+                };
+                self.pending_bytes -= oldest.len();
+                self.stats.dropped += 1;
+            }
+        }
+        if self.pending_bytes + document.len() > self.max_buffer_bytes {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.pending_bytes += document.len();
+        self.pending.push_back(document);
+    }
+
+    /// Send buffered documents in order, retrying each with backoff
+    fn send_pending(&mut self) -> io::Result<()> {
+        if self.paused_until.is_some_and(|until| Instant::now() < until) && !self.pending.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other, "sink unavailable, retrying later"));
+        }
+        self.paused_until = None;
+
+        while let Some(document) = self.pending.pop_front() {
+            let mut backoff = self.initial_backoff;
+            let mut retries = 0;
+            while let Err(e) = self.send(&document) {
+                if retries == self.max_retries {
+                    self.pending.push_front(document);
+                    self.paused_until = Some(Instant::now() + self.max_backoff);
+                    return Err(e);
+                }
+                retries += 1;
+                self.stats.retries += 1;
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(self.max_backoff);
+            }
+            self.pending_bytes -= document.len();
+            self.stats.sent += 1;
+        }
+        Ok(())
+    }
+
+    /// Attempt to send a document, dropping the connection on failure so the next attempt reconnects
+    fn send(&mut self, document: &[u8]) -> io::Result<()> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert((self.connect)()?),
+        };
+        let result = connection.write_all(document).and_then(|_| connection.flush());
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+}
+
+impl<W: Write, C: FnMut() -> io::Result<W>> Write for RetryWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        let mut complete = false;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..=end]);
+            rest = &rest[end + 1..];
+            let document = std::mem::take(&mut self.line);
+            self.enqueue(document);
+            complete = true;
+        }
+        self.line.extend_from_slice(rest);
+
+        // Undelivered documents stay buffered, surfaced by flush
+        if complete {
+            let _ = self.send_pending();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_pending()
+    }
+}
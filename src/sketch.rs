@@ -0,0 +1,133 @@
+//! # Sketch
+//!
+//! Bounded memory quantile sketch backing histograms via
+//! [Builder::with_histogram_sketch](super::Builder::with_histogram_sketch)
+
+use std::collections::BTreeMap;
+
+/// Quantiles are within 1% of the true value
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Bins per sign before the lowest magnitudes are collapsed together, bounding memory
+const MAX_BINS: usize = 2048;
+
+/// DDSketch (<https://arxiv.org/abs/1908.10693>) counting values in logarithmically sized bins
+pub(crate) struct Sketch {
+    gamma: f64,
+    ln_gamma: f64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+    non_finite: u64,
+}
+
+impl Sketch {
+    pub fn new() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        Self {
+            gamma,
+            ln_gamma: gamma.ln(),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+            non_finite: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Number of values counted since the last clear
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of non-finite values dropped since the last clear
+    pub fn non_finite(&self) -> u64 {
+        self.non_finite
+    }
+
+    /// Count a value, non-finite values are dropped and only counted via [Sketch::non_finite]
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            self.non_finite += 1;
+            return;
+        }
+
+        // Subnormal values can't be indexed, they're indistinguishable from 0 at this accuracy anyway
+        if value.abs() < f64::MIN_POSITIVE {
+            self.zeros += 1;
+        } else {
+            let index = (value.abs().ln() / self.ln_gamma).ceil() as i32;
+            let bins = if value > 0.0 {
+                &mut self.positive
+            } else {
+                &mut self.negative
+            };
+            *bins.entry(index).or_default() += 1;
+
+            // Merge the lowest magnitudes, keeping the accuracy of the higher quantiles that matter for latencies
+            if bins.len() > MAX_BINS {
+                if let Some((_, count)) = bins.pop_first() {
+                    if let Some(mut lowest) = bins.first_entry() {
+                        *lowest.get_mut() += count;
+                    }
+                }
+            }
+        }
+        self.count += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.positive.clear();
+        self.negative.clear();
+        self.zeros = 0;
+        self.count = 0;
+        self.non_finite = 0;
+    }
+
+    /// Representative value of a bin, within the relative accuracy of every value counted in it
+    fn value(&self, index: i32) -> f64 {
+        2.0 * self.gamma.powi(index) / (self.gamma + 1.0)
+    }
+
+    /// Bins in ascending order of value, as representative value and count
+    fn bins(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let negative = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(&index, &count)| (-self.value(index), count));
+        let zeros = (self.zeros > 0).then_some((0.0, self.zeros));
+        let positive = self.positive.iter().map(|(&index, &count)| (self.value(index), count));
+        negative.chain(zeros).chain(positive)
+    }
+
+    /// Value at quantile `q` (0-1), None if empty
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let rank = q.clamp(0.0, 1.0) * (self.count.saturating_sub(1)) as f64;
+        let mut seen = 0;
+        self.bins().find_map(|(value, count)| {
+            seen += count;
+            (seen as f64 > rank).then_some(value)
+        })
+    }
+
+    /// Bins as values and counts, adjacent bins are merged to keep at most `max_buckets`
+    pub fn distribution(&self, max_buckets: usize) -> (Vec<f64>, Vec<u64>) {
+        let bins: Vec<(f64, u64)> = self.bins().collect();
+        let max_buckets = max_buckets.max(1);
+        let per_bucket = ((bins.len() + max_buckets - 1) / max_buckets).max(1);
+
+        bins.chunks(per_bucket)
+            .map(|chunk| {
+                let count: u64 = chunk.iter().map(|(_, count)| count).sum();
+                let sum: f64 = chunk.iter().map(|(value, count)| value * *count as f64).sum();
+                (sum / count as f64, count)
+            })
+            .unzip()
+    }
+}
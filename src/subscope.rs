@@ -0,0 +1,123 @@
+//! # Subscope
+//!
+//! Scoping a library's metrics within the host application's collector
+
+use super::collector::{Collector, Recorder};
+use metrics::SharedString;
+
+static HANDLE_METADATA: metrics::Metadata<'static> =
+    metrics::Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
+
+/// Label name added by [Subscope::component_dimension]
+pub const COMPONENT: &str = "Component";
+
+/// [metrics::Recorder] registering metrics with a [Collector] under a library's name, obtained via
+/// [Collector::subscope]
+///
+/// * Metric names are prefixed with the name and a `.`, e.g. `MyLib.requests`
+/// * Or via [Subscope::component_dimension], names are passed through unchanged and a `Component` dimension is added
+///   instead, so the same metric from several libraries can be graphed together
+/// * Metrics land in the host application's namespace with its default dimensions and properties
+///
+/// Libraries can take a [Subscope] from the host application and record through it via
+/// [metrics::with_local_recorder] or the [Subscope::counter], [Subscope::gauge] and [Subscope::histogram] handles
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::with_local_recorder(&metrics.subscope("MyLib"), || {
+///     // Emitted as MyLib.requests
+///     metrics::counter!("requests").increment(1);
+/// });
+///
+/// // Emitted as requests with a Component dimension of MyLib
+/// metrics.subscope("MyLib").component_dimension().counter("requests", Vec::new()).increment(1);
+/// ```
+pub struct Subscope<'a> {
+    recorder: Recorder<'a>,
+    name: SharedString,
+    component_dimension: bool,
+}
+
+impl<'a> Subscope<'a> {
+    pub(crate) fn new(collector: &'a Collector, name: SharedString) -> Self {
+        Self {
+            recorder: collector.into(),
+            name,
+            component_dimension: false,
+        }
+    }
+
+    /// Add a `Component` dimension with the name instead of prefixing metric names
+    pub fn component_dimension(mut self) -> Self {
+        self.component_dimension = true;
+        self
+    }
+
+    /// Registers a counter up front, see [Collector::counter]
+    pub fn counter(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Counter {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_counter(self, &key, &HANDLE_METADATA)
+    }
+
+    /// Registers a gauge up front, see [Collector::gauge]
+    pub fn gauge(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Gauge {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_gauge(self, &key, &HANDLE_METADATA)
+    }
+
+    /// Registers a histogram up front, see [Collector::histogram]
+    pub fn histogram(&self, name: impl Into<metrics::KeyName>, labels: impl metrics::IntoLabels) -> metrics::Histogram {
+        let key = metrics::Key::from_parts(name, labels);
+        metrics::Recorder::register_histogram(self, &key, &HANDLE_METADATA)
+    }
+
+    /// Key name within the scope
+    fn key_name(&self, key: metrics::KeyName) -> metrics::KeyName {
+        if self.component_dimension {
+            key
+        } else {
+            format!("{}.{}", self.name, key.as_str()).into()
+        }
+    }
+
+    /// Key within the scope
+    fn key(&self, key: &metrics::Key) -> metrics::Key {
+        if self.component_dimension {
+            key.with_extra_labels(vec![metrics::Label::new(COMPONENT, self.name.clone())])
+        } else {
+            let (name, labels) = key.clone().into_parts();
+            metrics::Key::from_parts(self.key_name(name), labels)
+        }
+    }
+}
+
+impl metrics::Recorder for Subscope<'_> {
+    fn describe_counter(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder.describe_counter(self.key_name(key), unit, description)
+    }
+
+    fn describe_gauge(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder.describe_gauge(self.key_name(key), unit, description)
+    }
+
+    fn describe_histogram(&self, key: metrics::KeyName, unit: Option<metrics::Unit>, description: SharedString) {
+        self.recorder.describe_histogram(self.key_name(key), unit, description)
+    }
+
+    fn register_counter(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Counter {
+        self.recorder.register_counter(&self.key(key), metadata)
+    }
+
+    fn register_gauge(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Gauge {
+        self.recorder.register_gauge(&self.key(key), metadata)
+    }
+
+    fn register_histogram(&self, key: &metrics::Key, metadata: &metrics::Metadata) -> metrics::Histogram {
+        self.recorder.register_histogram(&self.key(key), metadata)
+    }
+}
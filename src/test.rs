@@ -4,6 +4,11 @@ use super::*;
 mod tests {
     use super::*;
 
+    /// Builds a collector for use with [metrics::with_local_recorder] so tests don't fight over the global recorder
+    fn local_collector(builder: builder::Builder) -> &'static collector::Collector {
+        Box::leak(Box::new(builder.collector().unwrap()))
+    }
+
     #[test]
     fn simple_test() {
         let port = format!("{}", 7779);
@@ -33,9 +38,3053 @@ mod tests {
         let output_str = std::str::from_utf8(&output).unwrap();
         assert_eq!(
             output_str,
-            r#"{"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Address","Port","module","api"]],"Metrics":[{"Name":"not_found","Unit":"Count"},{"Name":"runtime","Unit":"Milliseconds"},{"Name":"success","Unit":"Count"},{"Name":"thing"}]}]},"Address":"10.172.207.225","Port":"7779","api":"a_function","module":"directory","not_found":1,"runtime":[4.0,5.0],"success":2,"thing":4614275588213125939}
+            r#"{"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Address","Port","module","api"]],"Metrics":[{"Name":"not_found","Unit":"Count"},{"Name":"runtime","Unit":"Milliseconds"},{"Name":"success","Unit":"Count"},{"Name":"thing"}]}]},"Address":"10.172.207.225","Port":"7779","api":"a_function","module":"directory","not_found":1,"runtime":[4.0,5.0],"success":2,"thing":7.11}
 {"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Address","Port","module","api"]],"Metrics":[{"Name":"success","Unit":"Count"}]}]},"Address":"10.172.207.225","Port":"7779","api":"b_function","module":"directory","success":1}
 "#
         );
     }
+
+    #[test]
+    fn estimate_cost() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Function", "My_Function_Name")
+                .custom_metric_warning_threshold(2),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::counter!("requests", "Method" => "Default").increment(1);
+            metrics::counter!("requests", "Method" => "Default").increment(1);
+            metrics::counter!("requests", "Method" => "Another").increment(1);
+            metrics::histogram!("latency", "Method" => "Default").record(1.0);
+        });
+
+        let estimate = metrics.estimate_cost();
+        assert_eq!(estimate.custom_metrics, 3);
+        assert_eq!(estimate.monthly_cost, 3.0 * CUSTOM_METRIC_MONTHLY_COST);
+    }
+
+    #[test]
+    fn cardinality_report() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Function", "My_Function_Name")
+                .with_timestamp(1687657545423)
+                .with_cardinality_audit(),
+        );
+        let recorder = collector::Recorder::from(metrics);
+
+        for flush in 0..3 {
+            metrics::with_local_recorder(&recorder, || {
+                metrics::counter!("requests", "Method" => "Default").increment(1);
+                if flush == 0 {
+                    metrics::counter!("requests", "Method" => "Another").increment(1);
+                }
+            });
+            metrics.flush(std::io::sink()).unwrap();
+        }
+
+        let report = metrics.cardinality_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].metric, "requests");
+        assert_eq!(
+            report[0].dimensions,
+            vec![
+                ("Function".to_string(), "My_Function_Name".to_string()),
+                ("Method".to_string(), "Default".to_string())
+            ]
+        );
+        assert_eq!(report[0].documents, 3);
+        assert_eq!(report[1].dimensions[1].1, "Another");
+        assert_eq!(report[1].documents, 1);
+    }
+
+    #[test]
+    fn mirror_recorder() {
+        let legacy = local_collector(builder::Builder::new().cloudwatch_namespace("legacy").with_timestamp(0));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_mirror_recorder(collector::Recorder::from(legacy)),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::describe_counter!("requests", metrics::Unit::Count, "");
+            metrics::counter!("requests", "Method" => "Default").increment(2);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        legacy.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"requests","Unit":"Count"}]}]},"Method":"Default","requests":2}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"legacy","Dimensions":[["Method"]],"Metrics":[{"Name":"requests","Unit":"Count"}]}]},"Method":"Default","requests":2}
+"#
+        );
+    }
+
+    #[test]
+    fn metrics_logger() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Function", "My_Function_Name")
+                .with_timestamp(1687657545423),
+        );
+        metrics.set_property("RequestId", "ABC123");
+
+        let mut logger = MetricsLogger::from_collector(metrics);
+        logger
+            .set_namespace("Logger")
+            .put_dimension("Method", "Default")
+            .put_metric("latency", 4.0, Some(metrics::Unit::Milliseconds))
+            .put_metric("latency", 5.0, Some(metrics::Unit::Milliseconds))
+            .put_metric("requests", 1.0, None);
+
+        let mut output = Vec::new();
+        logger.flush(&mut output).unwrap();
+        logger.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"Logger","Dimensions":[["Function","Method"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"},{"Name":"requests"}]}]},"Function":"My_Function_Name","Method":"Default","RequestId":"ABC123","latency":[4.0,5.0],"requests":1.0}
+"#
+        );
+    }
+
+    #[test]
+    fn metrics_scope() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(1687657545423),
+        );
+
+        let mut output = Vec::new();
+        {
+            let mut first = metrics.scope(&mut output);
+            let mut second = MetricsLogger::new("other").into_scope(Vec::new());
+
+            first
+                .set_property("RequestId", "ABC123")
+                .put_metric("requests", 1.0, None);
+            second.put_metric("requests", 2.0, None);
+
+            let second_output = second.finish().unwrap();
+            assert_eq!(
+                std::str::from_utf8(&second_output).unwrap().lines().count(),
+                1,
+                "finish flushes exactly once"
+            );
+        }
+
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"RequestId":"ABC123","requests":1.0}
+"#
+        );
+    }
+
+    #[test]
+    fn tenant_label() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_tenant_label("Tenant")
+                .with_tenant_namespaces()
+                .max_tenants(1),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::counter!("requests", "Tenant" => "a").increment(1);
+            metrics::counter!("requests", "Tenant" => "b").increment(1);
+            metrics::counter!("requests", "Tenant" => "c").increment(1);
+            metrics::counter!("requests").increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace/Other","Dimensions":[["Tenant"]],"Metrics":[{"Name":"requests"}]}]},"Tenant":"Other","requests":2}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace/a","Dimensions":[["Tenant"]],"Metrics":[{"Name":"requests"}]}]},"Tenant":"a","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn tenant_property() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_tenant_property("Tenant"),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::counter!("requests").increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.set_property("Tenant", "a").flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Tenant"]],"Metrics":[{"Name":"requests"}]}]},"Tenant":"a","requests":1}
+"#
+        );
+
+        assert!(builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .max_tenants(10)
+            .collector()
+            .is_err());
+    }
+
+    #[test]
+    fn tenant_registration_during_flush() {
+        // Tenants are admitted without the state lock, so registering doesn't wait on a flush in progress
+        static COLLECTOR: std::sync::OnceLock<&'static collector::Collector> = std::sync::OnceLock::new();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_tenant_label("Tenant")
+                .with_tenant_namespaces()
+                .max_tenants(1)
+                .on_document(|_| {
+                    let (sender, receiver) = std::sync::mpsc::channel();
+                    std::thread::spawn(move || {
+                        let metrics = COLLECTOR.get().unwrap();
+                        metrics
+                            .counter("requests", vec![metrics::Label::new("Tenant", "b")])
+                            .increment(1);
+                        sender.send(()).unwrap();
+                    });
+                    receiver
+                        .recv_timeout(std::time::Duration::from_secs(5))
+                        .expect("registration blocked by the flush");
+                }),
+        );
+        COLLECTOR.set(metrics).ok();
+
+        metrics
+            .counter("requests", vec![metrics::Label::new("Tenant", "a")])
+            .increment(1);
+        let values = metrics.flush_to_values();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["Tenant"], "a");
+
+        // The guardrail still applies, tenant b was attributed to the overflow tenant
+        let values = metrics.flush_to_values();
+        let overflow = values.iter().find(|value| value["Tenant"] == "Other").unwrap();
+        assert_eq!(overflow["_aws"]["CloudWatchMetrics"][0]["Namespace"], "namespace/Other");
+        assert_eq!(overflow["requests"], 1);
+    }
+
+    #[test]
+    fn global_labels() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_global_label("Region", "us-west-2")
+                .with_global_label("Method", "Default"),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::counter!("requests").increment(1);
+            metrics::counter!("requests", "Method" => "Another").increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method","Region"]],"Metrics":[{"Name":"requests"}]}]},"Method":"Another","Region":"us-west-2","requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Region","Method"]],"Metrics":[{"Name":"requests"}]}]},"Method":"Default","Region":"us-west-2","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn key_rewrite() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_key_rewrite(|key| match key.name() {
+                    "debug" => None,
+                    name => Some(metrics::Key::from_parts(
+                        name.to_uppercase(),
+                        key.labels()
+                            .filter(|label| label.key() != "UserId")
+                            .cloned()
+                            .collect::<Vec<_>>(),
+                    )),
+                }),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::describe_counter!("requests", metrics::Unit::Count, "");
+            metrics::counter!("requests", "UserId" => "1234").increment(1);
+            metrics::counter!("requests", "UserId" => "5678").increment(1);
+            metrics::counter!("debug").increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"REQUESTS","Unit":"Count"}]}]},"REQUESTS":2}
+"#
+        );
+    }
+
+    #[test]
+    fn handles() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        let requests = metrics.counter("requests", &[("Method", "Default")]);
+        let connections = metrics.gauge("connections", &[("Method", "Default")]);
+        let latency = metrics.histogram("latency", &[("Method", "Default")]);
+
+        requests.increment(1);
+        requests.increment(1);
+        connections.set(2.0);
+        latency.record(3.0);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"connections"},{"Name":"latency"},{"Name":"requests"}]}]},"Method":"Default","connections":2,"latency":[3.0],"requests":2}
+"#
+        );
+    }
+
+    #[test]
+    fn static_metrics() {
+        static_metrics! {
+            /// Requests handled
+            REQUESTS: counter("requests", Count, "Requests handled", "Method" => "Default");
+            LATENCY: histogram("latency", Milliseconds, "Request latency",);
+        }
+
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            REQUESTS.increment(1);
+            LATENCY.record(2.0);
+        });
+        REQUESTS.increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},"latency":[2.0]}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"requests","Unit":"Count"}]}]},"Method":"Default","requests":2}
+"#
+        );
+    }
+
+    #[test]
+    fn concurrent_flush() {
+        use std::sync::mpsc;
+
+        /// Writer that signals on its first write, then blocks until released
+        struct BlockingWriter {
+            started: mpsc::Sender<()>,
+            release: mpsc::Receiver<()>,
+        }
+
+        impl std::io::Write for BlockingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.started.send(()).is_ok() {
+                    self.release.recv().unwrap();
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let requests = metrics.counter("requests", &[("Method", "Default")]);
+
+        // A flush in progress makes try_flush return immediately
+        requests.increment(1);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            let flushing = s.spawn(|| {
+                metrics.flush(BlockingWriter {
+                    started: started_tx,
+                    release: release_rx,
+                })
+            });
+            started_rx.recv().unwrap();
+            drop(started_rx);
+            assert!(!metrics.try_flush(std::io::sink()).unwrap());
+            release_tx.send(()).unwrap();
+            flushing.join().unwrap().unwrap();
+        });
+        assert!(metrics.try_flush(std::io::sink()).unwrap());
+
+        // Every increment is emitted exactly once across concurrent flushes
+        let outputs: Vec<Vec<u8>> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut output = Vec::new();
+                        for _ in 0..100 {
+                            requests.increment(1);
+                            metrics.flush(&mut output).unwrap();
+                        }
+                        output
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        let total: u64 = outputs
+            .iter()
+            .flat_map(|output| std::str::from_utf8(output).unwrap().lines())
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["requests"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .sum();
+        assert_eq!(total, 800);
+    }
+
+    #[test]
+    fn flush_sequence() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_flush_sequence(),
+        );
+        let requests = metrics.counter("requests", Vec::new());
+
+        let mut output = Vec::new();
+        for _ in 0..2 {
+            requests.increment(1);
+            metrics.flush(&mut output).unwrap();
+            // Nothing to emit, the next flush continues the sequence without a gap
+            metrics.flush(&mut output).unwrap();
+        }
+        let output_str = std::str::from_utf8(&output).unwrap();
+        assert_eq!(
+            output_str,
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"FlushSequence":1,"ProcessStartTime":0,"requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"FlushSequence":2,"ProcessStartTime":0,"requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn host_metadata() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_host_metadata()
+                .with_property("Service", "test"),
+        );
+        let mut output = Vec::new();
+        metrics.write_single("requests", None, 1, &mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(document["ProcessId"], std::process::id());
+        assert_eq!(document["Service"], "test");
+        assert!(document["Executable"].is_string());
+    }
+
+    #[test]
+    fn build_info() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_build_info("1.2.3", "abc123"),
+        );
+
+        let mut output = Vec::new();
+        metrics.write_single("requests", None, 1, &mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"GitSha":"abc123","ServiceVersion":"1.2.3","requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn flush_to_values() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Service", "test")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+
+        let mut values = metrics.flush_to_values();
+        assert_eq!(values.len(), 1);
+        values[0].as_object_mut().unwrap().insert("Extra".into(), true.into());
+        assert_eq!(
+            values[0].to_string(),
+            r#"{"Extra":true,"Method":"Get","Service":"test","_aws":{"CloudWatchMetrics":[{"Dimensions":[["Service","Method"]],"Metrics":[{"Name":"requests"}],"Namespace":"namespace"}],"Timestamp":0},"requests":1}"#
+        );
+        assert!(metrics.flush_to_values().is_empty());
+    }
+
+    #[test]
+    fn on_document() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_property("UserEmail", "someone@example.com")
+                .on_document(|document| {
+                    document.remove("UserEmail");
+                    document.insert("Computed".into(), 42.into());
+                }),
+        );
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"Computed":42,"Method":"Get","_aws":{"CloudWatchMetrics":[{"Dimensions":[["Method"]],"Metrics":[{"Name":"requests"}],"Namespace":"namespace"}],"Timestamp":0},"requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn iso_time() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(1687657545423)
+                .with_iso_time(),
+        );
+
+        let mut output = Vec::new();
+        metrics.write_single("requests", None, 1, &mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":1687657545423,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"Time":"2023-06-25T01:45:45.423Z","requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn panic_flush() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        assert!(metrics.panic_flush(&mut output).unwrap());
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn install_panic_flush() {
+        let capture = testing::CaptureWriter::default();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_sink(capture.clone()),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        metrics.install_panic_flush();
+        let result = std::panic::catch_unwind(|| panic!("crash"));
+        drop(std::panic::take_hook());
+        assert!(result.is_err());
+
+        // Flushed to the configured sink rather than stdout
+        let documents = capture.documents();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0]["requests"], 1);
+    }
+
+    #[test]
+    fn on_error() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace").on_error({
+            let errors = errors.clone();
+            move |message| errors.lock().unwrap().push(message.to_owned())
+        }));
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.gauge("requests", Vec::new()).set(1.0);
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["Unable to register gauge Key(requests) as it was already registered as a counter".to_owned()]
+        );
+    }
+
+    #[test]
+    fn histogram_overflow() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let latency = metrics.histogram("latency", Vec::new());
+
+        // Values beyond the 100 per document limit are dropped rather than blocking
+        for value in 0..101 {
+            latency.record(value as f64);
+        }
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["latency"].as_array().unwrap().len(), 100);
+        assert!(metrics.flush_to_values().is_empty());
+
+        latency.record(1.0);
+        assert_eq!(metrics.flush_to_values()[0]["latency"], serde_json::json!([1.0]));
+    }
+
+    #[test]
+    fn local_recorder() {
+        let metrics = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .collector()
+            .unwrap();
+
+        metrics::with_local_recorder(&metrics.recorder(), || {
+            metrics::counter!("requests").increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn scoped_collectors() {
+        let collectors: Vec<collector::Collector> = (0..2)
+            .map(|i| {
+                builder::Builder::new()
+                    .cloudwatch_namespace(format!("namespace{i}"))
+                    .with_timestamp(0)
+                    .collector()
+                    .unwrap()
+            })
+            .collect();
+
+        // Each thread records into its own collector, without a process wide recorder
+        std::thread::scope(|s| {
+            for (i, metrics) in collectors.iter().enumerate() {
+                s.spawn(move || {
+                    metrics::with_local_recorder(&metrics.recorder(), || {
+                        metrics::counter!("requests").increment(i as u64 + 1);
+                    })
+                });
+            }
+        });
+
+        for (i, metrics) in collectors.iter().enumerate() {
+            let documents = metrics.flush_to_values();
+            assert_eq!(documents.len(), 1);
+            assert_eq!(
+                documents[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+                format!("namespace{i}")
+            );
+            assert_eq!(documents[0]["requests"], i as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn mock_clock() {
+        let clock = std::sync::Arc::new(MockClock::new(1000));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_clock(clock.clone())
+                .with_flush_sequence(),
+        );
+        let capture = testing::CaptureWriter::default();
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        clock.advance(std::time::Duration::from_secs(1));
+        metrics.flush(capture.clone()).unwrap();
+
+        let documents = capture.documents();
+        assert_eq!(documents[0]["_aws"]["Timestamp"], 2000);
+        assert_eq!(documents[0][PROCESS_START_TIME], 1000);
+    }
+
+    #[test]
+    fn property_dimension() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_property_dimension("AccountId"),
+        );
+        let requests = metrics.counter("requests", &[("Method", "Get")]);
+
+        // Omitted until the property is set
+        requests.increment(1);
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+
+        metrics.set_property("AccountId", "123456789012");
+        requests.increment(1);
+        metrics.flush(&mut output).unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"requests"}]}]},"Method":"Get","requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["AccountId","Method"]],"Metrics":[{"Name":"requests"}]}]},"AccountId":"123456789012","Method":"Get","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn stage_from_env() {
+        std::env::set_var("METRICS_TEST_STAGE", "prod");
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_stage_from_env("METRICS_TEST_STAGE"),
+        );
+
+        let mut output = Vec::new();
+        metrics.write_single("requests", None, 1, &mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Stage"]],"Metrics":[{"Name":"requests"}]}]},"Stage":"prod","requests":1}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn try_flush_within() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        // Out of time before the first document, the value is kept for the next flush
+        let mut output = Vec::new();
+        assert_eq!(
+            metrics
+                .try_flush_within(std::time::Duration::ZERO, &mut output)
+                .unwrap(),
+            0
+        );
+        assert!(output.is_empty());
+
+        assert_eq!(
+            metrics
+                .try_flush_within(std::time::Duration::from_secs(60), &mut output)
+                .unwrap(),
+            1
+        );
+        assert!(std::str::from_utf8(&output).unwrap().contains(r#""requests":1"#));
+    }
+
+    #[test]
+    fn try_flush_within_slow_writer() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        for stage in ["a", "b", "c"] {
+            metrics
+                .counter("requests", vec![metrics::Label::new("Stage", stage)])
+                .increment(1);
+        }
+
+        // Out of time once the first document is written, the others are written first by the next flush
+        let mut writer = testing::FaultyWriter::new(Vec::new()).latency(std::time::Duration::from_millis(200));
+        assert_eq!(
+            metrics
+                .try_flush_within(std::time::Duration::from_millis(100), &mut writer)
+                .unwrap(),
+            1
+        );
+        assert_eq!(writer.writes(), 1);
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(output.contains(r#""Stage":"a""#));
+
+        metrics
+            .counter("requests", vec![metrics::Label::new("Stage", "a")])
+            .increment(1);
+        let mut output = Vec::new();
+        assert_eq!(
+            metrics
+                .try_flush_within(std::time::Duration::from_secs(60), &mut output)
+                .unwrap(),
+            3
+        );
+        let stages: Vec<_> = std::str::from_utf8(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["Stage"].clone())
+            .collect();
+        assert_eq!(stages, ["b", "c", "a"]);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn lint() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace").with_lint());
+        let capture = testing::CaptureWriter::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let capture = capture.clone();
+                move || capture.clone()
+            })
+            .with_ansi(false)
+            .without_time()
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+                metrics::describe_counter!("requests", metrics::Unit::Count, "");
+                metrics::describe_gauge!("connections", "");
+                metrics::counter!("requests").increment(1);
+                metrics::gauge!("connections").set(1.0);
+                metrics::histogram!("latency").record(1.0);
+            });
+            metrics.flush(std::io::sink()).unwrap();
+            metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+                metrics::histogram!("latency").record(1.0);
+            });
+            metrics.flush(std::io::sink()).unwrap();
+        });
+
+        let output = capture.output();
+        assert_eq!(output.matches("WARN").count(), 2);
+        assert!(output.contains("Metric connections was emitted without a unit"));
+        assert!(output.contains("Metric latency was emitted without being described"));
+    }
+
+    #[test]
+    fn set_unit() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        let latency = metrics.histogram("latency", vec![metrics::Label::new("Method", "Get")]);
+
+        latency.record(1.0);
+        metrics.set_unit("latency", metrics::Unit::Milliseconds);
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        latency.record(0.001);
+        metrics
+            .set_unit("latency", metrics::Unit::Seconds)
+            .flush(&mut output)
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},"Method":"Get","latency":[1.0]}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"latency","Unit":"Seconds"}]}]},"Method":"Get","latency":[0.001]}
+"#
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive_dimensions() {
+        #[derive(Dimensions)]
+        #[dimension(name = "HttpMethod")]
+        enum Method {
+            Get,
+            #[dimension(name = "POST")]
+            Post,
+        }
+
+        #[derive(Dimensions)]
+        struct RequestDimensions {
+            method: Method,
+            #[dimension(name = "API")]
+            api: &'static str,
+            status_code: u16,
+        }
+
+        assert_eq!(Method::Post.labels(), vec![metrics::Label::new("HttpMethod", "POST")]);
+
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            let dimensions = RequestDimensions {
+                method: Method::Get,
+                api: "orders",
+                status_code: 200,
+            };
+            metrics::counter!("requests", &dimensions).increment(1);
+        });
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method","API","StatusCode"]],"Metrics":[{"Name":"requests"}]}]},"API":"orders","Method":"Get","StatusCode":"200","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn tick_flusher() {
+        let clock = std::sync::Arc::new(MockClock::new(0));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_clock(clock.clone()),
+        );
+        let frames = metrics.counter("frames", Vec::new());
+        let mut flusher = TickFlusher::new(metrics, testing::CaptureWriter::default())
+            .every_ticks(3)
+            .every(std::time::Duration::from_secs(1));
+
+        frames.increment(1);
+        assert!(!flusher.tick().unwrap());
+        frames.increment(1);
+        assert!(!flusher.tick().unwrap());
+        frames.increment(1);
+        assert!(flusher.tick().unwrap());
+
+        frames.increment(1);
+        clock.advance(std::time::Duration::from_millis(999));
+        assert!(!flusher.tick().unwrap());
+        clock.advance(std::time::Duration::from_millis(1));
+        assert!(flusher.tick().unwrap());
+
+        let documents = flusher.writer().documents();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["frames"], 3);
+        assert_eq!(documents[1]["frames"], 1);
+    }
+
+    #[test]
+    fn router() {
+        let app = local_collector(builder::Builder::new().cloudwatch_namespace("app").with_timestamp(0));
+        let plugin = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("plugin")
+                .with_dimension("Plugin", "example")
+                .with_timestamp(0),
+        );
+
+        metrics::with_local_recorder(&Router::new(app).route("plugin.", plugin), || {
+            metrics::describe_counter!("plugin.loads", metrics::Unit::Count, "");
+            metrics::counter!("plugin.loads").increment(1);
+            metrics::counter!("requests").increment(2);
+        });
+
+        let mut output = Vec::new();
+        app.flush(&mut output).unwrap();
+        plugin.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"app","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":2}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"plugin","Dimensions":[["Plugin"]],"Metrics":[{"Name":"plugin.loads","Unit":"Count"}]}]},"Plugin":"example","plugin.loads":1}
+"#
+        );
+    }
+
+    #[test]
+    fn reserved_properties() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Service", "example")
+                .with_timestamp(0)
+                .on_error({
+                    let errors = errors.clone();
+                    move |message| errors.lock().unwrap().push(message.to_owned())
+                }),
+        );
+
+        metrics.set_property("_aws", "corrupt").set_property("Service", "other");
+        metrics.counter("requests", Vec::new()).increment(1);
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Service"]],"Metrics":[{"Name":"requests"}]}]},"Service":"example","requests":1}
+"#
+        );
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec![
+                "Unable to set property _aws as it is reserved for the document metadata".to_owned(),
+                "Unable to set property Service as it is already a default dimension".to_owned(),
+            ]
+        );
+
+        let result = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_dimension("Service", "example")
+            .with_property("Service", "other")
+            .collector();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "property Service is invalid as it is already a default dimension"
+        );
+    }
+
+    #[test]
+    fn max_metrics_per_document() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_max_metrics_per_document(2)
+                .with_timestamp(0),
+        );
+        for name in ["a", "b", "c"] {
+            metrics
+                .counter(name, vec![metrics::Label::new("Method", "Get")])
+                .increment(1);
+        }
+        metrics.counter("d", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"d"}]}]},"d":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"a"},{"Name":"b"}]}]},"Method":"Get","a":1,"b":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"c"}]}]},"Method":"Get","c":1}
+"#
+        );
+    }
+
+    #[test]
+    fn flush_with_report() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        metrics
+            .counter("requests", vec![metrics::Label::new("Method", "Get")])
+            .increment(1);
+        let _latency = metrics.histogram("latency", vec![metrics::Label::new("Method", "Get")]);
+        let _errors = metrics.counter("errors", Vec::new());
+
+        let mut output = Vec::new();
+        let report = metrics.flush_with_report(&mut output).unwrap();
+        assert_eq!(report.documents, 1);
+        assert_eq!(report.bytes, output.len());
+        assert_eq!(
+            report.label_sets,
+            vec![
+                collector::LabelSetReport {
+                    labels: Vec::new(),
+                    emitted: Vec::new(),
+                    skipped: vec!["errors".to_owned()],
+                },
+                collector::LabelSetReport {
+                    labels: vec![("Method".to_owned(), "Get".to_owned())],
+                    emitted: vec!["requests".to_owned()],
+                    skipped: vec!["latency".to_owned()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unit_policy() {
+        for (unit_policy, unit) in [
+            (collector::UnitPolicy::LatestWins, "Bytes"),
+            (collector::UnitPolicy::FirstWins, "Count"),
+        ] {
+            let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+            let metrics = local_collector(
+                builder::Builder::new()
+                    .cloudwatch_namespace("namespace")
+                    .with_unit_policy(unit_policy)
+                    .on_error({
+                        let errors = errors.clone();
+                        move |message| errors.lock().unwrap().push(message.to_owned())
+                    }),
+            );
+
+            metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+                metrics::describe_counter!("requests", metrics::Unit::Count, "");
+                metrics::describe_counter!("requests", "");
+                metrics::describe_counter!("requests", metrics::Unit::Count, "");
+                metrics::describe_counter!("requests", metrics::Unit::Bytes, "");
+                metrics::counter!("requests").increment(1);
+            });
+
+            let values = metrics.flush_to_values();
+            assert_eq!(values[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Unit"], unit);
+            assert_eq!(
+                *errors.lock().unwrap(),
+                vec![format!(
+                    "Metric requests described as Bytes after Count, keeping {unit}"
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn local_dev_defaults() {
+        let metrics = local_collector(
+            builder::Builder::local_dev_defaults()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["Time"], "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn ecs_defaults() {
+        let metrics = local_collector(
+            builder::Builder::ecs_defaults()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        // An idle flush still tells a quiet service from a dead one
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(document[HEARTBEAT], 1);
+    }
+
+    #[test]
+    fn with_dimensions() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimensions([("Service", "Orders"), ("Cell", "1")])
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Service","Cell"]],"Metrics":[{"Name":"requests"}]}]},"Cell":"1","Service":"Orders","requests":1}
+"#
+        );
+
+        let result = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_dimension("Service", "Orders")
+            .with_dimensions([("Service", "Payments")])
+            .collector();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "dimension Service set more than once"
+        );
+    }
+
+    #[test]
+    fn pretty_writer() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.set_property("Note", "a \"quoted\" {string}, [with]: punctuation");
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut writer = PrettyWriter::new(Vec::new());
+        metrics.flush(&mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&writer.into_inner()).unwrap(),
+            r#"{
+  "_aws": {
+    "Timestamp": 0,
+    "CloudWatchMetrics": [
+      {
+        "Namespace": "namespace",
+        "Dimensions": [
+          []
+        ],
+        "Metrics": [
+          {
+            "Name": "requests"
+          }
+        ]
+      }
+    ]
+  },
+  "Note": "a \"quoted\" {string}, [with]: punctuation",
+  "requests": 1
+}
+"#
+        );
+    }
+
+    #[test]
+    fn redaction() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .redact_property("Token")
+                .with_redaction(|name, value| {
+                    if name == "UserEmail" {
+                        *value = "***".into();
+                    }
+                }),
+        );
+        metrics
+            .set_property("Token", "secret")
+            .set_property("UserEmail", "user@example.com")
+            .set_property("RequestId", "ABC123");
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        metrics.write_single("deploys", None, 1, &mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"RequestId":"ABC123","Token":"[REDACTED]","UserEmail":"***","requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"deploys"}]}]},"RequestId":"ABC123","Token":"[REDACTED]","UserEmail":"***","deploys":1}
+"#
+        );
+    }
+
+    #[test]
+    fn faulty_writer() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        let requests = metrics.counter("requests", Vec::new());
+
+        // Partial writes are retried by the serializer, producing the same output
+        requests.increment(1);
+        let capture = testing::CaptureWriter::default();
+        metrics
+            .flush(testing::FaultyWriter::new(capture.clone()).partial_writes(3))
+            .unwrap();
+        assert_eq!(
+            capture.output(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}
+"#
+        );
+
+        // Failures surface from flush, the values swapped out for the failed document are lost
+        requests.increment(1);
+        let error = metrics
+            .flush(
+                testing::FaultyWriter::new(std::io::sink())
+                    .fail_after(0)
+                    .error_kind(std::io::ErrorKind::BrokenPipe),
+            )
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+        assert!(metrics.flush_to_values().is_empty());
+    }
+
+    #[test]
+    fn retry_writer() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        let requests = metrics.counter("requests", Vec::new());
+        let capture = testing::CaptureWriter::default();
+
+        // The first connection fails, the retry reconnects and delivers
+        let mut connections = 0;
+        let mut writer = RetryWriter::new(|| {
+            connections += 1;
+            let writer = testing::FaultyWriter::new(capture.clone());
+            Ok(if connections == 1 { writer.fail_after(0) } else { writer })
+        })
+        .backoff(std::time::Duration::ZERO, std::time::Duration::ZERO);
+
+        requests.increment(1);
+        metrics.flush(&mut writer).unwrap();
+        std::io::Write::flush(&mut writer).unwrap();
+        assert_eq!(capture.documents().len(), 1);
+        assert_eq!(
+            writer.stats(),
+            RetryStats {
+                sent: 1,
+                retries: 1,
+                dropped: 0,
+                buffered: 0
+            }
+        );
+
+        // Unreachable sinks buffer up to the limit, dropping the oldest documents
+        let mut writer = RetryWriter::new(|| -> std::io::Result<std::io::Sink> {
+            Err(std::io::ErrorKind::ConnectionRefused.into())
+        })
+        .max_retries(0)
+        .max_buffer_bytes(300);
+
+        for _ in 0..3 {
+            requests.increment(1);
+            metrics.flush(&mut writer).unwrap();
+        }
+        assert!(std::io::Write::flush(&mut writer).is_err());
+        assert_eq!(writer.stats().buffered, 2);
+        assert_eq!(writer.stats().dropped, 1);
+    }
+
+    #[test]
+    fn bounded_histogram() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let latency = metrics.bounded_histogram("latency", Vec::new());
+
+        for value in 0..100 {
+            latency.try_record(value as f64).unwrap();
+        }
+        assert_eq!(latency.remaining(), 0);
+        assert_eq!(latency.try_record(100.0), Err(Saturated));
+
+        assert_eq!(metrics.flush_to_values()[0]["latency"].as_array().unwrap().len(), 100);
+        assert_eq!(latency.remaining(), 100);
+        latency.try_record(1.0).unwrap();
+        assert_eq!(metrics.flush_to_values()[0]["latency"], serde_json::json!([1.0]));
+    }
+
+    #[test]
+    fn gauge_compaction() {
+        let clock = std::sync::Arc::new(MockClock::new(0));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_clock(clock.clone())
+                .with_gauge_compaction(std::time::Duration::from_secs(60)),
+        );
+        let connections = metrics.gauge("connections", Vec::new());
+
+        connections.set(1.0);
+        assert_eq!(metrics.flush_to_values()[0]["connections"], 1);
+        clock.advance(std::time::Duration::from_secs(30));
+        assert!(metrics.flush_to_values().is_empty());
+
+        connections.set(2.0);
+        assert_eq!(metrics.flush_to_values()[0]["connections"], 2);
+        clock.advance(std::time::Duration::from_secs(59));
+        assert!(metrics.flush_to_values().is_empty());
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(metrics.flush_to_values()[0]["connections"], 2);
+    }
+
+    #[test]
+    fn emit_zeros_for() {
+        let metrics = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .with_emit_zeros_for("slo.*")
+            .with_emit_zeros_for("heartbeats")
+            .collector()
+            .unwrap();
+
+        let counters = [
+            metrics.counter("slo.errors", Vec::new()),
+            metrics.counter("heartbeats", Vec::new()),
+            metrics.counter("orders", Vec::new()),
+        ];
+        for counter in &counters {
+            counter.increment(1);
+        }
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        output.clear();
+
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"heartbeats"},{"Name":"slo.errors"}]}]},"heartbeats":0,"slo.errors":0}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn heartbeat() {
+        let metrics = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_dimension("Service", "orders")
+            .with_timestamp(0)
+            .with_heartbeat()
+            .collector()
+            .unwrap();
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Service"]],"Metrics":[{"Name":"Heartbeat","Unit":"Count"}]}]},"Service":"orders","Heartbeat":1}"#
+                .to_owned()
+                + "\n"
+        );
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        let report = metrics.flush_with_report(std::io::sink()).unwrap();
+        assert_eq!(report.documents, 1);
+        assert!(report.label_sets[0].emitted.contains(&"requests".to_owned()));
+    }
+
+    #[test]
+    fn write_single_to_namespace() {
+        let metrics = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .collector()
+            .unwrap();
+
+        let mut output = Vec::new();
+        metrics
+            .write_single_to_namespace("admin", "deploys", Some(metrics::Unit::Count), 1, &mut output)
+            .unwrap();
+        metrics.write_single("requests", None, 1, &mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"admin","Dimensions":[[]],"Metrics":[{"Name":"deploys","Unit":"Count"}]}]},"deploys":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn record_duration() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            metrics::describe_histogram!("latency", metrics::Unit::Milliseconds, "");
+        });
+        metrics.record_duration("latency", Vec::new(), std::time::Duration::from_micros(1500));
+        metrics.record_duration("runtime", Vec::new(), std::time::Duration::from_millis(250));
+        drop(metrics.timer("latency", Vec::new()));
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["latency"][0], 1.5);
+        assert!(values[0]["latency"][1].as_f64().unwrap() < 1000.0);
+        assert_eq!(values[0]["runtime"][0], 0.25);
+    }
+
+    #[test]
+    fn record_duration_during_flush() {
+        // Looking up the unit doesn't lock the state, so recording a duration doesn't wait on a flush in progress
+        static COLLECTOR: std::sync::OnceLock<&'static collector::Collector> = std::sync::OnceLock::new();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .on_document(|_| {
+                    let (sender, receiver) = std::sync::mpsc::channel();
+                    std::thread::spawn(move || {
+                        let metrics = COLLECTOR.get().unwrap();
+                        metrics.record_duration("latency", Vec::new(), std::time::Duration::from_millis(2));
+                        sender.send(()).unwrap();
+                    });
+                    receiver
+                        .recv_timeout(std::time::Duration::from_secs(5))
+                        .expect("recording blocked by the flush");
+                }),
+        );
+        COLLECTOR.set(metrics).ok();
+        metrics.set_unit("latency", metrics::Unit::Milliseconds);
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.flush(std::io::sink()).unwrap();
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["latency"][0], 2.0);
+    }
+
+    #[test]
+    fn set_property_during_flush() {
+        // Documents are built once the state lock is released, so setting a property doesn't wait on them
+        static COLLECTOR: std::sync::OnceLock<&'static collector::Collector> = std::sync::OnceLock::new();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .on_document(|_| {
+                    let (sender, receiver) = std::sync::mpsc::channel();
+                    std::thread::spawn(move || {
+                        COLLECTOR.get().unwrap().set_property("Stage", "late");
+                        sender.send(()).unwrap();
+                    });
+                    receiver
+                        .recv_timeout(std::time::Duration::from_secs(5))
+                        .expect("setting a property blocked by the flush");
+                }),
+        );
+        COLLECTOR.set(metrics).ok();
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        let values = metrics.flush_to_values();
+        assert_eq!(values.len(), 1);
+        assert!(values[0].get("Stage").is_none());
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["Stage"], "late");
+    }
+
+    #[cfg(feature = "lambda")]
+    #[tokio::test]
+    async fn mock_runtime_api() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .lambda_cold_start_metric("ColdStart")
+                .with_lambda_request_id("RequestId"),
+        );
+
+        let api = testing::MockRuntimeApi::start().unwrap();
+        api.invoke("request-1", r#"{"name":"one"}"#);
+        api.invoke("request-2", r#"{"name":"two"}"#);
+        api.run(
+            metrics,
+            |event: lambda_runtime::LambdaEvent<serde_json::Value>| async move {
+                metrics.counter("requests", Vec::new()).increment(1);
+                Ok::<_, lambda_runtime::Error>(event.payload)
+            },
+        )
+        .await
+        .unwrap();
+
+        let responses = api.responses();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].request_id, "request-2");
+        assert!(!responses[1].error);
+        assert_eq!(responses[1].body, br#"{"name":"two"}"#);
+
+        let documents = api.capture().documents();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[0]["ColdStart"], 1);
+        assert_eq!(documents[0]["RequestId"], "request-1");
+        assert_eq!(documents[1]["requests"], 1);
+        assert_eq!(documents[1]["RequestId"], "request-1");
+        assert_eq!(documents[2]["requests"], 1);
+        assert_eq!(documents[2]["RequestId"], "request-2");
+    }
+
+    #[cfg(feature = "lambda")]
+    #[tokio::test]
+    async fn mock_runtime_api_panic() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .lambda_panic_metric("Panic")
+                .with_lambda_request_id("RequestId"),
+        );
+
+        let api = testing::MockRuntimeApi::start().unwrap();
+        api.invoke("request-1", r#"{"panic":true}"#);
+        api.invoke("request-2", r#"{"panic":false}"#);
+        api.run(
+            metrics,
+            |event: lambda_runtime::LambdaEvent<serde_json::Value>| async move {
+                metrics.counter("requests", Vec::new()).increment(1);
+                if event.payload["panic"] == true {
+                    panic!("handler panicked");
+                }
+                Ok::<_, lambda_runtime::Error>(event.payload)
+            },
+        )
+        .await
+        .unwrap();
+
+        // The runtime reports the panic as an error and carries on with the next invocation
+        let responses = api.responses();
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].error);
+        assert!(!responses[1].error);
+
+        // Flushed once before the panic resumes, not again once the runtime has turned it into an error
+        let documents = api.capture().documents();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["Panic"], 1);
+        assert_eq!(documents[0]["requests"], 1);
+        assert_eq!(documents[0]["RequestId"], "request-1");
+        assert_eq!(documents[1]["requests"], 1);
+        assert_eq!(documents[1]["RequestId"], "request-2");
+        assert!(documents[1].get("Panic").is_none());
+    }
+
+    #[cfg(feature = "perf")]
+    #[test]
+    fn bench_stats() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+
+        let stats = metrics.bench_stats();
+        assert_eq!(stats.flushes, 1);
+        assert_eq!(stats.documents, 1);
+        assert_eq!(stats.bytes, output.len() as u64);
+        // Registering doesn't lock the state, only the flush does
+        assert_eq!(stats.lock_acquisitions, 1);
+    }
+
+    #[test]
+    fn sorted_labels() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_sorted_labels(),
+        );
+
+        metrics.counter("requests", &[("b", "2"), ("a", "1")]).increment(1);
+        metrics.counter("requests", &[("a", "1"), ("b", "2")]).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["a","b"]],"Metrics":[{"Name":"requests"}]}]},"a":"1","b":"2","requests":2}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn dimension_from_property() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_dimension_from_property("Stage", "stage"),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics
+            .set_property("stage", "beta")
+            .set_property("RequestId", "ABC123");
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Stage"]],"Metrics":[{"Name":"requests"}]}]},"Stage":"beta","RequestId":"ABC123","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn counter_emission() {
+        let clock = std::sync::Arc::new(MockClock::new(0));
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_clock(clock.clone())
+                .with_counter_emission("legacy.*", CounterEmission::Gauge)
+                .with_counter_emission("throughput", CounterEmission::Rate),
+        );
+        let legacy = metrics.counter("legacy.requests", Vec::new());
+        let throughput = metrics.counter("throughput", Vec::new());
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        legacy.increment(3);
+        throughput.increment(30);
+        clock.advance(std::time::Duration::from_secs(10));
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["legacy.requests"], 3);
+        assert_eq!(values[0]["throughput"], 3);
+        assert_eq!(values[0]["requests"], 1);
+        assert_eq!(
+            values[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"][2],
+            serde_json::json!({"Name": "throughput", "Unit": "Count/Second"})
+        );
+
+        throughput.increment(5);
+        clock.advance(std::time::Duration::from_secs(2));
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["legacy.requests"], 0);
+        assert_eq!(values[0]["throughput"], 2.5);
+        assert!(values[0].get("requests").is_none());
+    }
+
+    #[test]
+    fn flush_order() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_flush_order(collector::FlushOrder::Registration),
+        );
+        metrics.counter("requests", &[("Tenant", "b")]).increment(1);
+        metrics.counter("requests", &[("Tenant", "a")]).increment(1);
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let tenants: Vec<_> = metrics
+            .flush_to_values()
+            .iter()
+            .map(|value| value["Tenant"].clone())
+            .collect();
+        assert_eq!(tenants, [serde_json::json!("b"), "a".into(), serde_json::Value::Null]);
+
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_flush_order(collector::FlushOrder::Label("Tenant".into())),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics
+            .counter("requests", &[("Method", "Get"), ("Tenant", "b")])
+            .increment(1);
+        metrics.counter("requests", &[("Tenant", "a")]).increment(1);
+
+        let tenants: Vec<_> = metrics
+            .flush_to_values()
+            .iter()
+            .map(|value| value["Tenant"].clone())
+            .collect();
+        assert_eq!(tenants, [serde_json::json!("a"), "b".into(), serde_json::Value::Null]);
+    }
+
+    #[test]
+    fn limit_warnings() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let captured = errors.clone();
+        let mut builder = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .with_limit_warnings()
+            .on_error(move |message| captured.lock().unwrap().push(message.to_owned()));
+        for i in 0..25 {
+            builder = builder.with_dimension(format!("Dimension{i}"), "value");
+        }
+        let metrics = local_collector(builder);
+
+        for i in 0..95 {
+            metrics.counter(format!("metric{i}"), Vec::new()).increment(1);
+        }
+        metrics.set_property("Payload", "x".repeat(240 * 1024));
+        metrics.flush(std::io::sink()).unwrap();
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("has 95 metrics"));
+        assert!(errors[1].contains("has 25 dimensions"));
+        assert!(errors[2].contains("approaching the CloudWatch Logs limit"));
+    }
+
+    #[test]
+    fn limit_warnings_handler_uses_collector() {
+        // Warnings are reported once the flush has released the state, so the handler may use the collector
+        static COLLECTOR: std::sync::OnceLock<&'static collector::Collector> = std::sync::OnceLock::new();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_limit_warnings()
+                .on_error(|message| {
+                    if let Some(metrics) = COLLECTOR.get() {
+                        metrics.set_property("LastError", message.to_owned());
+                    }
+                }),
+        );
+        COLLECTOR.set(metrics).ok();
+
+        for i in 0..95 {
+            metrics.counter(format!("metric{i}"), Vec::new()).increment(1);
+        }
+        metrics.flush(std::io::sink()).unwrap();
+
+        let properties = metrics.properties();
+        assert!(properties["LastError"].as_str().unwrap().contains("has 95 metrics"));
+    }
+
+    #[test]
+    fn debug_mirror() {
+        let mirror = testing::CaptureWriter::default();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_debug_mirror(mirror.clone(), 0.25),
+        );
+        let requests = metrics.counter("requests", Vec::new());
+
+        let mut output = Vec::new();
+        for i in 1..=8 {
+            requests.increment(i);
+            metrics.flush(&mut output).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 8);
+        let mirrored = mirror.documents();
+        assert_eq!(mirrored.len(), 2);
+        assert_eq!(mirrored[0]["requests"], 4);
+        assert_eq!(mirrored[1]["requests"], 8);
+    }
+
+    #[cfg(feature = "compat")]
+    #[test]
+    fn compat_macros() {
+        use crate::compat::{counter, gauge, histogram, increment_counter, increment_gauge};
+
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        metrics::with_local_recorder(&collector::Recorder::from(metrics), || {
+            increment_counter!("requests", "Method" => "Get");
+            counter!("requests", 2, "Method" => "Get");
+            gauge!("connections", 3.0);
+            increment_gauge!("connections", 1.0);
+            histogram!("latency", 12.5, &[("Method", "Get")]);
+        });
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["connections"], 4);
+        assert_eq!(values[1]["requests"], 3);
+        assert_eq!(values[1]["latency"][0], 12.5);
+    }
+
+    #[test]
+    fn startup_probe() {
+        let capture = testing::CaptureWriter::default();
+        builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .with_startup_probe_writer("Startup", capture.clone())
+            .collector()
+            .unwrap();
+        assert_eq!(
+            capture.output(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"Startup","Unit":"Count"}]}]},"Startup":1}
+"#
+        );
+
+        let result = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_startup_probe_writer("Startup", testing::FaultyWriter::new(std::io::sink()).fail_after(0))
+            .collector();
+        assert!(result.is_err_and(|e| e.to_string().starts_with("startup probe failed")));
+
+        // Without a writer the probe goes to the configured sink
+        let capture = testing::CaptureWriter::default();
+        builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_startup_probe("Startup")
+            .with_sink(capture.clone())
+            .collector()
+            .unwrap();
+        assert_eq!(capture.documents()[0]["Startup"], 1);
+    }
+
+    #[test]
+    fn namespace_suffix() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_namespace_suffix("staging")
+                .with_tenant_label("Tenant")
+                .with_tenant_namespaces(),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.counter("requests", &[("Tenant", "acme")]).increment(1);
+
+        let namespaces: Vec<_> = metrics
+            .flush_to_values()
+            .iter()
+            .map(|value| value["_aws"]["CloudWatchMetrics"][0]["Namespace"].clone())
+            .collect();
+        assert_eq!(namespaces, ["namespace-staging", "namespace-staging/acme"]);
+
+        let capture = testing::CaptureWriter::default();
+        metrics
+            .write_single_to_namespace("admin", "deploys", None, 1, capture.clone())
+            .unwrap();
+        assert_eq!(
+            capture.documents()[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "admin-staging"
+        );
+
+        let metrics = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_namespace_prefix("dev")
+            .collector()
+            .unwrap();
+        assert_eq!(metrics.config.cloudwatch_namespace.as_ref(), "dev-namespace");
+    }
+
+    #[test]
+    fn max_metrics_per_document_default() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        for i in 0..150 {
+            metrics
+                .counter(format!("counter{i:03}"), vec![metrics::Label::new("Method", "Get")])
+                .increment(1);
+        }
+
+        // The Embedded Metric Format allows at most 100 metrics per document
+        let values = metrics.flush_to_values();
+        assert_eq!(values.len(), 2);
+        let lens: Vec<usize> = values
+            .iter()
+            .map(|value| {
+                value["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(lens, [100, 50]);
+        assert_eq!(values[0]["Method"], "Get");
+        assert_eq!(values[1]["Method"], "Get");
+        assert_eq!(values[1]["counter149"], 1);
+    }
+
+    #[test]
+    fn histogram_statistic_sets() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_histogram_statistic_sets(),
+        );
+        let latency = metrics.histogram("latency", Vec::new());
+        for value in 1..=150 {
+            latency.record(value as f64);
+        }
+
+        let values = metrics.flush_to_values();
+        assert_eq!(
+            values[0]["latency"],
+            serde_json::json!({"Max": 150, "Min": 1, "SampleCount": 150, "Sum": 11325})
+        );
+
+        // Back to values once the buffer no longer overflows
+        latency.record(1.5);
+        assert_eq!(metrics.flush_to_values()[0]["latency"], serde_json::json!([1.5]));
+    }
+
+    #[test]
+    fn histogram_sketch() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_histogram_sketch("latency", SketchOutput::Percentiles(vec![50.0, 99.9]))
+                .with_histogram_sketch("size", SketchOutput::Distribution),
+        );
+        let latency = metrics.histogram("latency", Vec::new());
+        let size = metrics.histogram("size", Vec::new());
+        for value in 1..=10_000 {
+            latency.record(value as f64);
+            size.record(value as f64);
+        }
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values.len(), 1);
+        let metric_names: Vec<&str> = values[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metric| metric["Name"].as_str().unwrap())
+            .collect();
+        assert_eq!(metric_names, ["latency.p50", "latency.p99.9", "size"]);
+
+        // Quantiles are within 1% of the true value
+        let p50 = values[0]["latency.p50"].as_f64().unwrap();
+        assert!((p50 - 5_000.0).abs() <= 50.0, "{p50}");
+        let p999 = values[0]["latency.p99.9"].as_f64().unwrap();
+        assert!((p999 - 9_990.0).abs() <= 100.0, "{p999}");
+
+        let counts = values[0]["size"]["Counts"].as_array().unwrap();
+        assert!(counts.len() <= 100);
+        assert_eq!(values[0]["size"]["Values"].as_array().unwrap().len(), counts.len());
+        assert_eq!(counts.iter().map(|count| count.as_u64().unwrap()).sum::<u64>(), 10_000);
+
+        // The sketch is reset by each flush
+        assert!(metrics.flush_to_values().is_empty());
+        latency.record(42.0);
+        let p50 = metrics.flush_to_values()[0]["latency.p50"].as_f64().unwrap();
+        assert!((p50 - 42.0).abs() <= 0.42, "{p50}");
+    }
+
+    #[test]
+    fn histogram_sketch_percentiles_split() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_histogram_sketch("latency", SketchOutput::Percentiles(vec![50.0, 90.0, 99.0])),
+        );
+        for i in 0..99 {
+            metrics.counter(format!("count{i:02}"), Vec::new()).increment(1);
+        }
+        metrics.histogram("latency", Vec::new()).record(1.0);
+
+        // The percentiles of a single histogram are split across documents rather than exceeding the limit
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        let documents: Vec<&[u8]> = output.split_inclusive(|&b| b == b'\n').collect();
+        assert_eq!(documents.len(), 2);
+        for document in &documents {
+            validate_document(document).unwrap();
+        }
+
+        let values: Vec<serde_json::Value> = documents
+            .iter()
+            .map(|document| serde_json::from_slice(document).unwrap())
+            .collect();
+        let metric_count = |document: &serde_json::Value| {
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                .as_array()
+                .unwrap()
+                .len()
+        };
+        assert_eq!(metric_count(&values[0]), 100);
+        assert!(values[0]["latency.p50"].is_number());
+        assert_eq!(metric_count(&values[1]), 2);
+        assert!(values[1]["latency.p90"].is_number());
+        assert!(values[1]["latency.p99"].is_number());
+    }
+
+    #[test]
+    fn dimension_rollup() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Function", "orders")
+                .with_dimension_rollup(["Function"])
+                .with_dimension_rollup(["API"])
+                .with_timestamp(0),
+        );
+        metrics
+            .counter("requests", vec![metrics::Label::new("API", "create")])
+            .increment(1);
+        metrics.counter("cold_starts", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Function"]],"Metrics":[{"Name":"cold_starts"}]}]},"Function":"orders","cold_starts":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Function","API"],["Function"],["API"]],"Metrics":[{"Name":"requests"}]}]},"API":"create","Function":"orders","requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn invocation_isolation() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics::with_local_recorder(&metrics.invocation_recorder(), || {
+            metrics::counter!("requests").increment(2);
+        });
+        metrics.counter("background", Vec::new()).increment(3);
+
+        // Only the invocation's own metrics are emitted at the end of the invocation
+        let mut output = Vec::new();
+        metrics.flush_invocation(&mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(document["requests"], 2);
+        assert!(document.get("background").is_none());
+
+        let values = metrics.flush_to_values();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["requests"], 1);
+        assert_eq!(values[0]["background"], 3);
+
+        // Both are reset by their flush
+        let mut output = Vec::new();
+        metrics.flush_invocation(&mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn namespace_route() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("MyApp")
+                .with_namespace_route("db_", "MyApp/Database")
+                .with_namespace_suffix("dev")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.counter("db_queries", Vec::new()).increment(2);
+        metrics.counter("db_errors", Vec::new()).increment(3);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"MyApp-dev","Dimensions":[[]],"Metrics":[{"Name":"requests"}]},{"Namespace":"MyApp/Database-dev","Dimensions":[[]],"Metrics":[{"Name":"db_errors"},{"Name":"db_queries"}]}]},"db_errors":3,"db_queries":2,"requests":1}
+"#
+        );
+
+        // Single metrics are routed too
+        let mut output = Vec::new();
+        metrics.write_single("db_connections", None, 1, &mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            document["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "MyApp/Database-dev"
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn flush_async() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        futures::executor::block_on(metrics.flush_async(&mut output)).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"requests":1}
+"#
+        );
+    }
+
+    #[test]
+    fn property_fn() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let depth = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(1));
+        let weak = std::sync::Arc::downgrade(&depth);
+        metrics.set_property_fn("QueueDepth", move || {
+            weak.upgrade()
+                .map(|depth| depth.load(std::sync::atomic::Ordering::Relaxed))
+                .into()
+        });
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        assert_eq!(metrics.flush_to_values()[0]["QueueDepth"], 1);
+
+        // Computed again at each flush
+        depth.store(5, std::sync::atomic::Ordering::Relaxed);
+        metrics.counter("requests", Vec::new()).increment(1);
+        assert_eq!(metrics.flush_to_values()[0]["QueueDepth"], 5);
+
+        drop(depth);
+        metrics.counter("requests", Vec::new()).increment(1);
+        assert_eq!(metrics.flush_to_values()[0]["QueueDepth"], serde_json::Value::Null);
+
+        // Replaced by a plain property of the same name
+        metrics.set_property("QueueDepth", 7);
+        metrics.counter("requests", Vec::new()).increment(1);
+        assert_eq!(metrics.flush_to_values()[0]["QueueDepth"], 7);
+    }
+
+    #[test]
+    fn property_fn_panic() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.set_property_fn("QueueDepth", || panic!("queue is gone"));
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let flush = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| metrics.flush(std::io::sink())));
+        assert!(flush.is_err());
+
+        // The panic happened outside of the collector's locks, it's still usable
+        metrics.remove_property("QueueDepth");
+        metrics.set_property("RequestId", "ABC123");
+        metrics.counter("requests", Vec::new()).increment(1);
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["RequestId"], "ABC123");
+        assert_eq!(values[0]["requests"], 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn spawn_flusher() {
+        use tokio::io::AsyncReadExt;
+
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+            let flusher = metrics.spawn_flusher(std::time::Duration::from_millis(10), writer);
+
+            metrics.counter("requests", Vec::new()).increment(1);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+            // The final flush on shutdown picks up values recorded since the last one
+            metrics.counter("errors", Vec::new()).increment(1);
+            flusher.shutdown().await;
+
+            let mut output = String::new();
+            reader.read_to_string(&mut output).await.unwrap();
+            let documents: Vec<serde_json::Value> =
+                output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+            assert_eq!(documents.len(), 2);
+            assert_eq!(documents[0]["requests"], 1);
+            assert_eq!(documents[1]["errors"], 1);
+        });
+    }
+
+    #[test]
+    fn subscope() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("app").with_timestamp(0));
+
+        metrics::with_local_recorder(&metrics.subscope("MyLib"), || {
+            metrics::describe_counter!("loads", metrics::Unit::Count, "");
+            metrics::counter!("loads").increment(1);
+        });
+        metrics
+            .subscope("MyLib")
+            .component_dimension()
+            .counter("requests", Vec::new())
+            .increment(2);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"app","Dimensions":[[]],"Metrics":[{"Name":"MyLib.loads","Unit":"Count"}]}]},"MyLib.loads":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"app","Dimensions":[["Component"]],"Metrics":[{"Name":"requests"}]}]},"Component":"MyLib","requests":2}
+"#
+        );
+    }
+
+    #[cfg(feature = "lambda-http")]
+    #[tokio::test]
+    async fn mock_runtime_api_http() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .lambda_cold_start_metric("ColdStart")
+                .with_lambda_request_id("RequestId"),
+        );
+
+        let api = testing::MockRuntimeApi::start().unwrap();
+        api.invoke("request-1", testing::http_event("GET", "/one", ""));
+        api.invoke("request-2", testing::http_event("POST", "/two", "body"));
+        api.run_http(metrics, |request: lambda_http::Request| async move {
+            let path = request.uri().path().to_owned();
+            metrics.counter("requests", &[("Path", path)]).increment(1);
+            Ok::<_, lambda_runtime::Error>("ok")
+        })
+        .await
+        .unwrap();
+
+        let responses = api.responses();
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|response| !response.error));
+
+        // The cold start metric once, then exactly one flush per request
+        let documents = api.capture().documents();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[0]["ColdStart"], 1);
+        assert_eq!(documents[0]["RequestId"], "request-1");
+        assert_eq!(documents[1]["Path"], "/one");
+        assert_eq!(documents[1]["requests"], 1);
+        assert_eq!(documents[1]["RequestId"], "request-1");
+        assert_eq!(documents[2]["Path"], "/two");
+        assert_eq!(documents[2]["requests"], 1);
+        assert_eq!(documents[2]["RequestId"], "request-2");
+        assert!(documents
+            .iter()
+            .skip(1)
+            .all(|document| document.get("ColdStart").is_none()));
+    }
+
+    #[test]
+    fn sink() {
+        let documents = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let emitted = documents.clone();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_sink(move |document: &[u8]| {
+                    emitted
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(document.to_vec()).unwrap());
+                    Ok(())
+                }),
+        );
+
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.counter("requests", &[("Method", "Put")]).increment(2);
+        metrics.flush_to_sink().unwrap();
+        metrics.write_single("errors", None, 1, metrics.sink_writer()).unwrap();
+
+        // One emit per document
+        let documents = documents.lock().unwrap();
+        assert_eq!(documents.len(), 3);
+        assert!(documents.iter().all(|document| document.ends_with('\n')));
+        assert!(documents[0].contains(r#""Method":"Get","requests":1"#));
+        assert!(documents[1].contains(r#""Method":"Put","requests":2"#));
+        assert!(documents[2].contains(r#""errors":1"#));
+    }
+
+    #[test]
+    fn agent_sink() {
+        use std::io::BufRead;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = AgentSink::new(&format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+        let udp_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp = AgentSink::new(&format!("udp://{}", udp_socket.local_addr().unwrap())).unwrap();
+
+        tcp.emit(b"{\"a\":1}\n").unwrap();
+        tcp.emit(b"{\"b\":2}\n").unwrap();
+        let mut reader = std::io::BufReader::new(listener.accept().unwrap().0);
+        let mut lines = Vec::new();
+        for _ in 0..2 {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(lines, ["{\"a\":1}\n", "{\"b\":2}\n"]);
+
+        udp.emit(b"{\"c\":3}\n").unwrap();
+        let mut datagram = [0; 64];
+        let len = udp_socket.recv(&mut datagram).unwrap();
+        assert_eq!(&datagram[..len], b"{\"c\":3}\n");
+        assert!(udp.emit(&vec![b' '; 65_508]).is_err());
+
+        assert!(AgentSink::new("127.0.0.1:25888").is_err());
+        assert!(AgentSink::new("http://127.0.0.1:25888").is_err());
+    }
+
+    #[test]
+    fn firehose_sink() {
+        let batches = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let submitted = batches.clone();
+        let sink = FirehoseSink::new(move |records: &[Vec<u8>]| {
+            let mut batches = submitted.lock().unwrap();
+            batches.push(records.len());
+            // Fail the second record of the first request
+            Ok(if batches.len() == 1 { vec![1] } else { Vec::new() })
+        })
+        .backoff(
+            std::time::Duration::from_millis(50),
+            std::time::Duration::from_millis(50),
+        );
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_sink(sink),
+        );
+
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.counter("requests", &[("Method", "Put")]).increment(1);
+        metrics.counter("requests", &[("Method", "Post")]).increment(1);
+
+        // The failed record is kept rather than retried while flushing, then resubmitted by the next flush
+        let e = metrics.flush_to_sink().unwrap_err();
+        assert_eq!(e.to_string(), "1 records failed, retrying with a later flush");
+        assert_eq!(*batches.lock().unwrap(), [3]);
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        metrics.flush_to_sink().unwrap();
+        assert_eq!(*batches.lock().unwrap(), [3, 1]);
+
+        // Records are discarded once the retries are exhausted
+        let sink = FirehoseSink::new(|_: &[Vec<u8>]| Err(std::io::Error::new(std::io::ErrorKind::Other, "throttled")))
+            .max_retries(1)
+            .backoff(std::time::Duration::ZERO, std::time::Duration::ZERO);
+        sink.emit(b"{}\n").unwrap();
+        assert!(sink.flush().is_err());
+        let e = sink.flush().unwrap_err();
+        assert_eq!(e.to_string(), "discarding 1 records after 1 retries: throttled");
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn put_metric_data_sink() {
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let submitted = requests.clone();
+        let sink = PutMetricDataSink::new(move |namespace: &str, data: &[MetricDatum]| {
+            submitted.lock().unwrap().push((namespace.to_owned(), data.to_vec()));
+            Ok(())
+        });
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(1000)
+                .with_dimension("Service", "api")
+                .with_namespace_route("plugin.", "plugin")
+                .with_histogram_statistic_sets()
+                .with_sink(sink),
+        );
+
+        metrics::with_local_recorder(&metrics.recorder(), || {
+            metrics::describe_histogram!("latency", metrics::Unit::Milliseconds, "");
+        });
+        metrics.counter("requests", &[("Method", "Get")]).increment(2);
+        metrics.histogram("latency", Vec::new()).record(1.5);
+        metrics.counter("plugin.loads", Vec::new()).increment(1);
+        metrics.flush_to_sink().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (namespace, data) = &requests[0];
+        assert_eq!(namespace, "namespace");
+        assert_eq!(data.len(), 2);
+        let requests_datum = data.iter().find(|datum| datum.name == "requests").unwrap();
+        assert_eq!(
+            requests_datum.dimensions,
+            [
+                ("Service".to_owned(), "api".to_owned()),
+                ("Method".to_owned(), "Get".to_owned())
+            ]
+        );
+        assert_eq!(requests_datum.timestamp, 1000);
+        assert_eq!(requests_datum.value, MetricValue::Value(2.0));
+        let latency = data.iter().find(|datum| datum.name == "latency").unwrap();
+        assert_eq!(latency.unit.as_deref(), Some("Milliseconds"));
+        assert_eq!(
+            latency.value,
+            MetricValue::Values {
+                values: vec![1.5],
+                counts: Vec::new()
+            }
+        );
+
+        let (namespace, data) = &requests[1];
+        assert_eq!(namespace, "plugin");
+        assert_eq!(data[0].name, "plugin.loads");
+        assert_eq!(data[0].value, MetricValue::Value(1.0));
+    }
+
+    #[test]
+    fn flush_property() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        let requests = metrics.counter("requests", Vec::new());
+
+        metrics.set_property("Service", "api");
+        metrics.set_flush_property("CustomerId", "ABC123");
+        requests.increment(1);
+        let values = metrics.flush_to_values();
+        assert_eq!(values[0]["CustomerId"], "ABC123");
+        assert_eq!(values[0]["Service"], "api");
+
+        requests.increment(1);
+        let values = metrics.flush_to_values();
+        assert!(values[0].get("CustomerId").is_none());
+        assert_eq!(values[0]["Service"], "api");
+
+        // Setting it persistently keeps it
+        metrics.set_flush_property("CustomerId", "ABC123");
+        metrics.set_property("CustomerId", "DEF456");
+        metrics.flush_to_values();
+        requests.increment(1);
+        assert_eq!(metrics.flush_to_values()[0]["CustomerId"], "DEF456");
+    }
+
+    #[test]
+    fn set_properties() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .on_error(move |message| reported.lock().unwrap().push(message.to_owned())),
+        );
+
+        metrics.set_properties([
+            ("RequestId".into(), "ABC123".into()),
+            ("_aws".into(), "corrupt".into()),
+            (
+                "Context".into(),
+                serde_json::json!({"Tenant": "acme", "Features": ["beta", "gamma"], "Limits": {"Rps": 10}}),
+            ),
+        ]);
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        let mut output = Vec::new();
+        metrics.flush(&mut output).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"requests"}]}]},"Context":{"Features":["beta","gamma"],"Limits":{"Rps":10},"Tenant":"acme"},"RequestId":"ABC123","requests":1}
+"#
+        );
+        assert_eq!(errors.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn flush_to_string() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        assert_eq!(metrics.flush_to_string().unwrap(), "");
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.counter("requests", &[("Method", "Put")]).increment(2);
+        assert_eq!(
+            metrics.flush_to_string().unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"requests"}]}]},"Method":"Get","requests":1}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Method"]],"Metrics":[{"Name":"requests"}]}]},"Method":"Put","requests":2}
+"#
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Service", "api")
+                .with_timestamp(0),
+        );
+
+        metrics.counter("requests", &[("Method", "Get")]).increment(2);
+        metrics.gauge("connections", Vec::new()).set(3.5);
+        let latency = metrics.histogram("latency", Vec::new());
+        latency.record(1.0);
+        latency.record(2.0);
+
+        let expected = vec![
+            SnapshotEntry {
+                name: "connections".to_owned(),
+                labels: Vec::new(),
+                value: SnapshotValue::Gauge(3.5),
+            },
+            SnapshotEntry {
+                name: "latency".to_owned(),
+                labels: Vec::new(),
+                value: SnapshotValue::Histogram(2),
+            },
+            SnapshotEntry {
+                name: "requests".to_owned(),
+                labels: vec![("Method".to_owned(), "Get".to_owned())],
+                value: SnapshotValue::Counter(2),
+            },
+        ];
+        assert_eq!(metrics.snapshot(), expected);
+
+        // Taking a snapshot doesn't reset anything
+        assert_eq!(metrics.snapshot(), expected);
+        metrics.flush_to_values();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot[0].value, SnapshotValue::Gauge(3.5));
+        assert_eq!(snapshot[1].value, SnapshotValue::Histogram(0));
+        assert_eq!(snapshot[2].value, SnapshotValue::Counter(0));
+    }
+
+    #[test]
+    fn assert_metric_emitted() {
+        let capture = testing::CaptureWriter::default();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_dimension("Service", "api")
+                .with_sink(capture.clone()),
+        );
+
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.counter("requests", &[("Method", "Put")]).increment(2);
+        metrics.histogram("latency", Vec::new()).record(1.5);
+        metrics.flush_to_sink().unwrap();
+
+        capture.assert_metric_emitted("requests", 1, &[("Method", "Get")]);
+        capture.assert_metric_emitted("requests", 2.0, &[("Service", "api"), ("Method", "Put")]);
+        capture.assert_metric_emitted("latency", vec![1.5], &[]);
+
+        let documents = capture.documents();
+        assert!(testing::find_metric(&documents, "requests", &[("Method", "Post")]).is_none());
+        assert!(testing::find_metric(&documents, "errors", &[]).is_none());
+        let mismatch = std::panic::catch_unwind(|| testing::assert_metric_emitted(&documents, "requests", 3, &[]));
+        assert!(mismatch.is_err());
+    }
+
+    #[test]
+    fn parsed_document() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_dimension("Service", "api")
+                .with_property("Version", "1.0"),
+        );
+
+        metrics::with_local_recorder(&metrics.recorder(), || {
+            metrics::describe_histogram!("latency", metrics::Unit::Milliseconds, "");
+        });
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.histogram("latency", &[("Method", "Get")]).record(1.5);
+
+        let output = metrics.flush_to_string().unwrap();
+        let document: ParsedDocument = output.parse().unwrap();
+        assert_eq!(
+            document,
+            ParsedDocument {
+                aws: ParsedAws {
+                    timestamp: 0,
+                    cloudwatch_metrics: vec![ParsedDirective {
+                        namespace: "namespace".to_owned(),
+                        dimensions: vec![vec!["Service".to_owned(), "Method".to_owned()]],
+                        metrics: vec![
+                            ParsedMetric {
+                                name: "latency".to_owned(),
+                                unit: Some("Milliseconds".to_owned()),
+                            },
+                            ParsedMetric {
+                                name: "requests".to_owned(),
+                                unit: None,
+                            },
+                        ],
+                    }],
+                },
+                dimensions: [("Method", "Get"), ("Service", "api")]
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                    .collect(),
+                properties: [("Version".to_owned(), "1.0".into())].into_iter().collect(),
+                values: [
+                    ("latency".to_owned(), serde_json::json!([1.5])),
+                    ("requests".to_owned(), 1.into())
+                ]
+                .into_iter()
+                .collect(),
+            }
+        );
+
+        // Round trips to the same output
+        assert_eq!(serde_json::to_string(&document).unwrap() + "\n", output);
+    }
+
+    #[test]
+    fn strict_validation() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let captured = errors.clone();
+        let mut builder = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .with_strict_validation()
+            .on_error(move |message| captured.lock().unwrap().push(message.to_owned()));
+        let mut labels = Vec::new();
+        for i in 0..10 {
+            builder = builder.with_dimension_rollup([format!("Label{i}")]);
+            labels.push(metrics::Label::new(format!("Label{i}"), "value"));
+        }
+        let metrics = local_collector(builder);
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.counter("rolled_up", labels.clone()).increment(1);
+
+        let mut output = Vec::new();
+        let error = metrics.flush(&mut output).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.into_inner().unwrap().downcast_ref::<ValidationError>(),
+            Some(&ValidationError::TooManyDimensionSets {
+                namespace: "namespace".to_owned(),
+                count: 11
+            })
+        );
+        assert_eq!(errors.lock().unwrap().len(), 1);
+
+        // The valid document is still written
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains(r#""requests":1"#));
+
+        // Invalid documents are an error rather than a panic when flushing to a string
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.counter("rolled_up", labels).increment(1);
+        let error = metrics.flush_to_string().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn strict_validation_handler_uses_collector() {
+        // Invalid documents are reported once the flush has released the state, so the handler may use the collector
+        static COLLECTOR: std::sync::OnceLock<&'static collector::Collector> = std::sync::OnceLock::new();
+        let mut builder = builder::Builder::new()
+            .cloudwatch_namespace("namespace")
+            .with_timestamp(0)
+            .with_strict_validation()
+            .on_error(|message| {
+                if let Some(metrics) = COLLECTOR.get() {
+                    metrics.set_property("LastError", message.to_owned());
+                }
+            });
+        let mut labels = Vec::new();
+        for i in 0..10 {
+            builder = builder.with_dimension_rollup([format!("Label{i}")]);
+            labels.push(metrics::Label::new(format!("Label{i}"), "value"));
+        }
+        let metrics = local_collector(builder);
+        COLLECTOR.set(metrics).ok();
+
+        metrics.counter("rolled_up", labels).increment(1);
+        assert!(metrics.flush(std::io::sink()).is_err());
+
+        let properties = metrics.properties();
+        assert!(properties["LastError"]
+            .as_str()
+            .unwrap()
+            .starts_with("Dropping invalid metrics document"));
+    }
+
+    #[test]
+    fn name_sanitizer() {
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let captured = errors.clone();
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_name_sanitizer(NameSanitizer::new().max_value_chars(4))
+                .on_error(move |message| captured.lock().unwrap().push(message.to_owned())),
+        );
+
+        metrics
+            .counter("café\tlatency", &[(":Path", "/a\u{7}bcdef")])
+            .increment(1);
+        metrics.counter("requests", &[("Path", " ")]).increment(1);
+        metrics.counter("valid", &[("Path", "/")]).increment(1);
+
+        let documents = metrics.flush_to_values();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["Path"], "/");
+        assert_eq!(documents[0]["valid"], 1);
+        assert_eq!(documents[1]["Path"], "/a_b");
+        assert_eq!(documents[1]["caf__latency"], 1);
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("label Path of requests has an empty value"));
+    }
+
+    #[test]
+    fn non_finite_values() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+
+        metrics.counter("requests", Vec::new()).increment(1);
+        metrics.gauge("ratio", Vec::new()).set(f64::NAN);
+        let latency = metrics.histogram("latency", Vec::new());
+        for value in [1.0, f64::NAN, f64::INFINITY, 2.0, f64::NEG_INFINITY] {
+            latency.record(value);
+        }
+
+        let documents = metrics.flush_to_values();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0]["requests"], 1);
+        assert_eq!(documents[0]["latency"], serde_json::json!([1.0, 2.0]));
+        assert!(documents[0].get("ratio").is_none());
+        assert_eq!(metrics.dropped_non_finite(), 4);
+    }
+
+    #[test]
+    fn non_finite_sketch_values() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_histogram_sketch("latency", SketchOutput::Percentiles(vec![50.0]))
+                .with_histogram_sketch("size", SketchOutput::Distribution),
+        );
+
+        let latency = metrics.histogram("latency", Vec::new());
+        for value in [1.0, f64::NAN, f64::INFINITY] {
+            latency.record(value);
+        }
+        metrics.histogram("size", Vec::new()).record(f64::NEG_INFINITY);
+
+        // Counted like the values dropped from buffered histograms, a sketch with only non-finite values is omitted
+        let documents = metrics.flush_to_values();
+        assert_eq!(documents.len(), 1);
+        assert!(documents[0]["latency.p50"].is_number());
+        assert!(documents[0].get("size").is_none());
+        assert_eq!(metrics.dropped_non_finite(), 3);
+
+        // Cleared with the sketch
+        latency.record(2.0);
+        metrics.flush(std::io::sink()).unwrap();
+        assert_eq!(metrics.dropped_non_finite(), 3);
+    }
+
+    #[test]
+    fn concurrent_gauge_updates() {
+        let metrics = local_collector(builder::Builder::new().cloudwatch_namespace("namespace"));
+        let gauge = metrics.gauge("in_flight", Vec::new());
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10_000 {
+                        gauge.increment(1.0);
+                        gauge.decrement(0.5);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(metrics.snapshot()[0].value, SnapshotValue::Gauge(40_000.0));
+    }
+
+    #[test]
+    fn histogram_capacity() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_histogram_capacity(1000),
+        );
+        let latency = metrics.histogram("latency", Vec::new());
+        let sizes = metrics.histogram("sizes", Vec::new());
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let (latency, sizes) = (&latency, &sizes);
+                scope.spawn(move || {
+                    for i in 0..250 {
+                        latency.record((i % 10) as f64);
+                        sizes.record((thread * 250 + i) as f64);
+                    }
+                });
+            }
+        });
+
+        let documents = metrics.flush_to_values();
+        assert_eq!(
+            documents[0]["latency"]["Values"],
+            serde_json::json!((0..10).map(f64::from).collect::<Vec<_>>())
+        );
+        assert_eq!(documents[0]["latency"]["Counts"], serde_json::json!(vec![100; 10]));
+        assert_eq!(documents[0]["sizes"]["SampleCount"], 1000);
+        assert_eq!(documents[0]["sizes"]["Sum"], 499_500);
+
+        // The drained half is reused once the other has been flushed
+        latency.record(1.0);
+        metrics.flush_to_values();
+        latency.record(2.0);
+        assert_eq!(metrics.flush_to_values()[0]["latency"], serde_json::json!([2.0]));
+    }
+
+    #[test]
+    fn registration_during_flush() {
+        use std::sync::mpsc;
+
+        /// Writer that signals on its first write, then blocks until released
+        struct BlockingWriter {
+            started: mpsc::Sender<()>,
+            release: mpsc::Receiver<()>,
+        }
+
+        impl std::io::Write for BlockingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.started.send(()).is_ok() {
+                    self.release.recv().unwrap();
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", Vec::new()).increment(1);
+
+        // Registering a new label set completes while a flush holds the state
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            let flushing = s.spawn(|| {
+                metrics.flush(BlockingWriter {
+                    started: started_tx,
+                    release: release_rx,
+                })
+            });
+            started_rx.recv().unwrap();
+            drop(started_rx);
+            metrics.counter("errors", &[("Method", "Default")]).increment(1);
+            metrics.counter("requests", Vec::new()).increment(1);
+
+            // As does setting a property, the state isn't locked while writing
+            metrics.set_property("RequestId", "ABC123");
+            release_tx.send(()).unwrap();
+            flushing.join().unwrap().unwrap();
+        });
+
+        let documents = metrics.flush_to_values();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["requests"], 1);
+        assert_eq!(documents[1]["errors"], 1);
+        assert_eq!(documents[1]["RequestId"], "ABC123");
+        assert_eq!(metrics.estimate_cost().custom_metrics, 2);
+    }
+
+    #[test]
+    fn single_write_per_flush() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0),
+        );
+        metrics.counter("requests", &[("Method", "Get")]).increment(1);
+        metrics.counter("requests", &[("Method", "Put")]).increment(1);
+        metrics.histogram("latency", Vec::new()).record(1.0);
+
+        let mut writer = testing::FaultyWriter::new(Vec::new());
+        metrics.flush(&mut writer).unwrap();
+        assert_eq!(writer.writes(), 1);
+        assert_eq!(std::str::from_utf8(&writer.into_inner()).unwrap().lines().count(), 3);
+
+        // Nothing to write
+        let mut writer = testing::FaultyWriter::new(Vec::new());
+        metrics.flush(&mut writer).unwrap();
+        assert_eq!(writer.writes(), 0);
+    }
+
+    #[test]
+    fn write_single_with() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_dimension("Service", "orders"),
+        );
+        metrics.set_property("RequestId", "ABC123");
+
+        let mut output = Vec::new();
+        let options = WriteOptions::new()
+            .dimension("Tenant", "tenant-1")
+            .timestamp(42)
+            .property("RequestId", "DEF456")
+            .property("Version", 2);
+        metrics
+            .write_single_with("ColdStart", Some(metrics::Unit::Count), 1, &options, &mut output)
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            r#"{"_aws":{"Timestamp":42,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[["Service","Tenant"]],"Metrics":[{"Name":"ColdStart","Unit":"Count"}]}]},"Service":"orders","Tenant":"tenant-1","RequestId":"DEF456","Version":2,"ColdStart":1}
+"#
+        );
+
+        // Dimensions and properties that would corrupt the document are rejected
+        for options in [
+            WriteOptions::new().dimension("Service", "payments"),
+            WriteOptions::new().dimension("Tenant", "a").dimension("Tenant", "b"),
+            WriteOptions::new().dimension("Tenant", "a").property("Tenant", "b"),
+            WriteOptions::new().property("_aws", 1),
+        ] {
+            let error = metrics
+                .write_single_with("ColdStart", None, 1, &options, std::io::sink())
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn write_batch() {
+        let metrics = local_collector(
+            builder::Builder::new()
+                .cloudwatch_namespace("namespace")
+                .with_timestamp(0)
+                .with_max_metrics_per_document(2),
+        );
+
+        let mut output = testing::FaultyWriter::new(Vec::new());
+        metrics
+            .write_batch(
+                &[
+                    ("BatchSize", Some(metrics::Unit::Count), 250.0),
+                    ("BatchDuration", Some(metrics::Unit::Milliseconds), 1200.0),
+                    ("BatchRetries", None, 1.0),
+                ],
+                &mut output,
+            )
+            .unwrap();
+
+        // Split at the document limit, written at once
+        assert_eq!(output.writes(), 1);
+        assert_eq!(
+            std::str::from_utf8(&output.into_inner()).unwrap(),
+            r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"BatchSize","Unit":"Count"},{"Name":"BatchDuration","Unit":"Milliseconds"}]}]},"BatchDuration":1200.0,"BatchSize":250.0}
+{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"namespace","Dimensions":[[]],"Metrics":[{"Name":"BatchRetries"}]}]},"BatchRetries":1.0}
+"#
+        );
+
+        let error = metrics
+            .write_batch(&[("BatchSize", None, 1), ("BatchSize", None, 2)], std::io::sink())
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
 }
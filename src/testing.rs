@@ -0,0 +1,671 @@
+//! # Testing
+//!
+//! Helpers for asserting on emitted metrics without deploying to AWS
+//!
+//! # Example
+//! ```
+//! use metrics_cloudwatch_embedded::testing::CaptureWriter;
+//!
+//! let metrics = metrics_cloudwatch_embedded::Builder::new()
+//!      .cloudwatch_namespace("MyApplication")
+//!      .init()
+//!      .unwrap();
+//!
+//! let capture = CaptureWriter::default();
+//! metrics::counter!("requests").increment(1);
+//! metrics.flush(capture.clone()).unwrap();
+//!
+//! assert_eq!(capture.documents()[0]["requests"], 1);
+//! capture.assert_metric_emitted("requests", 1, &[]);
+//! ```
+
+use serde_json::value::Value;
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "lambda")]
+use {
+    crate::{lambda::MetricsLayer, Clock, Collector},
+    std::collections::VecDeque,
+    std::future::Future,
+    std::io::{BufRead, BufReader, Write},
+    std::net::{SocketAddr, TcpListener, TcpStream},
+    std::sync::Condvar,
+    std::task::{Poll, Waker},
+};
+
+/// Cloneable [std::io::Write] capturing everything written to it in memory, each clone shares the same buffer
+#[derive(Debug, Default, Clone)]
+pub struct CaptureWriter {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CaptureWriter {
+    /// Everything written so far as a string
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.lock().unwrap_or_else(PoisonError::into_inner)).into_owned()
+    }
+
+    /// Everything written so far parsed as one JSON document per line
+    /// * Panics if a line isn't valid JSON
+    pub fn documents(&self) -> Vec<Value> {
+        self.output()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("captured line is not a JSON document"))
+            .collect()
+    }
+
+    /// Discards everything written so far
+    pub fn clear(&self) {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    /// Asserts a document written so far emitted the metric, see [assert_metric_emitted]
+    #[track_caller]
+    pub fn assert_metric_emitted(&self, name: &str, value: impl Into<Value>, dimensions: &[(&str, &str)]) {
+        assert_metric_emitted(&self.documents(), name, value, dimensions)
+    }
+}
+
+/// Every document is written to the buffer, e.g. for [Builder::with_sink](crate::Builder::with_sink)
+impl crate::Sink for CaptureWriter {
+    fn emit(&self, document: &[u8]) -> std::io::Result<()> {
+        std::io::Write::write_all(&mut self.clone(), document)
+    }
+}
+
+/// Finds the value of a metric in documents, from the first document declaring it with the given dimensions
+/// * Each dimension must be in one of the metric's dimension sets with the given value, other dimensions are ignored
+pub fn find_metric<'a>(documents: &'a [Value], name: &str, dimensions: &[(&str, &str)]) -> Option<&'a Value> {
+    documents.iter().find_map(|document| {
+        let directives = document["_aws"]["CloudWatchMetrics"].as_array()?;
+        let declared = directives.iter().any(|directive| {
+            let has_metric = directive["Metrics"]
+                .as_array()
+                .is_some_and(|metrics| metrics.iter().any(|metric| metric["Name"] == name));
+            let dimension_sets = directive["Dimensions"].as_array().map_or(&[][..], Vec::as_slice);
+            let has_dimensions = dimensions.iter().all(|(dimension, value)| {
+                document[*dimension] == *value
+                    && dimension_sets
+                        .iter()
+                        .any(|set| set.as_array().is_some_and(|set| set.iter().any(|d| d == dimension)))
+            });
+            has_metric && has_dimensions
+        });
+        declared.then(|| &document[name])
+    })
+}
+
+/// Asserts documents emitted the metric with the given value and dimensions, see [find_metric]
+/// * Numbers are compared by value, so `1` matches `1.0`
+/// * Panics listing the documents otherwise
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::testing::assert_metric_emitted;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests", "Method" => "Default").increment(1);
+/// assert_metric_emitted(&metrics.flush_to_values(), "requests", 1, &[("Method", "Default")]);
+/// ```
+#[track_caller]
+pub fn assert_metric_emitted(documents: &[Value], name: &str, value: impl Into<Value>, dimensions: &[(&str, &str)]) {
+    let expected = value.into();
+    let pretty = || documents.iter().map(Value::to_string).collect::<Vec<_>>().join("\n");
+    match find_metric(documents, name, dimensions) {
+        Some(actual) if values_equal(actual, &expected) => {}
+        Some(actual) => panic!(
+            "metric {name} with dimensions {dimensions:?} was emitted as {actual}, expected {expected}, documents:\n{}",
+            pretty()
+        ),
+        None => panic!(
+            "metric {name} with dimensions {dimensions:?} was not emitted, documents:\n{}",
+            pretty()
+        ),
+    }
+}
+
+/// JSON equality comparing numbers by value
+fn values_equal(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Number(actual), Value::Number(expected)) => actual.as_f64() == expected.as_f64(),
+        (Value::Array(actual), Value::Array(expected)) => {
+            actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, e)| values_equal(a, e))
+        }
+        _ => actual == expected,
+    }
+}
+
+impl std::io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds a simulated [lambda_runtime::LambdaInvocation] for driving a
+/// [MetricsService](crate::lambda::MetricsService) directly, with a deadline `timeout` after the clock's current time
+///
+/// *requires the `lambda` feature flag*
+#[cfg(feature = "lambda")]
+pub fn invocation(
+    clock: &dyn crate::Clock,
+    request_id: impl Into<String>,
+    timeout: std::time::Duration,
+    body: impl Into<bytes::Bytes>,
+) -> lambda_runtime::LambdaInvocation {
+    let mut context = lambda_runtime::Context::default();
+    context.request_id = request_id.into();
+    context.deadline = clock.now() + timeout.as_millis() as u64;
+    context.invoked_function_arn = "arn:aws:lambda:us-east-1:123456789012:function:test".to_owned();
+
+    lambda_runtime::LambdaInvocation {
+        parts: http::Response::new(()).into_parts().0,
+        body: body.into(),
+        context,
+    }
+}
+
+/// Builds an API Gateway HTTP API (payload format 2.0) event for queueing via [MockRuntimeApi::invoke] and
+/// handling with [MockRuntimeApi::run_http]
+///
+/// *requires the `lambda-http` feature flag*
+#[cfg(feature = "lambda-http")]
+pub fn http_event(method: &str, path: &str, body: &str) -> String {
+    serde_json::json!({
+        "version": "2.0",
+        "routeKey": "$default",
+        "rawPath": path,
+        "rawQueryString": "",
+        "headers": {"host": "localhost"},
+        "requestContext": {
+            "accountId": "123456789012",
+            "apiId": "api",
+            "domainName": "localhost",
+            "domainPrefix": "localhost",
+            "http": {
+                "method": method,
+                "path": path,
+                "protocol": "HTTP/1.1",
+                "sourceIp": "127.0.0.1",
+                "userAgent": "test"
+            },
+            "requestId": "api-request",
+            "routeKey": "$default",
+            "stage": "$default",
+            "time": "01/Jan/2024:00:00:00 +0000",
+            "timeEpoch": 0
+        },
+        "body": body,
+        "isBase64Encoded": false
+    })
+    .to_string()
+}
+
+/// [std::io::Write] wrapper injecting failures, partial writes and latency, for verifying how code flushing
+/// metrics behaves when the destination misbehaves
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::testing::{CaptureWriter, FaultyWriter};
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::counter!("requests").increment(1);
+/// assert!(metrics.flush(FaultyWriter::new(CaptureWriter::default()).fail_after(0)).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct FaultyWriter<W: std::io::Write> {
+    inner: W,
+    writes: usize,
+    fail_after: Option<usize>,
+    fail_every: Option<usize>,
+    max_write: Option<usize>,
+    latency: Option<std::time::Duration>,
+    error_kind: std::io::ErrorKind,
+}
+
+impl<W: std::io::Write> FaultyWriter<W> {
+    /// Constructs a writer passing everything through to inner until faults are configured
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            writes: 0,
+            fail_after: None,
+            fail_every: None,
+            max_write: None,
+            latency: None,
+            error_kind: std::io::ErrorKind::Other,
+        }
+    }
+
+    /// Fail every write after the first `writes` calls succeed
+    pub fn fail_after(mut self, writes: usize) -> Self {
+        self.fail_after = Some(writes);
+        self
+    }
+
+    /// Fail every nth write, e.g. to exercise retries
+    pub fn fail_every(mut self, n: usize) -> Self {
+        self.fail_every = Some(n.max(1));
+        self
+    }
+
+    /// Accept at most `max_bytes` per write, as sockets and pipes may
+    pub fn partial_writes(mut self, max_bytes: usize) -> Self {
+        self.max_write = Some(max_bytes.max(1));
+        self
+    }
+
+    /// Sleep before every write
+    pub fn latency(mut self, latency: std::time::Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Kind of the injected errors, defaults to [std::io::ErrorKind::Other]
+    pub fn error_kind(mut self, error_kind: std::io::ErrorKind) -> Self {
+        self.error_kind = error_kind;
+        self
+    }
+
+    /// Number of write calls so far, including failed ones
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for FaultyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(latency) = self.latency {
+            std::thread::sleep(latency);
+        }
+        self.writes += 1;
+
+        let fail = self.fail_after.is_some_and(|writes| self.writes > writes)
+            || self.fail_every.is_some_and(|n| self.writes % n == 0);
+        if fail {
+            return Err(std::io::Error::new(self.error_kind, "injected failure"));
+        }
+
+        let len = self.max_write.map_or(buf.len(), |max_write| buf.len().min(max_write));
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Response or error reported to a [MockRuntimeApi] for an invocation
+///
+/// *requires the `lambda` feature flag*
+#[cfg(feature = "lambda")]
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub request_id: String,
+    /// Whether the handler reported an error rather than a response
+    pub error: bool,
+    pub body: Vec<u8>,
+}
+
+#[cfg(feature = "lambda")]
+#[derive(Default)]
+struct MockState {
+    events: VecDeque<(String, Vec<u8>)>,
+    responses: Vec<MockResponse>,
+    /// Incremented by each [MockRuntimeApi::run] so requests left waiting by an earlier run are abandoned
+    generation: u64,
+    /// Whether the runtime is waiting for an invocation with none queued
+    idle: bool,
+    waker: Option<Waker>,
+}
+
+#[cfg(feature = "lambda")]
+type MockShared = (Mutex<MockState>, Condvar);
+
+/// In-process mock of the [Lambda runtime API](https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html) for
+/// end-to-end tests of the [handler::run](crate::lambda::handler::run) and `handler::run_http` stacks without
+/// deploying to AWS, e.g. of cold start metrics, request id properties and flushing after each invocation
+///
+/// * [MockRuntimeApi::start] points the runtime at the mock via `AWS_LAMBDA_RUNTIME_API` and sets the other
+///   environment variables it requires, the environment is process wide so only run one mock at a time
+/// * Metrics are written to [MockRuntimeApi::capture] instead of stdout
+///
+/// *requires the `lambda` feature flag*
+///
+/// # Example
+/// ```no_run
+/// use metrics_cloudwatch_embedded::testing::MockRuntimeApi;
+///
+/// # async fn test() {
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .lambda_cold_start_metric("ColdStart")
+///      .with_lambda_request_id("RequestId")
+///      .init()
+///      .unwrap();
+///
+/// let api = MockRuntimeApi::start().unwrap();
+/// api.invoke("request-1", r#"{"name":"world"}"#);
+/// api.run(metrics, |event: lambda_runtime::LambdaEvent<serde_json::Value>| async move {
+///     metrics::counter!("requests").increment(1);
+///     Ok::<_, lambda_runtime::Error>(event.payload)
+/// })
+/// .await
+/// .unwrap();
+///
+/// let documents = api.capture().documents();
+/// assert_eq!(documents[0]["ColdStart"], 1);
+/// assert_eq!(documents[1]["RequestId"], "request-1");
+/// # }
+/// ```
+#[cfg(feature = "lambda")]
+pub struct MockRuntimeApi {
+    address: SocketAddr,
+    state: Arc<MockShared>,
+    capture: CaptureWriter,
+}
+
+#[cfg(feature = "lambda")]
+impl MockRuntimeApi {
+    /// Starts serving on a local port and points the Lambda runtime at it
+    /// * Function name, version and memory size are only set if not already in the environment
+    pub fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(MockShared::default());
+
+        let shared = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                std::thread::spawn(move || serve(stream, &state));
+            }
+        });
+
+        std::env::set_var("AWS_LAMBDA_RUNTIME_API", address.to_string());
+        for (name, value) in [
+            ("AWS_LAMBDA_FUNCTION_NAME", "test"),
+            ("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST"),
+            ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "128"),
+            ("AWS_LAMBDA_LOG_GROUP_NAME", "/aws/lambda/test"),
+            ("AWS_LAMBDA_LOG_STREAM_NAME", "test"),
+        ] {
+            if std::env::var_os(name).is_none() {
+                std::env::set_var(name, value);
+            }
+        }
+
+        Ok(Self {
+            address,
+            state,
+            capture: CaptureWriter::default(),
+        })
+    }
+
+    /// Address the mock is listening on, as set in `AWS_LAMBDA_RUNTIME_API`
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Queues an invocation with the given request id and event payload
+    pub fn invoke(&self, request_id: impl Into<String>, event: impl Into<Vec<u8>>) {
+        let mut state = self.lock();
+        state.events.push_back((request_id.into(), event.into()));
+        state.idle = false;
+        self.state.1.notify_all();
+    }
+
+    /// Runs `handler` through the same stack as [handler::run](crate::lambda::handler::run), returning once every
+    /// queued invocation has been handled and its metrics flushed
+    /// * Returns an error if the runtime exits first, e.g. from a panic in the handler
+    pub async fn run<T, F, Request, Response>(
+        &self,
+        metrics: &'static Collector,
+        handler: T,
+    ) -> Result<(), lambda_runtime::Error>
+    where
+        T: FnMut(lambda_runtime::LambdaEvent<Request>) -> F,
+        F: Future<Output = Result<Response, lambda_runtime::Error>>,
+        Request: for<'de> serde::Deserialize<'de>,
+        Response: serde::Serialize,
+    {
+        let layer = self.layer(metrics);
+        self.drive(crate::lambda::service::run_layered(
+            layer,
+            lambda_runtime::service_fn(handler),
+        ))
+        .await
+    }
+
+    /// Runs `handler` through the same stack as [handler::run_http](crate::lambda::handler::run_http), returning
+    /// once every queued invocation has been handled and its metrics flushed
+    /// * Queue invocations with an API Gateway event, e.g. via [http_event]
+    /// * Returns an error if the runtime exits first, e.g. from a panic in the handler
+    ///
+    /// *requires the `lambda-http` feature flag*
+    #[cfg(feature = "lambda-http")]
+    pub async fn run_http<T, F, Response>(
+        &self,
+        metrics: &'static Collector,
+        handler: T,
+    ) -> Result<(), lambda_runtime::Error>
+    where
+        T: FnMut(lambda_http::Request) -> F,
+        F: Future<Output = Result<Response, lambda_runtime::Error>> + Send,
+        Response: lambda_http::IntoResponse,
+    {
+        let layer = self.layer(metrics);
+        let handler = lambda_http::Adapter::from(lambda_http::service_fn(handler));
+        self.drive(crate::lambda::service::run_layered(layer, handler)).await
+    }
+
+    /// [MetricsLayer] writing to [MockRuntimeApi::capture]
+    fn layer(&self, metrics: &'static Collector) -> MetricsLayer {
+        let capture = self.capture.clone();
+        MetricsLayer::new(metrics).with_writer(move || capture.clone())
+    }
+
+    /// Poll `runtime` until every queued invocation has been handled
+    async fn drive(
+        &self,
+        runtime: impl Future<Output = Result<(), lambda_runtime::Error>>,
+    ) -> Result<(), lambda_runtime::Error> {
+        {
+            let mut state = self.lock();
+            state.generation += 1;
+            state.idle = false;
+            self.state.1.notify_all();
+        }
+
+        // The runtime only asks for the next invocation once the previous one has been flushed
+        let idle = futures::future::poll_fn(|cx| {
+            let mut state = self.lock();
+            if state.idle {
+                return Poll::Ready(());
+            }
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        });
+
+        futures::pin_mut!(runtime, idle);
+        match futures::future::select(runtime, idle).await {
+            futures::future::Either::Left((result, _)) => {
+                result?;
+                Err("Lambda runtime exited before handling every invocation".into())
+            }
+            futures::future::Either::Right(_) => Ok(()),
+        }
+    }
+
+    /// Responses and errors reported so far, in order
+    pub fn responses(&self) -> Vec<MockResponse> {
+        self.lock().responses.clone()
+    }
+
+    /// Metrics written by [MockRuntimeApi::run]
+    pub fn capture(&self) -> &CaptureWriter {
+        &self.capture
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "lambda")]
+impl Drop for MockRuntimeApi {
+    fn drop(&mut self) {
+        // Abandon any requests waiting for an invocation
+        self.lock().generation += 1;
+        self.state.1.notify_all();
+    }
+}
+
+/// Serve runtime API requests on a connection until it's closed
+#[cfg(feature = "lambda")]
+fn serve(stream: TcpStream, state: &MockShared) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+
+    while let Ok(Some((path, body))) = read_request(&mut reader) {
+        let response = if path.ends_with("/invocation/next") {
+            let Some((request_id, event)) = next_event(state) else {
+                return;
+            };
+            let deadline = crate::SystemClock.now() + 30_000;
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Lambda-Runtime-Aws-Request-Id: {request_id}\r\n\
+                 Lambda-Runtime-Deadline-Ms: {deadline}\r\n\
+                 Lambda-Runtime-Invoked-Function-Arn: arn:aws:lambda:us-east-1:123456789012:function:test\r\n\
+                 Lambda-Runtime-Trace-Id: Root=1-00000000-000000000000000000000000;Parent=0000000000000000;Sampled=0\r\n\
+                 \r\n",
+                event.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&event);
+            response
+        } else {
+            // `/invocation/{request id}/response` or `/invocation/{request id}/error`, init errors are accepted and ignored
+            if let Some(rest) = path.strip_prefix("/2018-06-01/runtime/invocation/") {
+                let (request_id, kind) = rest.split_once('/').unwrap_or((rest, ""));
+                state
+                    .0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .responses
+                    .push(MockResponse {
+                        request_id: request_id.to_owned(),
+                        error: kind == "error",
+                        body,
+                    });
+            }
+            b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n".to_vec()
+        };
+
+        if writer.write_all(&response).and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Wait for a queued invocation, marking the mock idle while there are none
+/// * Returns None once the [MockRuntimeApi::run] the request was made under is over
+#[cfg(feature = "lambda")]
+fn next_event(state: &MockShared) -> Option<(String, Vec<u8>)> {
+    let mut guard = state.0.lock().unwrap_or_else(PoisonError::into_inner);
+    let generation = guard.generation;
+    loop {
+        if guard.generation != generation {
+            return None;
+        }
+        if let Some(event) = guard.events.pop_front() {
+            return Some(event);
+        }
+        guard.idle = true;
+        if let Some(waker) = guard.waker.take() {
+            waker.wake();
+        }
+        guard = state.1.wait(guard).unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+/// Read an HTTP/1.1 request, returning its path and body or None once the connection is closed
+#[cfg(feature = "lambda")]
+fn read_request(reader: &mut impl BufRead) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let path = line.split_whitespace().nth(1).unwrap_or_default().to_owned();
+
+    let mut content_length = 0;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = line.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16).unwrap_or(0);
+            if size == 0 {
+                // Skip any trailers up to the final empty line
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else {
+        body.resize(content_length, 0);
+        reader.read_exact(&mut body)?;
+    }
+    Ok(Some((path, body)))
+}
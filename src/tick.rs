@@ -0,0 +1,97 @@
+//! # Tick
+//!
+//! Flushing from a synchronous loop (e.g. a game server's frame loop) rather than per request
+
+use super::collector::Collector;
+use std::io;
+use std::time::Duration;
+
+/// Flushes a [Collector] every N ticks and/or once a wall-clock interval has elapsed, whichever comes first
+///
+/// Call [TickFlusher::tick] once per iteration of the loop, wall-clock time is read from the collector's
+/// [Clock](crate::Clock) so it can be driven by a [MockClock](crate::MockClock) in tests
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::TickFlusher;
+/// use std::time::Duration;
+///
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyGameServer")
+///      .init()
+///      .unwrap();
+///
+/// let mut flusher = TickFlusher::new(metrics, std::io::stdout())
+///     .every_ticks(600)
+///     .every(Duration::from_secs(10));
+///
+/// for _frame in 0..1000 {
+///     metrics::counter!("frames").increment(1);
+///     flusher.tick().unwrap();
+/// }
+/// flusher.flush().unwrap();
+/// ```
+pub struct TickFlusher<'a, W: io::Write> {
+    collector: &'a Collector,
+    writer: W,
+    every_ticks: Option<u64>,
+    every: Option<Duration>,
+    ticks: u64,
+    last_flush: u64,
+}
+
+impl<'a, W: io::Write> TickFlusher<'a, W> {
+    /// Constructs a flusher writing to `writer`, which never flushes on its own until [TickFlusher::every_ticks]
+    /// and/or [TickFlusher::every] are set
+    pub fn new(collector: &'a Collector, writer: W) -> Self {
+        Self {
+            collector,
+            writer,
+            every_ticks: None,
+            every: None,
+            ticks: 0,
+            last_flush: collector.config.clock.now(),
+        }
+    }
+
+    /// Flush every `ticks` calls to [TickFlusher::tick]
+    pub fn every_ticks(mut self, ticks: u64) -> Self {
+        self.every_ticks = Some(ticks);
+        self
+    }
+
+    /// Flush on the first [TickFlusher::tick] after `interval` has elapsed since the last flush
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.every = Some(interval);
+        self
+    }
+
+    /// Count a tick, flushing if either threshold has been reached
+    /// * Returns whether a flush happened
+    pub fn tick(&mut self) -> io::Result<bool> {
+        self.ticks += 1;
+
+        let ticks_due = self.every_ticks.is_some_and(|every_ticks| self.ticks >= every_ticks);
+        let interval_due = self.every.is_some_and(|every| {
+            self.collector.config.clock.now().saturating_sub(self.last_flush) >= every.as_millis() as u64
+        });
+
+        if ticks_due || interval_due {
+            self.flush()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Flush now and restart both thresholds, e.g. when the loop exits
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.ticks = 0;
+        self.last_flush = self.collector.config.clock.now();
+        self.collector.flush(&mut self.writer)
+    }
+
+    /// The underlying writer
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
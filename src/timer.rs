@@ -0,0 +1,53 @@
+//! # Timer
+//!
+//! Recording [std::time::Duration]s in the unit a histogram is described with
+
+use super::collector::Collector;
+use std::time::{Duration, Instant};
+
+/// Guard returned from [Collector::timer] recording the time elapsed since it was started when dropped
+/// * Recorded via [Collector::record_duration], so in the unit the histogram is described with
+///
+/// # Example
+/// ```
+/// let metrics = metrics_cloudwatch_embedded::Builder::new()
+///      .cloudwatch_namespace("MyApplication")
+///      .init()
+///      .unwrap();
+///
+/// metrics::describe_histogram!("latency", metrics::Unit::Milliseconds, "");
+///
+/// {
+///     let _timer = metrics.timer("latency", &[("Method", "Get")]);
+///     // ... handle the request
+/// }
+/// ```
+pub struct Timer<'a> {
+    collector: &'a Collector,
+    name: metrics::KeyName,
+    histogram: metrics::Histogram,
+    start: Instant,
+}
+
+impl<'a> Timer<'a> {
+    pub(crate) fn new(collector: &'a Collector, name: metrics::KeyName, histogram: metrics::Histogram) -> Self {
+        Self {
+            collector,
+            name,
+            histogram,
+            start: Instant::now(),
+        }
+    }
+
+    /// Time elapsed since the timer was started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.histogram
+            .record(self.collector.duration_value(self.name.as_str(), self.start.elapsed()));
+    }
+}
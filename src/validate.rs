@@ -0,0 +1,153 @@
+//! # Validate
+//!
+//! Checking documents against the Embedded Metric Format and CloudWatch Logs limits, CloudWatch drops documents
+//! exceeding them without reporting an error
+
+use super::collector::{MAX_DIMENSIONS, MAX_DOCUMENT_BYTES, MAX_HISTOGRAM_VALUES, MAX_METRICS};
+use super::emf::ParsedDocument;
+use serde_json::value::Value;
+use std::fmt;
+
+/// The Embedded Metric Format supports a maximum of 9 dimension sets per directive
+const MAX_DIMENSION_SETS: usize = 9;
+
+/// CloudWatch supports metric and dimension names of up to 1024 characters
+const MAX_NAME_CHARS: usize = 1024;
+
+/// A limit exceeded by a document, returned from [validate_document] and from flushes via
+/// [Builder::with_strict_validation](crate::Builder::with_strict_validation)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Not a well formed document
+    Malformed(String),
+    /// More than 100 metrics across the directives
+    TooManyMetrics { count: usize },
+    /// More than 100 values for a metric
+    TooManyValues { metric: String, count: usize },
+    /// More than 30 dimensions in a dimension set
+    TooManyDimensions { namespace: String, count: usize },
+    /// More than 9 dimension sets in a directive
+    TooManyDimensionSets { namespace: String, count: usize },
+    /// A metric or dimension name longer than 1024 characters
+    NameTooLong { name: String },
+    /// More than 256 KB
+    DocumentTooLarge { bytes: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed document: {e}"),
+            Self::TooManyMetrics { count } => {
+                write!(f, "document has {count} metrics, exceeding the limit of {MAX_METRICS}")
+            }
+            Self::TooManyValues { metric, count } => write!(
+                f,
+                "metric {metric} has {count} values, exceeding the limit of {MAX_HISTOGRAM_VALUES}"
+            ),
+            Self::TooManyDimensions { namespace, count } => write!(
+                f,
+                "dimension set in {namespace} has {count} dimensions, exceeding the limit of {MAX_DIMENSIONS}"
+            ),
+            Self::TooManyDimensionSets { namespace, count } => write!(
+                f,
+                "directive for {namespace} has {count} dimension sets, exceeding the limit of {MAX_DIMENSION_SETS}"
+            ),
+            Self::NameTooLong { name } => write!(
+                f,
+                "name {}... has {} characters, exceeding the limit of {MAX_NAME_CHARS}",
+                name.chars().take(32).collect::<String>(),
+                name.chars().count()
+            ),
+            Self::DocumentTooLarge { bytes } => write!(
+                f,
+                "document is {bytes} bytes, exceeding the CloudWatch Logs limit of {MAX_DOCUMENT_BYTES} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for std::io::Error {
+    fn from(e: ValidationError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Check a single serialized document against the limits CloudWatch enforces, reporting the first exceeded
+/// * At most 100 metrics, 100 values per metric, 30 dimensions per dimension set and 9 dimension sets per directive
+/// * Metric and dimension names of at most 1024 characters
+/// * At most 256 KB, trailing whitespace including the newline isn't counted
+///
+/// # Example
+/// ```
+/// use metrics_cloudwatch_embedded::{validate_document, ValidationError};
+///
+/// let document = format!(
+///     r#"{{"_aws":{{"Timestamp":0,"CloudWatchMetrics":[{{"Namespace":"MyApplication","Dimensions":[[]],
+///         "Metrics":[{{"Name":"latency"}}]}}]}},"latency":{:?}}}"#,
+///     vec![1.0; 101]
+/// );
+/// assert_eq!(
+///     validate_document(document.as_bytes()),
+///     Err(ValidationError::TooManyValues { metric: "latency".to_owned(), count: 101 })
+/// );
+/// ```
+pub fn validate_document(document: &[u8]) -> Result<(), ValidationError> {
+    let trailing = document.iter().rev().take_while(|b| b.is_ascii_whitespace()).count();
+    let document = &document[..document.len() - trailing];
+    if document.len() > MAX_DOCUMENT_BYTES {
+        return Err(ValidationError::DocumentTooLarge { bytes: document.len() });
+    }
+    let parsed: ParsedDocument =
+        serde_json::from_slice(document).map_err(|e| ValidationError::Malformed(e.to_string()))?;
+
+    let directives = &parsed.aws.cloudwatch_metrics;
+    let metrics: usize = directives.iter().map(|directive| directive.metrics.len()).sum();
+    if metrics > MAX_METRICS {
+        return Err(ValidationError::TooManyMetrics { count: metrics });
+    }
+
+    let name_too_long = |name: &str| {
+        (name.chars().count() > MAX_NAME_CHARS).then(|| ValidationError::NameTooLong { name: name.to_owned() })
+    };
+    for directive in directives {
+        if directive.dimensions.len() > MAX_DIMENSION_SETS {
+            return Err(ValidationError::TooManyDimensionSets {
+                namespace: directive.namespace.clone(),
+                count: directive.dimensions.len(),
+            });
+        }
+        for dimension_set in &directive.dimensions {
+            if dimension_set.len() > MAX_DIMENSIONS {
+                return Err(ValidationError::TooManyDimensions {
+                    namespace: directive.namespace.clone(),
+                    count: dimension_set.len(),
+                });
+            }
+            if let Some(e) = dimension_set.iter().find_map(|name| name_too_long(name)) {
+                return Err(e);
+            }
+        }
+        if let Some(e) = directive.metrics.iter().find_map(|metric| name_too_long(&metric.name)) {
+            return Err(e);
+        }
+    }
+
+    for (metric, value) in &parsed.values {
+        // Histograms are an array of values or a distribution of values and counts
+        let values = match value {
+            Value::Array(values) => values.len(),
+            Value::Object(object) => object.get("Values").and_then(Value::as_array).map_or(0, Vec::len),
+            _ => 0,
+        };
+        if values > MAX_HISTOGRAM_VALUES {
+            return Err(ValidationError::TooManyValues {
+                metric: metric.clone(),
+                count: values,
+            });
+        }
+    }
+    Ok(())
+}